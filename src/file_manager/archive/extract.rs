@@ -0,0 +1,277 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+use tracing::info;
+use super::reader::{normalize_member_name, open_tar_stream, open_zip, read_members, zip_time, ArchiveKind};
+use crate::file_manager::file_tree::FileEntry;
+use crate::file_manager::file_utils::ConflictPolicy;
+use crate::file_manager::file_types::{check_type, read_head};
+use crate::file_manager::filename::clean_name;
+use crate::file_manager::jobs::{Job, JobStatus};
+use crate::file_manager::quota::{allowance_blocking, top_folder, Allowance};
+use crate::file_manager::transfer::{commit_part, ensure_dir, part_path, place_file, Placement};
+use crate::utils::config::Config;
+
+const COPY_BUFFER_SIZE: usize = 128 * 1024;
+
+/// How an archive entry is treated on extraction.
+enum EntryKind {
+    File,
+    Dir,
+    /// Symlinks, hard links and other special entries, which are never recreated.
+    Link,
+}
+
+/// Unpacks the archive at `archive` into the shared folder `target`, reporting
+/// progress through `job`. Blocks the calling thread; run it with `spawn_blocking`.
+///
+/// Every destination is resolved through `safe_path`, so entries can't leave the
+/// share (zip-slip) or be written through symlinks. Archives declaring more entries
+/// or bytes than `EXTRACT_MAX_ENTRIES` / `EXTRACT_MAX_BYTES` are refused, and the
+/// byte limit is enforced again while writing in case the headers lie.
+pub fn unpack_archive(
+    archive: &Path,
+    archive_rel: &str,
+    kind: ArchiveKind,
+    target: &str,
+    policy: ConflictPolicy,
+    tree: &Mutex<Option<FileEntry>>,
+    job: &Job,
+) {
+    let config = Config::from_env();
+    let allowance = match allowance_blocking(None, top_folder(&format!("{target}/_")), true, tree) {
+        Ok(allowance) => allowance,
+        Err(message) => return job.finish(JobStatus::Failed, Some(message)),
+    };
+    let mut extractor = Extractor {
+        archive_rel,
+        target,
+        policy,
+        tree,
+        job,
+        allowance,
+        max_bytes: config.extract_max_bytes(),
+        written: 0,
+        files: 0,
+        buf: vec![0u8; COPY_BUFFER_SIZE],
+    };
+
+    let result = extractor.check_limits(archive, kind, config.extract_max_entries()).and_then(|()| {
+        if kind == ArchiveKind::Zip {
+            extractor.unpack_zip(archive)
+        } else {
+            extractor.unpack_tar(archive, kind)
+        }
+    });
+
+    let files = extractor.files;
+    match result {
+        _ if job.is_cancelled() => job.finish(JobStatus::Cancelled, Some(format!("Cancelled after {files} file(s)"))),
+        Ok(()) => {
+            info!("📦 Extracted {files} file(s) from {archive_rel} into '{target}'");
+            job.finish(JobStatus::Completed, Some(format!("Extracted {files} file(s)")));
+        }
+        Err(e) => job.finish(JobStatus::Failed, Some(e.to_string())),
+    }
+}
+
+struct Extractor<'a> {
+    archive_rel: &'a str,
+    target: &'a str,
+    policy: ConflictPolicy,
+    tree: &'a Mutex<Option<FileEntry>>,
+    job: &'a Job,
+    allowance: Allowance,
+    max_bytes: u64,
+    written: u64,
+    files: u64,
+    buf: Vec<u8>,
+}
+
+impl Extractor<'_> {
+    /// Refuses archives whose headers already exceed the limits and sets the job totals.
+    fn check_limits(&self, archive: &Path, kind: ArchiveKind, max_entries: u64) -> io::Result<()> {
+        let members = read_members(archive, kind)?;
+        let entries = members.len() as u64;
+        if entries > max_entries {
+            return Err(io::Error::other(format!(
+                "Archive has {entries} entries, more than the limit of {max_entries}"
+            )));
+        }
+        let bytes: u64 = members.iter().filter(|m| !m.is_dir).map(|m| m.size).sum();
+        if bytes > self.max_bytes {
+            return Err(io::Error::other(format!(
+                "Archive unpacks to {bytes} bytes, more than the limit of {}",
+                self.max_bytes
+            )));
+        }
+        if bytes > self.allowance.bytes {
+            return Err(io::Error::new(io::ErrorKind::QuotaExceeded, self.allowance.exceeded()));
+        }
+        self.job.set_totals(bytes, entries);
+        Ok(())
+    }
+
+    fn unpack_zip(&mut self, archive: &Path) -> io::Result<()> {
+        let mut zip = open_zip(archive)?;
+        for index in 0..zip.len() {
+            let (name, kind, modified) = {
+                let file = zip.by_index_raw(index).map_err(io::Error::other)?;
+                let name = file.name().map_err(io::Error::other)?.into_owned();
+                if file.encrypted() {
+                    self.job.skip(format!("{name}: encrypted entries are not supported"));
+                    continue;
+                }
+                let kind = if file.is_symlink() {
+                    EntryKind::Link
+                } else if file.is_dir() {
+                    EntryKind::Dir
+                } else {
+                    EntryKind::File
+                };
+                (name, kind, file.last_modified().and_then(zip_time).map(SystemTime::from))
+            };
+            let mut file = zip.by_index(index).map_err(io::Error::other)?;
+            self.entry(&name, kind, modified, &mut file)?;
+        }
+        Ok(())
+    }
+
+    fn unpack_tar(&mut self, archive: &Path, kind: ArchiveKind) -> io::Result<()> {
+        let mut archive = tar::Archive::new(open_tar_stream(archive, kind)?);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let entry_type = entry.header().entry_type();
+            if entry_type.is_pax_global_extensions() {
+                continue;
+            }
+            let kind = if entry_type.is_dir() {
+                EntryKind::Dir
+            } else if entry_type.is_file() || entry_type.is_contiguous() || entry_type.is_gnu_sparse() {
+                EntryKind::File
+            } else {
+                EntryKind::Link
+            };
+            let name = entry.path()?.to_string_lossy().into_owned();
+            let modified = entry
+                .header()
+                .mtime()
+                .ok()
+                .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+            self.entry(&name, kind, modified, &mut entry)?;
+        }
+        Ok(())
+    }
+
+    /// Extracts one entry. Problems with a single entry are recorded as skipped;
+    /// errors are fatal for the whole job (I/O failures, limits, cancellation).
+    fn entry(&mut self, raw_name: &str, kind: EntryKind, modified: Option<SystemTime>, reader: &mut dyn Read) -> io::Result<()> {
+        if self.job.is_cancelled() {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "Cancelled"));
+        }
+        let Some(name) = normalize_member_name(raw_name) else {
+            self.job.skip(format!("{raw_name}: path leaves the archive root"));
+            return Ok(());
+        };
+        // Archives made elsewhere may carry names the share doesn't accept as they are
+        let name = match name.split('/').map(clean_name).collect::<Result<Vec<_>, _>>() {
+            Ok(parts) => parts.join("/"),
+            Err(e) => {
+                self.job.skip(format!("{name}: {e}"));
+                return Ok(());
+            }
+        };
+
+        let skipped = match &kind {
+            EntryKind::Link => Some("links and special files are not extracted".to_string()),
+            EntryKind::Dir => ensure_dir(&self.rel(&name))?,
+            EntryKind::File => self.write_file(&name, modified, reader)?,
+        };
+        if let Some(reason) = skipped {
+            self.job.skip(format!("{name}: {reason}"));
+        }
+        // Links aren't part of the listed totals
+        if !matches!(kind, EntryKind::Link) {
+            self.job.item_done();
+        }
+        Ok(())
+    }
+
+    fn rel(&self, name: &str) -> String {
+        if self.target.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{name}", self.target)
+        }
+    }
+
+    fn write_file(&mut self, name: &str, modified: Option<SystemTime>, reader: &mut dyn Read) -> io::Result<Option<String>> {
+        if let Some((parent, _)) = name.rsplit_once('/') {
+            if let Some(reason) = ensure_dir(&self.rel(parent))? {
+                return Ok(Some(reason));
+            }
+        }
+        if let Err(violation) = check_type(&self.rel(name), &[]) {
+            return Ok(Some(violation.to_string()));
+        }
+        let requested = self.rel(name);
+        let placement = place_file(&requested, self.policy, self.archive_rel)?;
+        let (rel, dest) = match &placement {
+            Placement::Write { rel, path, .. } => (rel.clone(), path.clone()),
+            Placement::Skip(reason) => return Ok(Some(reason.clone())),
+        };
+
+        // Stream into a hidden part file next to the destination, then move it into place
+        let part = part_path(&dest, self.job.id());
+        if let Err(e) = self.copy_into(&part, modified, reader) {
+            let _ = fs::remove_file(&part);
+            return Err(e);
+        }
+        // The content may not be what the name says
+        if let Err(violation) = check_type(&rel, &read_head(&part)?) {
+            let _ = fs::remove_file(&part);
+            self.allowance.release();
+            return Ok(Some(violation.to_string()));
+        }
+        match commit_part(&part, &requested, placement, self.policy, self.archive_rel, self.tree)? {
+            Placement::Write { .. } => {
+                self.allowance.commit();
+                self.files += 1;
+                Ok(None)
+            }
+            Placement::Skip(reason) => {
+                self.allowance.release();
+                Ok(Some(reason))
+            }
+        }
+    }
+
+    fn copy_into(&mut self, part: &Path, modified: Option<SystemTime>, reader: &mut dyn Read) -> io::Result<()> {
+        let mut out = File::create(part)?;
+        loop {
+            if self.job.is_cancelled() {
+                return Err(io::Error::new(io::ErrorKind::Interrupted, "Cancelled"));
+            }
+            let n = reader.read(&mut self.buf)?;
+            if n == 0 {
+                break;
+            }
+            self.written += n as u64;
+            if self.written > self.max_bytes {
+                return Err(io::Error::other(format!(
+                    "Archive unpacks to more than the limit of {} bytes",
+                    self.max_bytes
+                )));
+            }
+            self.allowance.take(n as u64)?;
+            out.write_all(&self.buf[..n])?;
+            self.job.add_bytes(n as u64);
+        }
+        if let Some(modified) = modified {
+            let _ = out.set_modified(modified);
+        }
+        Ok(())
+    }
+}
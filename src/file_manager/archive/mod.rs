@@ -0,0 +1,261 @@
+pub mod zip_writer;
+pub mod zip_layout;
+pub mod tar_writer;
+pub mod reader;
+pub mod extract;
+
+pub use zip_writer::{ZipMethod, ZipWriter};
+pub use zip_layout::{CrcCache, ZipLayout};
+pub use tar_writer::TarWriter;
+pub use extract::unpack_archive;
+pub use reader::{build_listing, list_members, stream_member, ArchiveKind, MemberData};
+
+use async_compression::tokio::write::{GzipEncoder, ZstdEncoder};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    sync::Mutex,
+};
+use tracing::warn;
+use super::file_tree::{FileEntry, FileType};
+use super::file_utils::{detect_file_type, numbered_name, safe_path};
+
+/// Name of the trailing entry that lists files which could not be archived.
+pub const SKIPPED_MANIFEST_NAME: &str = "SKIPPED_FILES.txt";
+
+/// Container formats offered for downloads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum ArchiveFormat {
+    #[default]
+    #[serde(rename = "zip")]
+    Zip,
+    #[serde(rename = "tar")]
+    Tar,
+    #[serde(rename = "tar.gz")]
+    TarGz,
+    #[serde(rename = "tar.zst")]
+    TarZst,
+}
+
+impl ArchiveFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::Tar => "tar",
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::TarZst => "tar.zst",
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "application/zip",
+            ArchiveFormat::Tar => "application/x-tar",
+            ArchiveFormat::TarGz => "application/gzip",
+            ArchiveFormat::TarZst => "application/zstd",
+        }
+    }
+}
+
+/// A file or folder queued for an archive, resolved from the in-memory tree.
+#[derive(Debug, Clone)]
+pub struct ArchiveItem {
+    /// Path inside the archive, `/`-separated; directories end with `/`.
+    pub name: String,
+    /// Path relative to the shared directory.
+    pub rel_path: String,
+    pub is_dir: bool,
+    /// The tree entry's lock, held while the file is read.
+    pub lock: Arc<Mutex<()>>,
+}
+
+/// Flattens a tree entry into archive items placed under `prefix`.
+/// Children are sorted by name so the same tree always yields the same order.
+pub fn collect_items(entry: &FileEntry, prefix: &str, items: &mut Vec<ArchiveItem>) {
+    collect_items_as(entry, &entry.name, prefix, items);
+}
+
+/// Like [`collect_items`], but stores the entry itself under `name`.
+pub fn collect_items_as(entry: &FileEntry, name: &str, prefix: &str, items: &mut Vec<ArchiveItem>) {
+    let name = format!("{prefix}{name}");
+    if entry.is_dir {
+        items.push(ArchiveItem {
+            name: format!("{name}/"),
+            rel_path: entry.path.clone(),
+            is_dir: true,
+            lock: entry.lock.clone(),
+        });
+        let mut children: Vec<&FileEntry> = entry.children.iter().flatten().collect();
+        children.sort_by(|a, b| a.name.cmp(&b.name));
+        for child in children {
+            collect_items(child, &format!("{name}/"), items);
+        }
+    } else {
+        items.push(ArchiveItem {
+            name,
+            rel_path: entry.path.clone(),
+            is_dir: false,
+            lock: entry.lock.clone(),
+        });
+    }
+}
+
+/// Returns a top-level archive name for `name` that is not in `used` yet,
+/// numbering repeats as `name (1).ext`, `name (2).ext`, ...
+pub fn unique_entry_name(name: &str, used: &mut HashSet<String>) -> String {
+    let mut candidate = name.to_string();
+    let mut n = 1;
+    while !used.insert(candidate.clone()) {
+        candidate = numbered_name(name, n);
+        n += 1;
+    }
+    candidate
+}
+
+/// Picks the compression method for a file: media and already-compressed formats are stored.
+pub fn method_for(path: &Path) -> ZipMethod {
+    if detect_file_type(path, false) != FileType::Other {
+        return ZipMethod::Stored;
+    }
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    match ext.as_str() {
+        "zip" | "gz" | "tgz" | "bz2" | "xz" | "zst" | "7z" | "rar" | "webm" | "webp" | "ogg"
+        | "m4a" | "m4v" | "heic" | "avif" | "docx" | "xlsx" | "pptx" | "odt" | "ods" | "epub" => {
+            ZipMethod::Stored
+        }
+        _ => ZipMethod::Deflated,
+    }
+}
+
+/// The per-format writer behind [`write_archive`].
+enum ArchiveWriter<W> {
+    Zip(ZipWriter<W>),
+    Tar(TarWriter<W>),
+}
+
+impl<W: AsyncWrite + Unpin> ArchiveWriter<W> {
+    async fn add_directory(&mut self, name: &str, modified: SystemTime) -> io::Result<()> {
+        match self {
+            ArchiveWriter::Zip(zip) => zip.add_directory(name, modified).await,
+            ArchiveWriter::Tar(tar) => tar.add_directory(name, modified).await,
+        }
+    }
+
+    async fn add_file<R: AsyncRead + Unpin>(
+        &mut self,
+        name: &str,
+        source: &Path,
+        modified: SystemTime,
+        size: u64,
+        reader: R,
+    ) -> io::Result<u64> {
+        match self {
+            ArchiveWriter::Zip(zip) => zip.add_file(name, modified, method_for(source), size, reader).await,
+            ArchiveWriter::Tar(tar) => tar.add_file(name, modified, size, reader).await,
+        }
+    }
+
+    async fn add_bytes(&mut self, name: &str, modified: SystemTime, data: &[u8]) -> io::Result<()> {
+        match self {
+            ArchiveWriter::Zip(zip) => zip.add_bytes(name, modified, data).await,
+            ArchiveWriter::Tar(tar) => tar.add_bytes(name, modified, data).await,
+        }
+    }
+
+    async fn finish(self) -> io::Result<W> {
+        match self {
+            ArchiveWriter::Zip(zip) => zip.finish().await,
+            ArchiveWriter::Tar(tar) => tar.finish().await,
+        }
+    }
+}
+
+/// Writes `items` to `out` in the requested format.
+///
+/// Each file is read under its entry lock and re-resolved through `safe_path`, so
+/// symlinks leading out of the share are never followed. Files that cannot be opened
+/// are skipped and, together with `skipped`, listed in a trailing
+/// [`SKIPPED_MANIFEST_NAME`] entry.
+pub async fn write_archive<W: AsyncWrite + Unpin>(
+    items: Vec<ArchiveItem>,
+    skipped: Vec<String>,
+    format: ArchiveFormat,
+    out: W,
+) -> io::Result<()> {
+    match format {
+        ArchiveFormat::Zip => {
+            let mut out = write_items(ArchiveWriter::Zip(ZipWriter::new(out)), items, skipped).await?;
+            out.shutdown().await
+        }
+        ArchiveFormat::Tar => {
+            let mut out = write_items(ArchiveWriter::Tar(TarWriter::new(out)), items, skipped).await?;
+            out.shutdown().await
+        }
+        ArchiveFormat::TarGz => {
+            let encoder = GzipEncoder::new(out);
+            let mut out = write_items(ArchiveWriter::Tar(TarWriter::new(encoder)), items, skipped).await?;
+            out.shutdown().await
+        }
+        ArchiveFormat::TarZst => {
+            let encoder = ZstdEncoder::new(out);
+            let mut out = write_items(ArchiveWriter::Tar(TarWriter::new(encoder)), items, skipped).await?;
+            out.shutdown().await
+        }
+    }
+}
+
+async fn write_items<W: AsyncWrite + Unpin>(
+    mut writer: ArchiveWriter<W>,
+    items: Vec<ArchiveItem>,
+    mut skipped: Vec<String>,
+) -> io::Result<W> {
+    for item in items {
+        let path = match safe_path(&item.rel_path) {
+            Ok(p) => p,
+            Err(_) => {
+                skipped.push(format!("{}: outside the shared folder", item.rel_path));
+                continue;
+            }
+        };
+
+        if item.is_dir {
+            let modified = tokio::fs::metadata(&path)
+                .await
+                .and_then(|m| m.modified())
+                .unwrap_or_else(|_| SystemTime::now());
+            writer.add_directory(&item.name, modified).await?;
+            continue;
+        }
+
+        let _file_guard = item.lock.lock().await;
+        let (file, meta) = match File::open(&path).await {
+            Ok(file) => match file.metadata().await {
+                Ok(meta) => (file, meta),
+                Err(e) => {
+                    skipped.push(format!("{}: {e}", item.rel_path));
+                    continue;
+                }
+            },
+            Err(e) => {
+                skipped.push(format!("{}: {e}", item.rel_path));
+                continue;
+            }
+        };
+        let modified = meta.modified().unwrap_or_else(|_| SystemTime::now());
+        writer.add_file(&item.name, &path, modified, meta.len(), file).await?;
+    }
+
+    if !skipped.is_empty() {
+        warn!("📦 Archive skipped {} file(s)", skipped.len());
+        let manifest = format!("The following files could not be archived:\n{}\n", skipped.join("\n"));
+        writer.add_bytes(SKIPPED_MANIFEST_NAME, SystemTime::now(), manifest.as_bytes()).await?;
+    }
+
+    writer.finish().await
+}
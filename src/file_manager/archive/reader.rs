@@ -0,0 +1,403 @@
+use bytes::Bytes;
+use chrono::{DateTime, NaiveDate, Utc};
+use flate2::read::GzDecoder;
+use futures_core::Stream;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+use tokio::sync::{mpsc, OwnedMutexGuard};
+use zip::{CompressionMethod, ZipArchive};
+use crate::file_manager::file_tree::FileType;
+use crate::file_manager::file_utils::{detect_file_type, file_version_tag, is_browser_supported};
+
+/// Chunk size used when decoding a compressed member for a response.
+const MEMBER_CHUNK_SIZE: usize = 128 * 1024;
+/// Archives whose member listing is kept; the least recently used is dropped first.
+const LISTING_CACHE_CAPACITY: usize = 32;
+
+/// Archive formats whose members can be listed and streamed in place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+impl ArchiveKind {
+    /// Recognises an archive by its file name (`.zip`, `.tar`, `.tar.gz`/`.tgz`, `.tar.zst`/`.tzst`).
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+        if name.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveKind::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Some(ArchiveKind::TarZst)
+        } else {
+            None
+        }
+    }
+
+    /// Strips the archive extension from `name`: `photos.tar.gz` becomes `photos`.
+    pub fn stem(self, name: &str) -> &str {
+        let suffixes: &[&str] = match self {
+            ArchiveKind::Zip => &[".zip"],
+            ArchiveKind::Tar => &[".tar"],
+            ArchiveKind::TarGz => &[".tar.gz", ".tgz"],
+            ArchiveKind::TarZst => &[".tar.zst", ".tzst"],
+        };
+        suffixes
+            .iter()
+            .find(|suffix| name.to_lowercase().ends_with(*suffix))
+            .map_or(name, |suffix| &name[..name.len() - suffix.len()])
+    }
+}
+
+/// Where a member's bytes can be read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemberData {
+    /// Stored uncompressed at `offset` in the archive file, so byte ranges map directly onto it.
+    Slice { offset: u64 },
+    /// Compressed, or inside a compressed stream: it has to be decoded from the start.
+    Encoded,
+}
+
+/// A file or folder found inside an archive.
+#[derive(Debug, Clone)]
+pub struct ArchiveMember {
+    /// Normalized `/`-separated path inside the archive, without a trailing slash.
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: Option<DateTime<Utc>>,
+    pub data: MemberData,
+    /// Position in the archive, used to find the member again when streaming it.
+    index: usize,
+}
+
+/// A virtual entry inside an archive, serialized like the tree's `FileEntry`.
+#[derive(Debug, Serialize)]
+pub struct ArchiveEntry {
+    pub name: String,
+    pub path: String,
+    pub is_dir: bool,
+    pub file_type: FileType,
+    pub size: Option<u64>,
+    pub modified: Option<String>,
+    pub children: Option<Vec<ArchiveEntry>>,
+    pub is_browser_supported: bool,
+    /// Always true; lets clients hide actions that only apply to real files.
+    pub in_archive: bool,
+}
+
+/// Reads the member list of an archive on a blocking thread.
+/// Compressed tarballs are decoded once from start to end; the listing is then
+/// cached until the archive's `file_version_tag` changes.
+pub async fn list_members(path: PathBuf, kind: ArchiveKind) -> io::Result<Vec<ArchiveMember>> {
+    tokio::task::spawn_blocking(move || {
+        let version = file_version_tag(&std::fs::metadata(&path)?);
+        if let Some(members) = cached_listing(&path, &version) {
+            return Ok(members);
+        }
+        let members = read_members(&path, kind)?;
+        // Only keep a listing of an archive that didn't change while it was read
+        if std::fs::metadata(&path).is_ok_and(|meta| file_version_tag(&meta) == version) {
+            cache_listing(&path, version, &members);
+        }
+        Ok(members)
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+/// Listings of recently read archives, keyed by path, with the version they were read at.
+fn listings() -> &'static Mutex<HashMap<PathBuf, CachedListing>> {
+    static LISTINGS: OnceLock<Mutex<HashMap<PathBuf, CachedListing>>> = OnceLock::new();
+    LISTINGS.get_or_init(Default::default)
+}
+
+struct CachedListing {
+    version: String,
+    members: Vec<ArchiveMember>,
+    last_used: Instant,
+}
+
+fn cached_listing(path: &Path, version: &str) -> Option<Vec<ArchiveMember>> {
+    let mut listings = listings().lock().unwrap();
+    let cached = listings.get_mut(path).filter(|cached| cached.version == version)?;
+    cached.last_used = Instant::now();
+    Some(cached.members.clone())
+}
+
+fn cache_listing(path: &Path, version: String, members: &[ArchiveMember]) {
+    let mut listings = listings().lock().unwrap();
+    if listings.len() >= LISTING_CACHE_CAPACITY && !listings.contains_key(path) {
+        let oldest = listings.iter().min_by_key(|(_, cached)| cached.last_used).map(|(path, _)| path.clone());
+        if let Some(oldest) = oldest {
+            listings.remove(&oldest);
+        }
+    }
+    let cached = CachedListing { version, members: members.to_vec(), last_used: Instant::now() };
+    listings.insert(path.to_path_buf(), cached);
+}
+
+/// Blocking version of [`list_members`].
+pub(super) fn read_members(path: &Path, kind: ArchiveKind) -> io::Result<Vec<ArchiveMember>> {
+    match kind {
+        ArchiveKind::Zip => read_zip_members(path),
+        ArchiveKind::Tar => read_tar_members(open_tar_stream(path, kind)?, true),
+        _ => read_tar_members(open_tar_stream(path, kind)?, false),
+    }
+}
+
+/// Decodes `member` on a blocking thread and yields its bytes.
+/// `guard` is the archive's tree lock; it is held until the member has been sent.
+pub fn stream_member(
+    path: PathBuf,
+    kind: ArchiveKind,
+    member: ArchiveMember,
+    guard: OwnedMutexGuard<()>,
+) -> impl Stream<Item = io::Result<Bytes>> {
+    let (tx, rx) = mpsc::channel(4);
+    tokio::task::spawn_blocking(move || {
+        let _guard = guard;
+        if let Err(e) = copy_member(&path, kind, &member, &tx) {
+            let _ = tx.blocking_send(Err(e));
+        }
+    });
+    futures_util::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|chunk| (chunk, rx)) })
+}
+
+/// Arranges members into a folder tree rooted at the archive itself.
+/// Folders that only appear as path prefixes are created implicitly; when a
+/// name occurs twice the later member wins, as it would on extraction.
+pub fn build_listing(archive_path: &str, archive_name: &str, members: &[ArchiveMember]) -> ArchiveEntry {
+    let mut root = ListingNode::default();
+    for member in members {
+        let mut node = &mut root;
+        for part in member.name.split('/') {
+            node = node.children.entry(part.to_string()).or_default();
+        }
+        node.member = Some(member);
+    }
+
+    ArchiveEntry {
+        name: archive_name.to_string(),
+        path: archive_path.to_string(),
+        is_dir: true,
+        file_type: FileType::Archive,
+        size: None,
+        modified: None,
+        children: Some(root.into_children(archive_path)),
+        is_browser_supported: false,
+        in_archive: true,
+    }
+}
+
+#[derive(Default)]
+struct ListingNode<'a> {
+    member: Option<&'a ArchiveMember>,
+    children: BTreeMap<String, ListingNode<'a>>,
+}
+
+impl ListingNode<'_> {
+    fn into_children(self, parent: &str) -> Vec<ArchiveEntry> {
+        self.children
+            .into_iter()
+            .map(|(name, node)| {
+                let path = format!("{parent}/{name}");
+                let is_dir = !node.children.is_empty() || node.member.is_none_or(|m| m.is_dir);
+                let modified = node.member.and_then(|m| m.modified).map(|t| t.to_rfc3339());
+                let size = node.member.filter(|_| !is_dir).map(|m| m.size);
+                ArchiveEntry {
+                    file_type: detect_file_type(Path::new(&name), is_dir),
+                    is_browser_supported: !is_dir && is_browser_supported(Path::new(&name)),
+                    children: is_dir.then(|| node.into_children(&path)),
+                    name,
+                    path,
+                    is_dir,
+                    size,
+                    modified,
+                    in_archive: true,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Turns a raw member name into a clean relative path.
+/// Names that climb out of the archive root (`..`) are dropped.
+pub(super) fn normalize_member_name(raw: &str) -> Option<String> {
+    let mut parts = Vec::new();
+    for part in raw.split(['/', '\\']) {
+        match part {
+            "" | "." => continue,
+            ".." => return None,
+            part => parts.push(part),
+        }
+    }
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+/// Converts a ZIP (MS-DOS) timestamp, taken as UTC.
+pub(super) fn zip_time(t: zip::DateTime) -> Option<DateTime<Utc>> {
+    NaiveDate::from_ymd_opt(t.year().into(), t.month().into(), t.day().into())?
+        .and_hms_opt(t.hour().into(), t.minute().into(), t.second().into())
+        .map(|t| t.and_utc())
+}
+
+pub(super) fn open_zip(path: &Path) -> io::Result<ZipArchive<BufReader<File>>> {
+    ZipArchive::new(BufReader::new(File::open(path)?)).map_err(io::Error::other)
+}
+
+pub(super) fn open_tar_stream(path: &Path, kind: ArchiveKind) -> io::Result<Box<dyn Read + Send>> {
+    let file = BufReader::new(File::open(path)?);
+    Ok(match kind {
+        ArchiveKind::TarGz => Box::new(GzDecoder::new(file)),
+        ArchiveKind::TarZst => Box::new(zstd::Decoder::with_buffer(file)?),
+        _ => Box::new(file),
+    })
+}
+
+fn read_zip_members(path: &Path) -> io::Result<Vec<ArchiveMember>> {
+    let mut zip = open_zip(path)?;
+    let mut members = Vec::with_capacity(zip.len());
+    for index in 0..zip.len() {
+        let file = zip.by_index_raw(index).map_err(io::Error::other)?;
+        // Links can't be followed safely and encrypted members can't be decoded
+        if file.is_symlink() || file.encrypted() {
+            continue;
+        }
+        let Some(name) = file.name().ok().and_then(|name| normalize_member_name(&name)) else {
+            continue;
+        };
+        let data = match (file.compression(), file.data_start()) {
+            (CompressionMethod::Stored, Some(offset)) => MemberData::Slice { offset },
+            _ => MemberData::Encoded,
+        };
+        members.push(ArchiveMember {
+            name,
+            is_dir: file.is_dir(),
+            size: file.size(),
+            modified: file.last_modified().and_then(zip_time),
+            data,
+            index,
+        });
+    }
+    Ok(members)
+}
+
+/// Lists regular files and folders of a tar stream. Offsets are only meaningful
+/// when the stream is the archive file itself (`seekable`).
+fn read_tar_members(reader: impl Read, seekable: bool) -> io::Result<Vec<ArchiveMember>> {
+    let mut archive = tar::Archive::new(reader);
+    let mut members = Vec::new();
+    for (index, entry) in archive.entries()?.enumerate() {
+        let entry = entry?;
+        let entry_type = entry.header().entry_type();
+        let is_dir = entry_type.is_dir();
+        let is_file = entry_type.is_file() || entry_type.is_contiguous() || entry_type.is_gnu_sparse();
+        if !is_dir && !is_file {
+            continue;
+        }
+        let Some(name) = normalize_member_name(&entry.path()?.to_string_lossy()) else {
+            continue;
+        };
+        let data = if seekable && !entry_type.is_gnu_sparse() {
+            MemberData::Slice { offset: entry.raw_file_position() }
+        } else {
+            MemberData::Encoded
+        };
+        members.push(ArchiveMember {
+            name,
+            is_dir,
+            size: if is_dir { 0 } else { entry.size() },
+            modified: entry
+                .header()
+                .mtime()
+                .ok()
+                .and_then(|secs| DateTime::from_timestamp(secs as i64, 0)),
+            data,
+            index,
+        });
+    }
+    Ok(members)
+}
+
+fn copy_member(
+    path: &Path,
+    kind: ArchiveKind,
+    member: &ArchiveMember,
+    tx: &mpsc::Sender<io::Result<Bytes>>,
+) -> io::Result<()> {
+    if kind == ArchiveKind::Zip {
+        let mut zip = open_zip(path)?;
+        let file = zip.by_index(member.index).map_err(io::Error::other)?;
+        return send_chunks(file, tx);
+    }
+
+    let mut archive = tar::Archive::new(open_tar_stream(path, kind)?);
+    for (index, entry) in archive.entries()?.enumerate() {
+        let entry = entry?;
+        if index == member.index {
+            return send_chunks(entry, tx);
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::NotFound, "member is no longer in the archive"))
+}
+
+fn send_chunks(mut reader: impl Read, tx: &mpsc::Sender<io::Result<Bytes>>) -> io::Result<()> {
+    let mut buf = vec![0u8; MEMBER_CHUNK_SIZE];
+    loop {
+        let n = reader.read(&mut buf)?;
+        // Stop quietly once the client has gone away
+        if n == 0 || tx.blocking_send(Ok(Bytes::copy_from_slice(&buf[..n]))).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_manager::test_support::share_dir;
+
+    fn write_tar_gz(path: &Path, names: &[&str]) {
+        let encoder = flate2::write::GzEncoder::new(File::create(path).unwrap(), flate2::Compression::fast());
+        let mut builder = tar::Builder::new(encoder);
+        for name in names {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(2);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, &b"hi"[..]).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    #[tokio::test]
+    async fn listings_are_cached_until_the_archive_changes() {
+        let dir = share_dir().join("listing-cache");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a.tar.gz");
+        write_tar_gz(&path, &["one.txt"]);
+
+        let first = list_members(path.clone(), ArchiveKind::TarGz).await.unwrap();
+        assert_eq!(first.len(), 1);
+        let version = file_version_tag(&std::fs::metadata(&path).unwrap());
+        assert!(cached_listing(&path, &version).is_some());
+
+        // Rewriting the archive changes its version tag, so it is read again
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        write_tar_gz(&path, &["one.txt", "two.txt"]);
+        let second = list_members(path.clone(), ArchiveKind::TarGz).await.unwrap();
+        assert_eq!(second.iter().map(|m| m.name.as_str()).collect::<Vec<_>>(), ["one.txt", "two.txt"]);
+    }
+}
@@ -0,0 +1,224 @@
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const BLOCK_SIZE: usize = 512;
+/// Largest size representable in the 11 octal digits of a ustar header.
+const USTAR_MAX_SIZE: u64 = 0o77_777_777_777;
+const READ_BUFFER_SIZE: usize = 128 * 1024;
+
+const TYPE_FILE: u8 = b'0';
+const TYPE_DIRECTORY: u8 = b'5';
+const TYPE_PAX_HEADER: u8 = b'x';
+
+/// Writes a POSIX (ustar + PAX) tar stream to an async sink.
+///
+/// Names longer than the ustar fields allow, non-ASCII names and files above 8 GiB
+/// are described by a preceding PAX extended header, which GNU tar, bsdtar and
+/// 7-Zip all understand.
+pub struct TarWriter<W> {
+    out: W,
+}
+
+impl<W: AsyncWrite + Unpin> TarWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+
+    /// Adds a directory entry. `name` must end with `/`.
+    pub async fn add_directory(&mut self, name: &str, modified: SystemTime) -> io::Result<()> {
+        self.write_header(name, 0, modified, TYPE_DIRECTORY, 0o755).await
+    }
+
+    /// Streams exactly `size` bytes of `reader` as a file entry.
+    /// A reader that ends early is padded with zeros so the stream stays well-formed.
+    pub async fn add_file<R: AsyncRead + Unpin>(
+        &mut self,
+        name: &str,
+        modified: SystemTime,
+        size: u64,
+        reader: R,
+    ) -> io::Result<u64> {
+        self.write_header(name, size, modified, TYPE_FILE, 0o644).await?;
+
+        let mut reader = reader.take(size);
+        let mut buf = vec![0u8; READ_BUFFER_SIZE];
+        let mut written = 0u64;
+        loop {
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            self.out.write_all(&buf[..n]).await?;
+            written += n as u64;
+        }
+
+        let mut remaining = size - written;
+        while remaining > 0 {
+            let n = remaining.min(READ_BUFFER_SIZE as u64) as usize;
+            buf[..n].fill(0);
+            self.out.write_all(&buf[..n]).await?;
+            remaining -= n as u64;
+        }
+        self.pad(size).await?;
+        Ok(written)
+    }
+
+    /// Adds a small in-memory file, e.g. a generated manifest.
+    pub async fn add_bytes(&mut self, name: &str, modified: SystemTime, data: &[u8]) -> io::Result<()> {
+        self.add_file(name, modified, data.len() as u64, data).await.map(|_| ())
+    }
+
+    /// Writes the two zero blocks that end the archive and returns the sink.
+    pub async fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(&[0u8; BLOCK_SIZE * 2]).await?;
+        self.out.flush().await?;
+        Ok(self.out)
+    }
+
+    async fn write_header(
+        &mut self,
+        name: &str,
+        size: u64,
+        modified: SystemTime,
+        entry_type: u8,
+        mode: u32,
+    ) -> io::Result<()> {
+        let mtime = modified
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        let fits_ustar_name = name.is_ascii() && name.len() <= 100;
+        let fits_ustar_size = size <= USTAR_MAX_SIZE;
+        if !fits_ustar_name || !fits_ustar_size {
+            let mut records = String::new();
+            if !fits_ustar_name {
+                records.push_str(&pax_record("path", name));
+            }
+            if !fits_ustar_size {
+                records.push_str(&pax_record("size", &size.to_string()));
+            }
+            let pax_name = format!("PaxHeaders/{}", ascii_fallback(name, 80));
+            let block = ustar_header(&pax_name, records.len() as u64, mtime, TYPE_PAX_HEADER, 0o644);
+            self.out.write_all(&block).await?;
+            self.out.write_all(records.as_bytes()).await?;
+            self.pad(records.len() as u64).await?;
+        }
+
+        let ustar_name = if fits_ustar_name { name.to_string() } else { ascii_fallback(name, 100) };
+        let ustar_size = if fits_ustar_size { size } else { 0 };
+        let block = ustar_header(&ustar_name, ustar_size, mtime, entry_type, mode);
+        self.out.write_all(&block).await
+    }
+
+    /// Pads the previous entry's data up to the next block boundary.
+    async fn pad(&mut self, len: u64) -> io::Result<()> {
+        let rem = (len % BLOCK_SIZE as u64) as usize;
+        if rem != 0 {
+            self.out.write_all(&[0u8; BLOCK_SIZE][..BLOCK_SIZE - rem]).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Builds a 512-byte ustar header block with its checksum filled in.
+fn ustar_header(name: &str, size: u64, mtime: u64, entry_type: u8, mode: u32) -> [u8; BLOCK_SIZE] {
+    let mut block = [0u8; BLOCK_SIZE];
+    block[..name.len()].copy_from_slice(name.as_bytes());
+    put_octal(&mut block[100..108], mode as u64);
+    put_octal(&mut block[108..116], 0); // uid
+    put_octal(&mut block[116..124], 0); // gid
+    put_octal(&mut block[124..136], size);
+    put_octal(&mut block[136..148], mtime);
+    block[156] = entry_type;
+    block[257..263].copy_from_slice(b"ustar\0");
+    block[263..265].copy_from_slice(b"00");
+
+    // The checksum is computed with its own field filled with spaces
+    block[148..156].fill(b' ');
+    let checksum: u32 = block.iter().map(|&b| b as u32).sum();
+    put_octal(&mut block[148..155], checksum as u64);
+    block[155] = b' ';
+    block
+}
+
+/// Writes `value` as zero-padded octal, leaving the last byte as the NUL terminator.
+fn put_octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    let text = format!("{value:0digits$o}");
+    let text = &text.as_bytes()[text.len().saturating_sub(digits)..];
+    field[..digits].copy_from_slice(text);
+    field[digits] = 0;
+}
+
+/// Formats a PAX record: `"<len> <key>=<value>\n"`, where `<len>` counts the whole record.
+fn pax_record(key: &str, value: &str) -> String {
+    let body = format!(" {key}={value}\n");
+    let mut len = body.len() + 1;
+    while len.to_string().len() + body.len() > len {
+        len += 1;
+    }
+    format!("{len}{body}")
+}
+
+/// ASCII-only, length-limited stand-in for a name carried in a PAX header.
+fn ascii_fallback(name: &str, max: usize) -> String {
+    let mut fallback: String = name
+        .chars()
+        .map(|c| if c.is_ascii() && !c.is_ascii_control() { c } else { '_' })
+        .collect();
+    if fallback.len() > max {
+        fallback = fallback[fallback.len() - max..].to_string();
+    }
+    fallback
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[tokio::test]
+    async fn round_trips_through_the_tar_crate() {
+        let now = SystemTime::now();
+        let long_name = format!("{}/file.txt", "deep".repeat(40));
+        let mut writer = TarWriter::new(Vec::new());
+        writer.add_directory("photos/", now).await.unwrap();
+        writer.add_bytes("photos/short.txt", now, b"short").await.unwrap();
+        writer.add_bytes(&long_name, now, b"long").await.unwrap();
+        writer.add_bytes("photos/été.txt", now, b"unicode").await.unwrap();
+        // A reader that ends early is padded to the promised size
+        writer.add_file("padded.bin", now, 6, &b"abc"[..]).await.unwrap();
+        let bytes = writer.finish().await.unwrap();
+
+        let mut archive = tar::Archive::new(&bytes[..]);
+        let entries: Vec<(String, Vec<u8>)> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let name = entry.path().unwrap().to_string_lossy().into_owned();
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data).unwrap();
+                (name, data)
+            })
+            .collect();
+
+        assert_eq!(entries.len(), 5);
+        assert_eq!(entries[0].0.trim_end_matches('/'), "photos");
+        assert_eq!(entries[1], ("photos/short.txt".to_string(), b"short".to_vec()));
+        assert_eq!(entries[2], (long_name, b"long".to_vec()));
+        assert_eq!(entries[3], ("photos/été.txt".to_string(), b"unicode".to_vec()));
+        assert_eq!(entries[4], ("padded.bin".to_string(), b"abc\0\0\0".to_vec()));
+    }
+
+    #[test]
+    fn pax_records_count_their_own_length() {
+        for value in ["x", &"y".repeat(95), &"z".repeat(995)] {
+            let record = pax_record("path", value);
+            let (len, _) = record.split_once(' ').unwrap();
+            assert_eq!(len.parse::<usize>().unwrap(), record.len());
+        }
+    }
+}
@@ -0,0 +1,492 @@
+use crc32fast::Hasher;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt, SeekFrom},
+    sync::Mutex,
+};
+use super::zip_writer::{
+    central_directory, check_name_len, data_descriptor, data_descriptor_len, directory_header, dos_date_time,
+    file_header, needs_zip64, CentralRecord, ZipMethod, DIRECTORY_ENTRY_FLAGS, FILE_ENTRY_FLAGS,
+};
+use super::{ArchiveItem, SKIPPED_MANIFEST_NAME};
+use crate::file_manager::file_utils::{file_version_tag, safe_path};
+
+const READ_BUFFER_SIZE: usize = 128 * 1024;
+
+/// Files whose CRC is remembered at most; the least recently used are dropped first.
+const CRC_CACHE_CAPACITY: usize = 50_000;
+
+/// CRC-32 values of archived files, for the current version of each path.
+///
+/// Data descriptors and the central directory need every member's CRC. Caching them
+/// means a resumed download only reads the files it actually sends. A path keeps
+/// one entry, replaced when the file's version tag changes.
+#[derive(Default)]
+pub struct CrcCache {
+    entries: std::sync::Mutex<CrcEntries>,
+}
+
+#[derive(Default)]
+struct CrcEntries {
+    by_path: HashMap<String, CachedCrc>,
+    /// Bumped on every use, so entries can be ranked by when they were last needed.
+    clock: u64,
+}
+
+struct CachedCrc {
+    version: String,
+    crc: u32,
+    last_used: u64,
+}
+
+impl CrcCache {
+    fn get(&self, rel_path: &str, version: &str) -> Option<u32> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.clock += 1;
+        let clock = entries.clock;
+        let cached = entries.by_path.get_mut(rel_path).filter(|cached| cached.version == version)?;
+        cached.last_used = clock;
+        Some(cached.crc)
+    }
+
+    fn insert(&self, rel_path: &str, version: &str, crc: u32) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.clock += 1;
+        let cached = CachedCrc { version: version.to_string(), crc, last_used: entries.clock };
+        entries.by_path.insert(rel_path.to_string(), cached);
+        if entries.by_path.len() > CRC_CACHE_CAPACITY {
+            // Drop the least recently used tenth at once, so eviction stays rare
+            let mut ages: Vec<u64> = entries.by_path.values().map(|cached| cached.last_used).collect();
+            let cutoff_index = CRC_CACHE_CAPACITY / 10;
+            let (_, &mut cutoff, _) = ages.select_nth_unstable(cutoff_index);
+            entries.by_path.retain(|_, cached| cached.last_used > cutoff);
+        }
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.entries.lock().unwrap().by_path.len()
+    }
+}
+
+/// Where the bytes of a planned entry come from.
+enum EntryData {
+    Directory,
+    File {
+        path: PathBuf,
+        lock: Arc<Mutex<()>>,
+        /// `file_version_tag` at planning time; the file must still match when it is read.
+        version: String,
+    },
+    /// Generated content, such as the skipped-files manifest.
+    Inline(Vec<u8>),
+}
+
+struct PlannedEntry {
+    name: String,
+    rel_path: String,
+    data: EntryData,
+    size: u64,
+    dos_time: u16,
+    dos_date: u16,
+    zip64: bool,
+    header_offset: u64,
+}
+
+enum Segment {
+    /// Local header bytes, fully known at planning time.
+    Header(Vec<u8>),
+    /// The stored bytes of entry `n`.
+    Data(usize),
+    /// The data descriptor of entry `n`, which carries its CRC.
+    Descriptor(usize),
+    CentralDirectory,
+}
+
+struct PlacedSegment {
+    start: u64,
+    len: u64,
+    segment: Segment,
+}
+
+/// A byte-exact plan of a store-only ZIP archive for a snapshot of the tree.
+///
+/// Every header, offset and the total length are computed before any file is read,
+/// so the same snapshot always produces the same bytes. That gives the archive a
+/// real `Content-Length` and ETag, and lets a `Range` request start at any offset.
+pub struct ZipLayout {
+    entries: Vec<PlannedEntry>,
+    segments: Vec<PlacedSegment>,
+    central_offset: u64,
+    pub total_len: u64,
+    /// Opaque version tag of the snapshot, usable as an entity tag.
+    pub tag: String,
+    pub last_modified: Option<SystemTime>,
+}
+
+impl ZipLayout {
+    /// Plans the archive from a metadata snapshot of `items`.
+    /// Files that cannot be opened are left out and listed in a manifest entry.
+    pub async fn plan(items: Vec<ArchiveItem>) -> ZipLayout {
+        let mut planned: Vec<(ArchiveItem, EntryData, u64, SystemTime)> = Vec::new();
+        let mut skipped: Vec<String> = Vec::new();
+
+        for item in items {
+            if let Err(e) = check_name_len(&item.name) {
+                skipped.push(format!("{}: {e}", item.rel_path));
+                continue;
+            }
+            let path = match safe_path(&item.rel_path) {
+                Ok(p) => p,
+                Err(_) => {
+                    skipped.push(format!("{}: outside the shared folder", item.rel_path));
+                    continue;
+                }
+            };
+            let meta = match tokio::fs::metadata(&path).await {
+                Ok(meta) => meta,
+                Err(e) => {
+                    skipped.push(format!("{}: {e}", item.rel_path));
+                    continue;
+                }
+            };
+            let modified = meta.modified().unwrap_or(UNIX_EPOCH);
+
+            if item.is_dir {
+                planned.push((item, EntryData::Directory, 0, modified));
+                continue;
+            }
+            if let Err(e) = File::open(&path).await {
+                skipped.push(format!("{}: {e}", item.rel_path));
+                continue;
+            }
+            let data = EntryData::File {
+                path,
+                lock: item.lock.clone(),
+                version: file_version_tag(&meta),
+            };
+            planned.push((item, data, meta.len(), modified));
+        }
+
+        let last_modified = planned.iter().map(|(_, _, _, modified)| *modified).max();
+
+        let mut entries: Vec<PlannedEntry> = Vec::new();
+        for (item, data, size, modified) in planned {
+            let (dos_time, dos_date) = dos_date_time(modified);
+            entries.push(PlannedEntry {
+                name: item.name,
+                rel_path: item.rel_path,
+                data,
+                size,
+                dos_time,
+                dos_date,
+                zip64: needs_zip64(size),
+                header_offset: 0,
+            });
+        }
+
+        if !skipped.is_empty() {
+            // The manifest reuses the snapshot's newest timestamp so its bytes stay stable
+            let manifest = format!("The following files could not be archived:\n{}\n", skipped.join("\n"));
+            let (dos_time, dos_date) = dos_date_time(last_modified.unwrap_or(UNIX_EPOCH));
+            entries.push(PlannedEntry {
+                name: SKIPPED_MANIFEST_NAME.to_string(),
+                rel_path: String::new(),
+                size: manifest.len() as u64,
+                data: EntryData::Inline(manifest.into_bytes()),
+                dos_time,
+                dos_date,
+                zip64: false,
+                header_offset: 0,
+            });
+        }
+
+        let mut segments = Vec::new();
+        let mut hasher = Sha256::new();
+        let mut offset = 0u64;
+        let push = |segments: &mut Vec<PlacedSegment>, offset: &mut u64, len: u64, segment: Segment| {
+            segments.push(PlacedSegment { start: *offset, len, segment });
+            *offset += len;
+        };
+
+        for (index, entry) in entries.iter_mut().enumerate() {
+            entry.header_offset = offset;
+            let header = match entry.data {
+                EntryData::Directory => directory_header(&entry.name, entry.dos_time, entry.dos_date),
+                _ => file_header(&entry.name, ZipMethod::Stored, entry.dos_time, entry.dos_date, entry.zip64),
+            };
+            hasher.update(&header);
+            match &entry.data {
+                EntryData::File { version, .. } => hasher.update(version.as_bytes()),
+                EntryData::Inline(data) => hasher.update(data),
+                EntryData::Directory => {}
+            }
+
+            push(&mut segments, &mut offset, header.len() as u64, Segment::Header(header));
+            if !matches!(entry.data, EntryData::Directory) {
+                push(&mut segments, &mut offset, entry.size, Segment::Data(index));
+                push(&mut segments, &mut offset, data_descriptor_len(entry.zip64), Segment::Descriptor(index));
+            }
+        }
+
+        let central_offset = offset;
+        let mut layout = ZipLayout {
+            entries,
+            segments,
+            central_offset,
+            total_len: 0,
+            tag: String::new(),
+            last_modified,
+        };
+        // The central directory's length does not depend on the CRCs, so zeros are fine here
+        let zero_crcs = vec![0; layout.entries.len()];
+        let central_len = central_directory(&layout.records(&zero_crcs), central_offset).len() as u64;
+        layout.segments.push(PlacedSegment {
+            start: central_offset,
+            len: central_len,
+            segment: Segment::CentralDirectory,
+        });
+        layout.total_len = central_offset + central_len;
+        let digest: String = hasher.finalize()[..8].iter().map(|b| format!("{b:02x}")).collect();
+        layout.tag = format!("zip-{:x}-{digest}", layout.total_len);
+        layout
+    }
+
+    fn records(&self, crcs: &[u32]) -> Vec<CentralRecord> {
+        self.entries
+            .iter()
+            .zip(crcs)
+            .map(|(entry, &crc)| {
+                let is_dir = matches!(entry.data, EntryData::Directory);
+                CentralRecord {
+                    name: entry.name.clone(),
+                    method: ZipMethod::Stored,
+                    flags: if is_dir { DIRECTORY_ENTRY_FLAGS } else { FILE_ENTRY_FLAGS },
+                    dos_time: entry.dos_time,
+                    dos_date: entry.dos_date,
+                    crc,
+                    compressed_size: entry.size,
+                    uncompressed_size: entry.size,
+                    header_offset: entry.header_offset,
+                    is_dir,
+                    zip64: entry.zip64,
+                }
+            })
+            .collect()
+    }
+
+    /// Writes bytes `start..=end` of the archive to `out`.
+    ///
+    /// Fails if a member file changed since the layout was planned, because the
+    /// promised length and entity tag would no longer hold.
+    pub async fn write_range<W: AsyncWrite + Unpin>(
+        &self,
+        crcs: &CrcCache,
+        start: u64,
+        end: u64,
+        out: &mut W,
+    ) -> io::Result<()> {
+        for placed in &self.segments {
+            let segment_end = placed.start + placed.len;
+            if placed.len == 0 || segment_end <= start || placed.start > end {
+                continue;
+            }
+            let from = start.saturating_sub(placed.start);
+            let to = placed.len.min(end + 1 - placed.start);
+
+            match &placed.segment {
+                Segment::Header(bytes) => out.write_all(&bytes[from as usize..to as usize]).await?,
+                Segment::Data(index) => self.write_data(*index, from, to, crcs, out).await?,
+                Segment::Descriptor(index) => {
+                    let entry = &self.entries[*index];
+                    let crc = self.crc(*index, crcs).await?;
+                    let bytes = data_descriptor(crc, entry.size, entry.size, entry.zip64);
+                    out.write_all(&bytes[from as usize..to as usize]).await?;
+                }
+                Segment::CentralDirectory => {
+                    let mut crcs_in_order = Vec::with_capacity(self.entries.len());
+                    for index in 0..self.entries.len() {
+                        crcs_in_order.push(self.crc(index, crcs).await?);
+                    }
+                    let bytes = central_directory(&self.records(&crcs_in_order), self.central_offset);
+                    out.write_all(&bytes[from as usize..to as usize]).await?;
+                }
+            }
+        }
+        out.flush().await
+    }
+
+    /// Streams bytes `from..to` of entry `index`, caching its CRC when the whole file passes through.
+    async fn write_data<W: AsyncWrite + Unpin>(
+        &self,
+        index: usize,
+        from: u64,
+        to: u64,
+        crcs: &CrcCache,
+        out: &mut W,
+    ) -> io::Result<()> {
+        let entry = &self.entries[index];
+        let (path, lock, version) = match &entry.data {
+            EntryData::File { path, lock, version } => (path, lock, version),
+            EntryData::Inline(data) => return out.write_all(&data[from as usize..to as usize]).await,
+            EntryData::Directory => return Ok(()),
+        };
+
+        let _file_guard = lock.lock().await;
+        let mut file = open_unchanged(path, version).await?;
+        file.seek(SeekFrom::Start(from)).await?;
+
+        let whole_file = from == 0 && to == entry.size;
+        let mut crc = Hasher::new();
+        let mut reader = file.take(to - from);
+        let mut buf = vec![0u8; READ_BUFFER_SIZE];
+        let mut copied = 0u64;
+        loop {
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            if whole_file {
+                crc.update(&buf[..n]);
+            }
+            out.write_all(&buf[..n]).await?;
+            copied += n as u64;
+        }
+        if copied != to - from {
+            return Err(io::Error::other(format!("{} changed while being archived", entry.rel_path)));
+        }
+        if whole_file {
+            crcs.insert(&entry.rel_path, version, crc.finalize());
+        }
+        Ok(())
+    }
+
+    /// Returns the CRC of entry `index`, reading the file only when it is not cached.
+    async fn crc(&self, index: usize, crcs: &CrcCache) -> io::Result<u32> {
+        let entry = &self.entries[index];
+        let (path, lock, version) = match &entry.data {
+            EntryData::File { path, lock, version } => (path, lock, version),
+            EntryData::Inline(data) => return Ok(crc32fast::hash(data)),
+            EntryData::Directory => return Ok(0),
+        };
+        if let Some(crc) = crcs.get(&entry.rel_path, version) {
+            return Ok(crc);
+        }
+
+        let _file_guard = lock.lock().await;
+        let mut file = open_unchanged(path, version).await?;
+        let mut crc = Hasher::new();
+        let mut buf = vec![0u8; READ_BUFFER_SIZE];
+        loop {
+            let n = file.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            crc.update(&buf[..n]);
+        }
+        let crc = crc.finalize();
+        crcs.insert(&entry.rel_path, version, crc);
+        Ok(crc)
+    }
+}
+
+/// Opens a member file, failing if it no longer matches the planned version.
+async fn open_unchanged(path: &PathBuf, version: &str) -> io::Result<File> {
+    let file = File::open(path).await?;
+    let meta = file.metadata().await?;
+    if file_version_tag(&meta) != version {
+        return Err(io::Error::other(format!("{} changed since the archive was planned", path.display())));
+    }
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_manager::test_support::share_dir;
+    use std::io::{Cursor, Read};
+
+    fn item(name: &str, rel_path: &str, is_dir: bool) -> ArchiveItem {
+        ArchiveItem {
+            name: name.to_string(),
+            rel_path: rel_path.to_string(),
+            is_dir,
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    async fn write(layout: &ZipLayout, crcs: &CrcCache, start: u64, end: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        layout.write_range(crcs, start, end, &mut out).await.unwrap();
+        out
+    }
+
+    #[tokio::test]
+    async fn planned_archive_round_trips_and_ranges_line_up() {
+        let dir = share_dir().join("zip-layout");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let big: Vec<u8> = (0..50_000u32).flat_map(|i| i.to_le_bytes()).collect();
+        std::fs::write(dir.join("sub/big.bin"), &big).unwrap();
+        std::fs::write(dir.join("a.txt"), b"hello").unwrap();
+
+        let items = vec![
+            item("zip-layout/", "zip-layout", true),
+            item("zip-layout/a.txt", "zip-layout/a.txt", false),
+            item("zip-layout/sub/", "zip-layout/sub", true),
+            item("zip-layout/sub/big.bin", "zip-layout/sub/big.bin", false),
+            item("zip-layout/missing.txt", "zip-layout/missing.txt", false),
+        ];
+        let layout = ZipLayout::plan(items).await;
+        let crcs = CrcCache::default();
+        let full = write(&layout, &crcs, 0, layout.total_len - 1).await;
+        assert_eq!(full.len() as u64, layout.total_len);
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(full.clone())).expect("valid zip");
+        let mut read = |name: &str| {
+            let mut data = Vec::new();
+            archive.by_name(name).unwrap().read_to_end(&mut data).unwrap();
+            data
+        };
+        assert_eq!(read("zip-layout/a.txt"), b"hello");
+        assert_eq!(read("zip-layout/sub/big.bin"), big);
+        let manifest = String::from_utf8(read(SKIPPED_MANIFEST_NAME)).unwrap();
+        assert!(manifest.contains("zip-layout/missing.txt"));
+
+        // Any split into ranges gives the same bytes, with a fresh CRC cache too
+        let cut = layout.total_len / 3;
+        let mut pieces = write(&layout, &CrcCache::default(), 0, cut).await;
+        pieces.extend(write(&layout, &CrcCache::default(), cut + 1, layout.total_len - 1).await);
+        assert_eq!(pieces, full);
+
+        // The same snapshot plans the same archive
+        let again = ZipLayout::plan(vec![item("zip-layout/a.txt", "zip-layout/a.txt", false)]).await;
+        let once_more = ZipLayout::plan(vec![item("zip-layout/a.txt", "zip-layout/a.txt", false)]).await;
+        assert_eq!(again.tag, once_more.tag);
+        assert_eq!(again.total_len, once_more.total_len);
+    }
+
+    #[test]
+    fn crc_cache_keeps_one_version_per_path_and_stays_bounded() {
+        let cache = CrcCache::default();
+        cache.insert("a", "v1", 1);
+        cache.insert("a", "v2", 2);
+        assert_eq!(cache.get("a", "v1"), None);
+        assert_eq!(cache.get("a", "v2"), Some(2));
+        assert_eq!(cache.len(), 1);
+
+        for i in 0..CRC_CACHE_CAPACITY + 10 {
+            cache.insert(&format!("f{i}"), "v", i as u32);
+            // Keep "a" in use so it survives eviction
+            cache.get("a", "v2");
+        }
+        assert!(cache.len() <= CRC_CACHE_CAPACITY);
+        assert_eq!(cache.get("a", "v2"), Some(2));
+        assert_eq!(cache.get("f0", "v"), None);
+    }
+}
@@ -0,0 +1,505 @@
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use crc32fast::Hasher;
+use flate2::{write::DeflateEncoder, Compression};
+use std::io::{self, Write};
+use std::time::SystemTime;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const LOCAL_FILE_HEADER_SIG: u32 = 0x0403_4b50;
+const DATA_DESCRIPTOR_SIG: u32 = 0x0807_4b50;
+const CENTRAL_DIRECTORY_SIG: u32 = 0x0201_4b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_SIG: u32 = 0x0606_4b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR_SIG: u32 = 0x0706_4b50;
+const END_OF_CENTRAL_DIRECTORY_SIG: u32 = 0x0605_4b50;
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+
+/// Version 4.5 of the specification is the first with ZIP64 support.
+const VERSION_ZIP64: u16 = 45;
+const VERSION_DEFAULT: u16 = 20;
+/// "Version made by": upper byte 3 marks Unix file attributes.
+const VERSION_MADE_BY: u16 = (3 << 8) | VERSION_ZIP64;
+
+/// General purpose flags: bit 3 (sizes and CRC follow in a data descriptor) and bit 11 (UTF-8 names).
+const FLAG_DATA_DESCRIPTOR: u16 = 0x0008;
+const FLAG_UTF8: u16 = 0x0800;
+
+/// Entries whose size may come close to 4 GiB are written in ZIP64 form up front,
+/// since a streaming writer cannot go back and widen the header later.
+const ZIP64_ENTRY_THRESHOLD: u64 = 0xF000_0000;
+const U32_SENTINEL: u64 = 0xFFFF_FFFF;
+
+const READ_BUFFER_SIZE: usize = 128 * 1024;
+
+/// Compression method of a ZIP entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZipMethod {
+    /// No compression; used for media and already-compressed formats.
+    Stored,
+    Deflated,
+}
+
+impl ZipMethod {
+    fn code(self) -> u16 {
+        match self {
+            ZipMethod::Stored => 0,
+            ZipMethod::Deflated => 8,
+        }
+    }
+}
+
+/// Everything the central directory needs to know about a written entry.
+pub(super) struct CentralRecord {
+    pub(super) name: String,
+    pub(super) method: ZipMethod,
+    pub(super) flags: u16,
+    pub(super) dos_time: u16,
+    pub(super) dos_date: u16,
+    pub(super) crc: u32,
+    pub(super) compressed_size: u64,
+    pub(super) uncompressed_size: u64,
+    pub(super) header_offset: u64,
+    pub(super) is_dir: bool,
+    /// Whether the local header was written in ZIP64 form (see [`needs_zip64`]).
+    pub(super) zip64: bool,
+}
+
+/// General purpose flags used for file entries (sizes and CRC in a trailing descriptor).
+pub(super) const FILE_ENTRY_FLAGS: u16 = FLAG_UTF8 | FLAG_DATA_DESCRIPTOR;
+/// General purpose flags used for directory entries.
+pub(super) const DIRECTORY_ENTRY_FLAGS: u16 = FLAG_UTF8;
+
+/// Whether an entry of the given size is written in ZIP64 form.
+pub(super) fn needs_zip64(size_hint: u64) -> bool {
+    size_hint >= ZIP64_ENTRY_THRESHOLD
+}
+
+/// Fails for names too long for the 16-bit length fields of ZIP headers.
+pub(super) fn check_name_len(name: &str) -> io::Result<()> {
+    if name.len() > u16::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("name of {} bytes is too long for a ZIP archive", name.len()),
+        ));
+    }
+    Ok(())
+}
+
+/// Builds the local header of a directory entry. `name` must end with `/`.
+pub(super) fn directory_header(name: &str, dos_time: u16, dos_date: u16) -> Vec<u8> {
+    let mut header = Vec::with_capacity(30 + name.len());
+    put_u32(&mut header, LOCAL_FILE_HEADER_SIG);
+    put_u16(&mut header, VERSION_DEFAULT);
+    put_u16(&mut header, DIRECTORY_ENTRY_FLAGS);
+    put_u16(&mut header, ZipMethod::Stored.code());
+    put_u16(&mut header, dos_time);
+    put_u16(&mut header, dos_date);
+    put_u32(&mut header, 0); // crc
+    put_u32(&mut header, 0); // compressed size
+    put_u32(&mut header, 0); // uncompressed size
+    put_u16(&mut header, name.len() as u16);
+    put_u16(&mut header, 0); // extra length
+    header.extend_from_slice(name.as_bytes());
+    header
+}
+
+/// Builds the local header of a file entry whose sizes and CRC follow in a data descriptor.
+pub(super) fn file_header(name: &str, method: ZipMethod, dos_time: u16, dos_date: u16, zip64: bool) -> Vec<u8> {
+    let mut header = Vec::with_capacity(30 + name.len() + 20);
+    put_u32(&mut header, LOCAL_FILE_HEADER_SIG);
+    put_u16(&mut header, if zip64 { VERSION_ZIP64 } else { VERSION_DEFAULT });
+    put_u16(&mut header, FILE_ENTRY_FLAGS);
+    put_u16(&mut header, method.code());
+    put_u16(&mut header, dos_time);
+    put_u16(&mut header, dos_date);
+    put_u32(&mut header, 0); // crc, in data descriptor
+    if zip64 {
+        put_u32(&mut header, U32_SENTINEL as u32);
+        put_u32(&mut header, U32_SENTINEL as u32);
+    } else {
+        put_u32(&mut header, 0);
+        put_u32(&mut header, 0);
+    }
+    put_u16(&mut header, name.len() as u16);
+    put_u16(&mut header, if zip64 { 20 } else { 0 });
+    header.extend_from_slice(name.as_bytes());
+    if zip64 {
+        // Sizes are zero here; the real values follow in the data descriptor
+        put_u16(&mut header, ZIP64_EXTRA_ID);
+        put_u16(&mut header, 16);
+        put_u64(&mut header, 0);
+        put_u64(&mut header, 0);
+    }
+    header
+}
+
+/// Length of the data descriptor that follows an entry's data.
+pub(super) fn data_descriptor_len(zip64: bool) -> u64 {
+    if zip64 { 24 } else { 16 }
+}
+
+/// Builds the data descriptor carrying an entry's CRC and final sizes.
+pub(super) fn data_descriptor(crc: u32, compressed_size: u64, uncompressed_size: u64, zip64: bool) -> Vec<u8> {
+    let mut descriptor = Vec::with_capacity(24);
+    put_u32(&mut descriptor, DATA_DESCRIPTOR_SIG);
+    put_u32(&mut descriptor, crc);
+    if zip64 {
+        put_u64(&mut descriptor, compressed_size);
+        put_u64(&mut descriptor, uncompressed_size);
+    } else {
+        put_u32(&mut descriptor, compressed_size as u32);
+        put_u32(&mut descriptor, uncompressed_size as u32);
+    }
+    descriptor
+}
+
+/// Builds the central directory for `records` followed by the (ZIP64) end records.
+/// `central_offset` is where the central directory starts in the archive.
+/// The length of the result does not depend on the CRC values.
+pub(super) fn central_directory(records: &[CentralRecord], central_offset: u64) -> Vec<u8> {
+    let mut out = Vec::new();
+    for record in records {
+        let mut extra = Vec::new();
+        let uncompressed = sentinel_or(record.uncompressed_size, &mut extra);
+        let compressed = sentinel_or(record.compressed_size, &mut extra);
+        let offset = sentinel_or(record.header_offset, &mut extra);
+        let extra = if extra.is_empty() {
+            extra
+        } else {
+            let mut field = Vec::with_capacity(4 + extra.len());
+            put_u16(&mut field, ZIP64_EXTRA_ID);
+            put_u16(&mut field, extra.len() as u16);
+            field.extend_from_slice(&extra);
+            field
+        };
+        let external_attributes: u32 = if record.is_dir {
+            (0o040755 << 16) | 0x10
+        } else {
+            0o100644 << 16
+        };
+
+        put_u32(&mut out, CENTRAL_DIRECTORY_SIG);
+        put_u16(&mut out, VERSION_MADE_BY);
+        // ZIP64 is needed if the local header used it or this record carries the extra field
+        let zip64 = record.zip64 || !extra.is_empty();
+        put_u16(&mut out, if zip64 { VERSION_ZIP64 } else { VERSION_DEFAULT });
+        put_u16(&mut out, record.flags);
+        put_u16(&mut out, record.method.code());
+        put_u16(&mut out, record.dos_time);
+        put_u16(&mut out, record.dos_date);
+        put_u32(&mut out, record.crc);
+        put_u32(&mut out, compressed);
+        put_u32(&mut out, uncompressed);
+        put_u16(&mut out, record.name.len() as u16);
+        put_u16(&mut out, extra.len() as u16);
+        put_u16(&mut out, 0); // comment length
+        put_u16(&mut out, 0); // disk number
+        put_u16(&mut out, 0); // internal attributes
+        put_u32(&mut out, external_attributes);
+        put_u32(&mut out, offset);
+        out.extend_from_slice(record.name.as_bytes());
+        out.extend_from_slice(&extra);
+    }
+
+    let central_size = out.len() as u64;
+    let entry_count = records.len() as u64;
+    let needs_zip64 = entry_count >= 0xFFFF
+        || central_size >= U32_SENTINEL
+        || central_offset >= U32_SENTINEL;
+
+    if needs_zip64 {
+        let zip64_end_offset = central_offset + central_size;
+        put_u32(&mut out, ZIP64_END_OF_CENTRAL_DIRECTORY_SIG);
+        put_u64(&mut out, 44); // size of the remaining record
+        put_u16(&mut out, VERSION_MADE_BY);
+        put_u16(&mut out, VERSION_ZIP64);
+        put_u32(&mut out, 0); // this disk
+        put_u32(&mut out, 0); // disk with central directory
+        put_u64(&mut out, entry_count);
+        put_u64(&mut out, entry_count);
+        put_u64(&mut out, central_size);
+        put_u64(&mut out, central_offset);
+
+        put_u32(&mut out, ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR_SIG);
+        put_u32(&mut out, 0);
+        put_u64(&mut out, zip64_end_offset);
+        put_u32(&mut out, 1); // total disks
+    }
+
+    put_u32(&mut out, END_OF_CENTRAL_DIRECTORY_SIG);
+    put_u16(&mut out, 0);
+    put_u16(&mut out, 0);
+    put_u16(&mut out, entry_count.min(0xFFFF) as u16);
+    put_u16(&mut out, entry_count.min(0xFFFF) as u16);
+    put_u32(&mut out, central_size.min(U32_SENTINEL) as u32);
+    put_u32(&mut out, central_offset.min(U32_SENTINEL) as u32);
+    put_u16(&mut out, 0); // comment length
+    out
+}
+
+/// Writes a ZIP archive to an async sink in a single forward pass.
+///
+/// Entry sizes and CRCs are not known until the data has been streamed, so file
+/// entries carry a trailing data descriptor (general purpose bit 3). ZIP64 records
+/// are emitted whenever an entry, offset or entry count outgrows the classic format.
+pub struct ZipWriter<W> {
+    out: W,
+    offset: u64,
+    records: Vec<CentralRecord>,
+}
+
+impl<W: AsyncWrite + Unpin> ZipWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self { out, offset: 0, records: Vec::new() }
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.out.write_all(bytes).await?;
+        self.offset += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Adds an empty directory entry. `name` must end with `/`.
+    pub async fn add_directory(&mut self, name: &str, modified: SystemTime) -> io::Result<()> {
+        check_name_len(name)?;
+        let (dos_time, dos_date) = dos_date_time(modified);
+        let header_offset = self.offset;
+        self.write(&directory_header(name, dos_time, dos_date)).await?;
+
+        self.records.push(CentralRecord {
+            name: name.to_string(),
+            method: ZipMethod::Stored,
+            flags: DIRECTORY_ENTRY_FLAGS,
+            dos_time,
+            dos_date,
+            crc: 0,
+            compressed_size: 0,
+            uncompressed_size: 0,
+            header_offset,
+            is_dir: true,
+            zip64: false,
+        });
+        Ok(())
+    }
+
+    /// Streams a file entry from `reader`.
+    ///
+    /// `size_hint` is the expected uncompressed size and decides whether the entry is
+    /// written in ZIP64 form. Returns the number of bytes read from `reader`.
+    pub async fn add_file<R: AsyncRead + Unpin>(
+        &mut self,
+        name: &str,
+        modified: SystemTime,
+        method: ZipMethod,
+        size_hint: u64,
+        mut reader: R,
+    ) -> io::Result<u64> {
+        check_name_len(name)?;
+        let (dos_time, dos_date) = dos_date_time(modified);
+        let header_offset = self.offset;
+        let zip64 = needs_zip64(size_hint);
+        self.write(&file_header(name, method, dos_time, dos_date, zip64)).await?;
+
+        let mut crc = Hasher::new();
+        let mut uncompressed_size = 0u64;
+        let mut compressed_size = 0u64;
+        let mut encoder = match method {
+            ZipMethod::Deflated => Some(DeflateEncoder::new(Vec::new(), Compression::default())),
+            ZipMethod::Stored => None,
+        };
+        let mut buf = vec![0u8; READ_BUFFER_SIZE];
+
+        loop {
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            let chunk = &buf[..n];
+            crc.update(chunk);
+            uncompressed_size += n as u64;
+
+            match encoder.as_mut() {
+                Some(encoder) => {
+                    encoder.write_all(chunk)?;
+                    let compressed = std::mem::take(encoder.get_mut());
+                    compressed_size += compressed.len() as u64;
+                    self.write(&compressed).await?;
+                }
+                None => {
+                    compressed_size += n as u64;
+                    self.write(chunk).await?;
+                }
+            }
+        }
+
+        if let Some(encoder) = encoder {
+            let rest = encoder.finish()?;
+            compressed_size += rest.len() as u64;
+            self.write(&rest).await?;
+        }
+
+        if !zip64 && (uncompressed_size >= U32_SENTINEL || compressed_size >= U32_SENTINEL) {
+            return Err(io::Error::other(format!("{name} grew past 4 GiB while being archived")));
+        }
+
+        let crc = crc.finalize();
+        self.write(&data_descriptor(crc, compressed_size, uncompressed_size, zip64)).await?;
+
+        self.records.push(CentralRecord {
+            name: name.to_string(),
+            method,
+            flags: FILE_ENTRY_FLAGS,
+            dos_time,
+            dos_date,
+            crc,
+            compressed_size,
+            uncompressed_size,
+            header_offset,
+            is_dir: false,
+            zip64,
+        });
+        Ok(uncompressed_size)
+    }
+
+    /// Adds a small in-memory file, e.g. a generated manifest.
+    pub async fn add_bytes(&mut self, name: &str, modified: SystemTime, data: &[u8]) -> io::Result<()> {
+        self.add_file(name, modified, ZipMethod::Deflated, data.len() as u64, data)
+            .await
+            .map(|_| ())
+    }
+
+    /// Writes the central directory and end records, then flushes and returns the sink.
+    pub async fn finish(mut self) -> io::Result<W> {
+        let records = std::mem::take(&mut self.records);
+        let central = central_directory(&records, self.offset);
+        self.write(&central).await?;
+        self.out.flush().await?;
+        Ok(self.out)
+    }
+}
+
+/// Returns the 32-bit field value for the central directory, moving values that
+/// do not fit into the ZIP64 extra field.
+fn sentinel_or(value: u64, zip64_extra: &mut Vec<u8>) -> u32 {
+    if value >= U32_SENTINEL {
+        put_u64(zip64_extra, value);
+        U32_SENTINEL as u32
+    } else {
+        value as u32
+    }
+}
+
+/// Converts a timestamp to MS-DOS time and date fields (UTC, clamped to 1980..=2107).
+pub(super) fn dos_date_time(time: SystemTime) -> (u16, u16) {
+    let datetime: DateTime<Utc> = time.into();
+    let year = datetime.year().clamp(1980, 2107) as u16;
+    let time = ((datetime.hour() as u16) << 11)
+        | ((datetime.minute() as u16) << 5)
+        | (datetime.second() as u16 / 2);
+    let date = ((year - 1980) << 9) | ((datetime.month() as u16) << 5) | datetime.day() as u16;
+    (time, date)
+}
+
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(buf: &mut Vec<u8>, value: u64) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+
+    fn read_back(bytes: Vec<u8>) -> Vec<(String, Vec<u8>)> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).expect("valid zip");
+        (0..archive.len())
+            .map(|i| {
+                let mut entry = archive.by_index(i).unwrap();
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data).unwrap();
+                (entry.name().unwrap().to_string(), data)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn round_trips_through_the_zip_crate() {
+        let now = SystemTime::now();
+        let big: Vec<u8> = (0..300_000u32).flat_map(|i| i.to_le_bytes()).collect();
+        let mut writer = ZipWriter::new(Vec::new());
+        writer.add_directory("photos/", now).await.unwrap();
+        writer.add_file("photos/a.bin", now, ZipMethod::Stored, big.len() as u64, &big[..]).await.unwrap();
+        writer.add_file("notes/été.txt", now, ZipMethod::Deflated, 5, &b"hello"[..]).await.unwrap();
+        writer.add_bytes("empty.txt", now, b"").await.unwrap();
+        let entries = read_back(writer.finish().await.unwrap());
+
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0], ("photos/".to_string(), vec![]));
+        assert_eq!(entries[1], ("photos/a.bin".to_string(), big));
+        assert_eq!(entries[2], ("notes/été.txt".to_string(), b"hello".to_vec()));
+        assert_eq!(entries[3], ("empty.txt".to_string(), vec![]));
+    }
+
+    #[tokio::test]
+    async fn zip64_entries_are_readable() {
+        let now = SystemTime::now();
+        let mut writer = ZipWriter::new(Vec::new());
+        // A size hint past the threshold writes the entry in ZIP64 form whatever its real size
+        writer.add_file("large.bin", now, ZipMethod::Stored, ZIP64_ENTRY_THRESHOLD, &b"data"[..]).await.unwrap();
+        writer.add_file("small.bin", now, ZipMethod::Deflated, 4, &b"more"[..]).await.unwrap();
+        let entries = read_back(writer.finish().await.unwrap());
+
+        assert_eq!(entries[0], ("large.bin".to_string(), b"data".to_vec()));
+        assert_eq!(entries[1], ("small.bin".to_string(), b"more".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn many_entries_use_the_zip64_end_record() {
+        let now = SystemTime::now();
+        let mut writer = ZipWriter::new(Vec::new());
+        for i in 0..0x1_0000 {
+            writer.add_directory(&format!("{i}/"), now).await.unwrap();
+        }
+        let bytes = writer.finish().await.unwrap();
+        let archive = zip::ZipArchive::new(Cursor::new(bytes)).expect("valid zip");
+        assert_eq!(archive.len(), 0x1_0000);
+    }
+
+    #[test]
+    fn version_needed_follows_the_local_header() {
+        let record = |zip64, header_offset| CentralRecord {
+            name: "a".to_string(),
+            method: ZipMethod::Stored,
+            flags: FILE_ENTRY_FLAGS,
+            dos_time: 0,
+            dos_date: 0,
+            crc: 0,
+            compressed_size: 10,
+            uncompressed_size: 10,
+            header_offset,
+            is_dir: false,
+            zip64,
+        };
+        let version_needed = |record| {
+            let central = central_directory(&[record], 0);
+            u16::from_le_bytes([central[6], central[7]])
+        };
+        assert_eq!(version_needed(record(false, 0)), VERSION_DEFAULT);
+        assert_eq!(version_needed(record(true, 0)), VERSION_ZIP64);
+        assert_eq!(version_needed(record(false, U32_SENTINEL)), VERSION_ZIP64);
+    }
+
+    #[tokio::test]
+    async fn overlong_names_are_refused() {
+        let name = "a".repeat(u16::MAX as usize + 1);
+        let mut writer = ZipWriter::new(Vec::new());
+        let err = writer.add_bytes(&name, SystemTime::now(), b"x").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,113 @@
+use axum::http::HeaderMap;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// Header carrying the expected hex SHA-256 of an upload.
+pub const SHA256_HEADER: &str = "x-content-sha256";
+
+/// Digests a client sent along with an upload, as raw bytes.
+#[derive(Debug, Clone, Default)]
+pub struct ExpectedDigests {
+    pub md5: Option<Vec<u8>>,
+    pub sha256: Option<Vec<u8>>,
+}
+
+impl ExpectedDigests {
+    /// Reads `X-Content-SHA256` (hex), `Content-MD5` and the `md5` / `sha-256`
+    /// entries of `Digest` (RFC 3230). Other algorithms in `Digest` are ignored.
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self, String> {
+        let decode = |name: &str, value: &str, len: usize| match BASE64.decode(value.trim()) {
+            Ok(bytes) if bytes.len() == len => Ok(bytes),
+            _ => Err(format!("Invalid {name} value")),
+        };
+
+        let mut expected = ExpectedDigests::default();
+        if let Some(value) = headers.get("content-md5") {
+            let value = value.to_str().map_err(|_| "Invalid Content-MD5 header".to_string())?;
+            expected.md5 = Some(decode("Content-MD5", value, 16)?);
+        }
+        for value in headers.get_all("digest") {
+            let value = value.to_str().map_err(|_| "Invalid Digest header".to_string())?;
+            for item in value.split(',') {
+                let Some((algorithm, encoded)) = item.split_once('=') else { continue };
+                match algorithm.trim().to_ascii_lowercase().as_str() {
+                    "md5" => expected.md5 = Some(decode("Digest md5", encoded, 16)?),
+                    "sha-256" => expected.sha256 = Some(decode("Digest sha-256", encoded, 32)?),
+                    _ => {}
+                }
+            }
+        }
+        if let Some(value) = headers.get(SHA256_HEADER) {
+            expected.sha256 = Some(parse_sha256(value.to_str().unwrap_or_default())?);
+        }
+        Ok(expected)
+    }
+}
+
+/// Parses a hex SHA-256 as sent in a header or form field.
+pub fn parse_sha256(hex: &str) -> Result<Vec<u8>, String> {
+    let hex = hex.trim();
+    let bytes: Option<Vec<u8>> = (hex.len() == 64)
+        .then(|| {
+            (0..hex.len())
+                .step_by(2)
+                .map(|i| hex.get(i..i + 2).and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+                .collect()
+        })
+        .flatten();
+    bytes.ok_or_else(|| "Invalid SHA-256 value; expected 64 hex digits".to_string())
+}
+
+/// Computes digests of data as it streams through: always SHA-256, which is
+/// recorded for the file, and MD5 only when the client sent one to check.
+pub struct Digests {
+    md5: Option<Md5>,
+    sha256: Sha256,
+}
+
+impl Digests {
+    pub fn new(expected: &ExpectedDigests) -> Self {
+        Self {
+            md5: expected.md5.as_ref().map(|_| Md5::new()),
+            sha256: Sha256::new(),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        if let Some(md5) = &mut self.md5 {
+            md5.update(data);
+        }
+        self.sha256.update(data);
+    }
+
+    /// Compares the computed digests with `expected` and returns the hex SHA-256,
+    /// or the name of the first digest that differs.
+    pub fn finish(self, expected: &ExpectedDigests) -> Result<String, &'static str> {
+        if let (Some(md5), Some(want)) = (self.md5, &expected.md5) {
+            if md5.finalize().as_slice() != want.as_slice() {
+                return Err("MD5");
+            }
+        }
+        let sha256 = self.sha256.finalize();
+        if expected.sha256.as_ref().is_some_and(|want| sha256.as_slice() != want.as_slice()) {
+            return Err("SHA-256");
+        }
+        Ok(sha256.iter().map(|b| format!("{b:02x}")).collect())
+    }
+}
+
+/// Hex SHA-256 of an in-memory body.
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Hex SHA-256 of a file on disk, read in full. Blocking.
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().iter().map(|b| format!("{b:02x}")).collect())
+}
@@ -0,0 +1,118 @@
+use axum::http::HeaderMap;
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast::Sender, Mutex};
+use tracing::{error, info};
+use super::file_tree::FileEntry;
+use super::file_utils::{find_entry, safe_path};
+use super::folder_policy::policy_for;
+use super::metadata;
+use super::tree_watcher::{TreeChanges, TreeNotice};
+use super::versions::remove_history;
+
+/// How often expired files are looked for.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Header carrying the time-to-live of an upload (see [`parse_ttl`]).
+pub const TTL_HEADER: &str = "x-expires-in";
+
+/// Parses a time-to-live such as `90` (seconds), `30m`, `12h` or `7d`.
+/// `0` means the file doesn't expire.
+pub fn parse_ttl(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (number, unit) = value.split_at(value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len()));
+    let seconds = match unit.trim() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("Invalid TTL '{value}'; use seconds or a number with s, m, h or d")),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(seconds))
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("Invalid TTL '{value}'; use seconds or a number with s, m, h or d"))
+}
+
+/// Reads the [`TTL_HEADER`] of a request, if it has one.
+pub fn ttl_from_headers(headers: &HeaderMap) -> Result<Option<Duration>, String> {
+    let Some(value) = headers.get(TTL_HEADER) else {
+        return Ok(None);
+    };
+    let value = value.to_str().map_err(|_| "Invalid X-Expires-In header".to_string())?;
+    parse_ttl(value).map(Some)
+}
+
+/// When a file written to `rel` now expires: after the `requested` TTL if one was
+/// given, else after the `ttl_hours` of its folder's policy. `None` if it doesn't.
+pub fn expiry_for(rel: &str, requested: Option<Duration>) -> Option<DateTime<Utc>> {
+    let ttl = requested.unwrap_or_else(|| {
+        let parent = rel.rsplit_once('/').map_or("", |(parent, _)| parent);
+        Duration::from_secs(policy_for(parent).ttl_hours().saturating_mul(60 * 60))
+    });
+    if ttl.is_zero() {
+        return None;
+    }
+    Utc::now().checked_add_signed(chrono::Duration::from_std(ttl).ok()?)
+}
+
+/// Records when the file just written to `rel` expires, replacing what was recorded
+/// for an earlier file there.
+pub fn record(rel: &str, requested: Option<Duration>) {
+    let expires_at = expiry_for(rel, requested).map(|at| at.to_rfc3339_opts(SecondsFormat::Secs, true));
+    metadata::record_expiry(rel, expires_at);
+}
+
+/// Deletes the files whose expiry has passed, along with their previous versions.
+/// Expired files don't go to the trash. Returns the paths that were deleted.
+pub async fn sweep(file_tree: &Mutex<Option<FileEntry>>) -> Vec<String> {
+    let mut deleted = Vec::new();
+    for rel in metadata::expired(Utc::now()) {
+        // Wait for anyone reading or replacing the file to finish
+        let entry = {
+            let mut tree_guard = file_tree.lock().await;
+            tree_guard.as_mut().and_then(|tree| find_entry(tree, &rel))
+        };
+        let _guard = match &entry {
+            Some(entry) => Some(entry.lock.lock().await),
+            None => None,
+        };
+        let Ok(path) = safe_path(&rel) else { continue };
+        let removed = match tokio::fs::symlink_metadata(&path).await {
+            Ok(meta) if meta.is_dir() => Ok(false),
+            Ok(_) => tokio::fs::remove_file(&path).await.and_then(|()| remove_history(&rel)).map(|()| true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        };
+        match removed {
+            Ok(removed) => {
+                metadata::remove(&rel);
+                if let Some(tree) = file_tree.lock().await.as_mut() {
+                    tree.detach(&rel);
+                }
+                if removed {
+                    info!("⌛ Deleted expired '{rel}'");
+                    deleted.push(rel);
+                }
+            }
+            Err(e) => error!("Failed to delete expired '{rel}': {e}"),
+        }
+    }
+    deleted
+}
+
+/// Deletes expired files every minute.
+pub async fn run_sweeper(file_tree: Arc<Mutex<Option<FileEntry>>>, tree_tx: Sender<TreeNotice>) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let deleted = sweep(&file_tree).await;
+        if !deleted.is_empty() {
+            let _ = tree_tx.send(TreeNotice::Changed(TreeChanges::removed(deleted)));
+        }
+    }
+}
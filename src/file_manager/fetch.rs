@@ -0,0 +1,573 @@
+use futures_util::StreamExt;
+use percent_encoding::percent_decode_str;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::{header, redirect, StatusCode, Url};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, SeekFrom};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tracing::warn;
+use super::checksum::{Digests, ExpectedDigests};
+use super::jobs::Job;
+use super::quota::Allowance;
+use crate::utils::config::Config;
+
+/// Times a broken download is resumed before the job gives up.
+const MAX_ATTEMPTS: u32 = 5;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// A connection that sends nothing for this long is dropped and resumed.
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+const MAX_REDIRECTS: usize = 10;
+
+/// A file downloaded into the staging folder.
+#[derive(Debug)]
+pub struct Download {
+    pub size: u64,
+    /// Hex SHA-256 of the content.
+    pub sha256: String,
+    /// File name the server suggested in `Content-Disposition`, if any.
+    pub suggested_name: Option<String>,
+}
+
+/// Checks that `url` is an absolute `http` or `https` URL, and not one naming a
+/// loopback or private address (see [`check_url`]).
+pub fn parse_url(url: &str) -> Result<Url, String> {
+    let url = Url::parse(url.trim()).map_err(|e| format!("Invalid URL: {e}"))?;
+    check_url(&url, Config::from_env().fetch_allow_hosts()).map_err(|e| e.to_string())?;
+    Ok(url)
+}
+
+/// A fetch that would reach the server's own network.
+#[derive(Debug)]
+pub struct BlockedHost(String);
+
+impl fmt::Display for BlockedHost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for BlockedHost {}
+
+/// Refuses URLs that aren't `http` or `https`, and IP addresses that aren't public
+/// unless the host is in `allow_hosts`. Names are checked when they are resolved.
+fn check_url(url: &Url, allow_hosts: &[String]) -> Result<(), BlockedHost> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(BlockedHost(format!("Unsupported URL scheme '{}'; use http or https", url.scheme())));
+    }
+    let host = url.host_str().unwrap_or_default().trim_matches(['[', ']']);
+    let Ok(ip) = host.parse::<IpAddr>() else {
+        return Ok(());
+    };
+    if is_public(ip) || allow_hosts.iter().any(|allowed| allowed == host) {
+        return Ok(());
+    }
+    Err(BlockedHost(format!("{ip} is not a public address; add it to FETCH_ALLOW_HOSTS to fetch from it")))
+}
+
+/// Whether `ip` is reachable on the internet, rather than the server itself or its
+/// local network (loopback, private, link-local, unique-local, unspecified, ...).
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || first & 0xfe00 == 0xfc00 // unique local, fc00::/7
+                    || first & 0xffc0 == 0xfe80) // link-local, fe80::/10
+            }
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || a == 0
+        || (a == 100 && b & 0xc0 == 64)) // shared address space, 100.64.0.0/10
+}
+
+/// Resolves host names for fetches, leaving out addresses that aren't public
+/// unless the name is in `FETCH_ALLOW_HOSTS`. Every connection goes through it,
+/// including those for redirects, so a name can't be re-pointed after the check.
+struct GuardedResolver {
+    allow_hosts: Vec<String>,
+}
+
+impl Resolve for GuardedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_lowercase();
+        let allowed = self.allow_hosts.contains(&host);
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|addr| allowed || is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                let message = format!("'{host}' has no public address; add it to FETCH_ALLOW_HOSTS to fetch from it");
+                return Err(Box::new(BlockedHost(message)) as Box<dyn Error + Send + Sync>);
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// HTTP client for fetches: addresses are checked on every connection, redirects
+/// included, and system proxies are not used since they would resolve names themselves.
+fn client() -> io::Result<reqwest::Client> {
+    let allow_hosts = Config::from_env().fetch_allow_hosts().to_vec();
+    let redirect_allow = allow_hosts.clone();
+    let redirects = redirect::Policy::custom(move |attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            return attempt.error(BlockedHost("Too many redirects".to_string()));
+        }
+        match check_url(attempt.url(), &redirect_allow) {
+            Ok(()) => attempt.follow(),
+            Err(blocked) => attempt.error(blocked),
+        }
+    });
+    reqwest::Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .read_timeout(READ_TIMEOUT)
+        .no_proxy()
+        .redirect(redirects)
+        .dns_resolver(Arc::new(GuardedResolver { allow_hosts }))
+        .build()
+        .map_err(io::Error::other)
+}
+
+/// The [`BlockedHost`] behind a request error, if that is what stopped it.
+fn blocked(error: &reqwest::Error) -> Option<&BlockedHost> {
+    let mut source = error.source();
+    while let Some(error) = source {
+        if let Some(blocked) = error.downcast_ref::<BlockedHost>() {
+            return Some(blocked);
+        }
+        source = error.source();
+    }
+    None
+}
+
+/// Name for a file fetched from `url`: its last path segment, or the host.
+pub fn name_from_url(url: &Url) -> String {
+    url.path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .map(|segment| percent_decode_str(segment).decode_utf8_lossy().into_owned())
+        .filter(|name| !name.is_empty())
+        .or_else(|| url.host_str().map(str::to_string))
+        .unwrap_or_else(|| "download".to_string())
+}
+
+/// Downloads `url` into `tmp`, reporting progress through `job`.
+///
+/// A connection that breaks off is resumed with a `Range` request after a short
+/// pause, up to [`MAX_ATTEMPTS`] times. The request carries `If-Range`, and the
+/// answer's `Content-Range` must start where the file left off; otherwise, or if
+/// the server sent no `ETag` or `Last-Modified`, the file is fetched again from the start. Hosts that aren't public are refused with
+/// `ErrorKind::PermissionDenied`. Content beyond `max_bytes` or beyond what `allowance`
+/// leaves fails the download with `ErrorKind::QuotaExceeded`; cancelling the job
+/// fails it with `ErrorKind::Interrupted`.
+pub async fn download(url: &Url, tmp: &Path, max_bytes: u64, allowance: &mut Allowance, job: &Job) -> io::Result<Download> {
+    let client = client()?;
+    let mut file = tokio::fs::File::create(tmp).await?;
+    let mut digests = Digests::new(&ExpectedDigests::default());
+    let mut received = 0u64;
+    let mut validator: Option<String> = None;
+    let mut suggested_name = None;
+    let mut attempts = 0;
+
+    loop {
+        attempts += 1;
+        let mut request = client.get(url.clone());
+        // Without a validator there is no telling whether the rest belongs to the same file
+        if let Some(validator) = validator.as_ref().filter(|_| received > 0) {
+            request = request
+                .header(header::RANGE, format!("bytes={received}-"))
+                .header(header::IF_RANGE, validator);
+        }
+        let response = tokio::select! {
+            response = request.send() => response,
+            () = job.cancelled() => return Err(io::Error::new(io::ErrorKind::Interrupted, "Cancelled")),
+        };
+        let response = match response {
+            Ok(response) => response,
+            Err(e) => {
+                if let Some(blocked) = blocked(&e) {
+                    return Err(io::Error::new(io::ErrorKind::PermissionDenied, blocked.to_string()));
+                }
+                if attempts >= MAX_ATTEMPTS {
+                    return Err(io::Error::other(format!("Failed to fetch: {e}")));
+                }
+                warn!("Fetching {url} failed, retrying: {e}");
+                backoff(attempts, job).await?;
+                continue;
+            }
+        };
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(io::Error::other(format!("The server answered {status}")));
+        }
+        let resumed = status == StatusCode::PARTIAL_CONTENT && range_start(response.headers()) == Some(received);
+        if received > 0 && !resumed {
+            // The server ignored the range, the file changed or the range is not the one
+            // asked for: start over
+            received = 0;
+            file.set_len(0).await?;
+            file.seek(SeekFrom::Start(0)).await?;
+            digests = Digests::new(&ExpectedDigests::default());
+            allowance.release();
+            job.set_bytes_done(0);
+            if status == StatusCode::PARTIAL_CONTENT {
+                warn!("Fetching {url} resumed at the wrong offset, starting over");
+                if attempts >= MAX_ATTEMPTS {
+                    return Err(io::Error::other("The server doesn't resume downloads correctly"));
+                }
+                continue;
+            }
+        }
+        if received == 0 {
+            validator = resume_validator(response.headers());
+            suggested_name = response
+                .headers()
+                .get(header::CONTENT_DISPOSITION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(disposition_filename);
+        }
+        let total = response.content_length().map(|len| len + received);
+        if let Some(total) = total {
+            if total > max_bytes {
+                return Err(too_large(max_bytes));
+            }
+            if total > allowance.bytes {
+                return Err(io::Error::new(io::ErrorKind::QuotaExceeded, allowance.exceeded()));
+            }
+            job.set_totals(total, 1);
+        }
+
+        let mut body = response.bytes_stream();
+        let broke_off = loop {
+            let chunk = tokio::select! {
+                chunk = body.next() => chunk,
+                () = job.cancelled() => return Err(io::Error::new(io::ErrorKind::Interrupted, "Cancelled")),
+            };
+            match chunk {
+                None => break None,
+                Some(Err(e)) => break Some(e.to_string()),
+                Some(Ok(chunk)) => {
+                    received += chunk.len() as u64;
+                    if received > max_bytes {
+                        return Err(too_large(max_bytes));
+                    }
+                    allowance.take(chunk.len() as u64)?;
+                    digests.update(&chunk);
+                    file.write_all(&chunk).await?;
+                    job.add_bytes(chunk.len() as u64);
+                }
+            }
+        };
+        let short = total.is_some_and(|total| received < total);
+        match broke_off.or_else(|| short.then(|| "connection closed early".to_string())) {
+            None => break,
+            Some(e) if attempts < MAX_ATTEMPTS => {
+                warn!("Fetching {url} broke off after {received} bytes, resuming: {e}");
+                file.flush().await?;
+                backoff(attempts, job).await?;
+            }
+            Some(e) => return Err(io::Error::other(format!("Download broke off: {e}"))),
+        }
+    }
+    file.flush().await?;
+
+    let sha256 = digests.finish(&ExpectedDigests::default()).map_err(io::Error::other)?;
+    Ok(Download { size: received, sha256, suggested_name })
+}
+
+/// Waits a little longer after each failed attempt, unless the job is cancelled.
+async fn backoff(attempts: u32, job: &Job) -> io::Result<()> {
+    tokio::select! {
+        () = tokio::time::sleep(Duration::from_secs(attempts.into())) => Ok(()),
+        () = job.cancelled() => Err(io::Error::new(io::ErrorKind::Interrupted, "Cancelled")),
+    }
+}
+
+/// Where the body of a `206 Partial Content` starts, from `Content-Range: bytes start-end/total`.
+fn range_start(headers: &header::HeaderMap) -> Option<u64> {
+    let value = headers.get(header::CONTENT_RANGE)?.to_str().ok()?;
+    let (start, _) = value.trim().strip_prefix("bytes ")?.split_once('-')?;
+    start.trim().parse().ok()
+}
+
+fn too_large(max_bytes: u64) -> io::Error {
+    io::Error::new(io::ErrorKind::QuotaExceeded, format!("File is larger than the {max_bytes} bytes allowed for fetches"))
+}
+
+/// What to send in `If-Range` when resuming: a strong `ETag`, or else `Last-Modified`.
+fn resume_validator(headers: &header::HeaderMap) -> Option<String> {
+    let etag = headers
+        .get(header::ETAG)
+        .and_then(|value| value.to_str().ok())
+        .filter(|etag| !etag.starts_with("W/"));
+    etag.or_else(|| headers.get(header::LAST_MODIFIED)?.to_str().ok())
+        .map(str::to_string)
+}
+
+/// The file name in a `Content-Disposition` header, preferring `filename*`.
+fn disposition_filename(value: &str) -> Option<String> {
+    let params = || value.split(';').filter_map(|param| param.trim().split_once('='));
+    let extended = params()
+        .find(|(key, _)| key.eq_ignore_ascii_case("filename*"))
+        .and_then(|(_, value)| value.split_once("''"))
+        .map(|(_, encoded)| percent_decode_str(encoded).decode_utf8_lossy().into_owned());
+    extended
+        .or_else(|| {
+            params()
+                .find(|(key, _)| key.eq_ignore_ascii_case("filename"))
+                .map(|(_, value)| value.trim_matches('"').to_string())
+        })
+        .filter(|name| !name.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_manager::checksum::sha256_hex;
+    use crate::file_manager::jobs::JobRegistry;
+    use crate::file_manager::quota::allowance_blocking;
+    use crate::file_manager::test_support::share_dir;
+    use axum::{body::Body, http::HeaderMap, response::Response, routing::get, Router};
+    use bytes::Bytes;
+    use futures_util::stream;
+    use std::sync::Mutex;
+
+    /// Content large enough to be cut in half, with no repeating pattern at the cut.
+    fn content(seed: u8) -> Vec<u8> {
+        (0..4096u32).map(|i| (i.wrapping_mul(31) as u8) ^ seed).collect()
+    }
+
+    /// Serves `router` on a free local port; fetches in these tests may reach it.
+    async fn serve(router: Router) -> Url {
+        std::env::set_var("FETCH_ALLOW_HOSTS", "127.0.0.1");
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        Url::parse(&format!("http://{addr}/file")).unwrap()
+    }
+
+    /// A `200` announcing all of `body` that breaks off after the first half.
+    fn broken_response(body: &[u8], etag: &str) -> Response {
+        let half = Bytes::copy_from_slice(&body[..body.len() / 2]);
+        // Let the first half reach the client before the connection is dropped
+        let lost = stream::once(async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Err(io::Error::other("connection lost"))
+        });
+        let chunks = stream::iter(vec![Ok(half)]).chain(lost);
+        Response::builder()
+            .header(header::CONTENT_LENGTH, body.len())
+            .header(header::ETAG, etag)
+            .body(Body::from_stream(chunks))
+            .unwrap()
+    }
+
+    fn full_response(body: &[u8], etag: &str) -> Response {
+        Response::builder()
+            .header(header::ETAG, etag)
+            .body(Body::from(body.to_vec()))
+            .unwrap()
+    }
+
+    fn partial_response(body: &[u8], start: usize, etag: &str) -> Response {
+        Response::builder()
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(header::CONTENT_RANGE, format!("bytes {start}-{}/{}", body.len() - 1, body.len()))
+            .header(header::ETAG, etag)
+            .body(Body::from(body[start..].to_vec()))
+            .unwrap()
+    }
+
+    /// Runs a download of `url` into a fresh staging file and returns the result and the file.
+    async fn fetch(url: &Url, name: &str, max_bytes: u64) -> (io::Result<Download>, Vec<u8>) {
+        let dir = share_dir().join("fetch-tests");
+        std::fs::create_dir_all(&dir).unwrap();
+        let tmp = dir.join(name);
+        let jobs = JobRegistry::default();
+        let job = jobs.start("fetch", name.to_string());
+        let mut allowance = allowance_blocking(None, None, true, &tokio::sync::Mutex::new(None)).unwrap();
+        let result = download(url, &tmp, max_bytes, &mut allowance, &job).await;
+        (result, std::fs::read(&tmp).unwrap_or_default())
+    }
+
+    /// Records the `Range` header of every request.
+    type Requests = Arc<Mutex<Vec<Option<String>>>>;
+
+    fn record(requests: &Requests, headers: &HeaderMap) -> usize {
+        let range = headers.get(header::RANGE).map(|v| v.to_str().unwrap().to_string());
+        let mut requests = requests.lock().unwrap();
+        requests.push(range);
+        requests.len()
+    }
+
+    #[tokio::test]
+    async fn a_broken_download_is_resumed() {
+        let body = content(1);
+        let requests = Requests::default();
+        let router = Router::new().route("/file", get({
+            let (body, requests) = (body.clone(), requests.clone());
+            move |headers: HeaderMap| async move {
+                match record(&requests, &headers) {
+                    1 => broken_response(&body, "\"v1\""),
+                    _ if headers.get(header::IF_RANGE).is_some_and(|v| v == "\"v1\"") => {
+                        partial_response(&body, body.len() / 2, "\"v1\"")
+                    }
+                    _ => full_response(&body, "\"v1\""),
+                }
+            }
+        }));
+        let url = serve(router).await;
+
+        let (result, saved) = fetch(&url, "resume.part", u64::MAX).await;
+        let download = result.unwrap();
+        assert_eq!(saved, body);
+        assert_eq!(download.size, body.len() as u64);
+        assert_eq!(download.sha256, sha256_hex(&body));
+        let requests = requests.lock().unwrap();
+        assert_eq!(*requests, vec![None, Some(format!("bytes={}-", body.len() / 2))]);
+    }
+
+    #[tokio::test]
+    async fn a_file_changed_meanwhile_is_fetched_from_the_start() {
+        let (old, new) = (content(2), content(3));
+        let requests = Requests::default();
+        let router = Router::new().route("/file", get({
+            let (old, new, requests) = (old.clone(), new.clone(), requests.clone());
+            move |headers: HeaderMap| async move {
+                match record(&requests, &headers) {
+                    1 => broken_response(&old, "\"v1\""),
+                    // If-Range names the old version, so the whole new file is sent
+                    _ => full_response(&new, "\"v2\""),
+                }
+            }
+        }));
+        let url = serve(router).await;
+
+        let (result, saved) = fetch(&url, "changed.part", u64::MAX).await;
+        assert_eq!(result.unwrap().sha256, sha256_hex(&new));
+        assert_eq!(saved, new);
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].is_some(), "the second request should try to resume");
+    }
+
+    #[tokio::test]
+    async fn a_resume_at_the_wrong_offset_starts_over() {
+        let body = content(4);
+        let requests = Requests::default();
+        let router = Router::new().route("/file", get({
+            let (body, requests) = (body.clone(), requests.clone());
+            move |headers: HeaderMap| async move {
+                match record(&requests, &headers) {
+                    1 => broken_response(&body, "\"v1\""),
+                    // Claims to resume, but sends the file from its first byte
+                    2 => partial_response(&body, 0, "\"v1\""),
+                    _ => full_response(&body, "\"v1\""),
+                }
+            }
+        }));
+        let url = serve(router).await;
+
+        let (result, saved) = fetch(&url, "offset.part", u64::MAX).await;
+        result.unwrap();
+        assert_eq!(saved, body);
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[2], None);
+    }
+
+    #[tokio::test]
+    async fn downloads_stop_at_the_size_cap() {
+        let body = content(5);
+        let router = Router::new()
+            .route("/file", get({
+                let body = body.clone();
+                move || async move { full_response(&body, "\"v1\"") }
+            }))
+            .route("/chunked", get({
+                let body = body.clone();
+                move || async move {
+                    // No Content-Length, so the cap is only noticed while receiving
+                    let chunks: Vec<io::Result<Bytes>> = body.chunks(512).map(|c| Ok(Bytes::copy_from_slice(c))).collect();
+                    Body::from_stream(stream::iter(chunks))
+                }
+            }));
+        let url = serve(router).await;
+
+        let (result, _) = fetch(&url, "cap.part", 1000).await;
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::QuotaExceeded);
+        let (result, _) = fetch(&url.join("chunked").unwrap(), "cap-chunked.part", 1000).await;
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::QuotaExceeded);
+    }
+
+    #[tokio::test]
+    async fn cancelling_the_job_stops_the_download() {
+        let router = Router::new().route("/file", get(|| async {
+            // One chunk, then nothing until the client gives up
+            let first = stream::iter(vec![Ok::<_, io::Error>(Bytes::from_static(b"partial"))]);
+            Body::from_stream(first.chain(stream::pending()))
+        }));
+        let url = serve(router).await;
+
+        let dir = share_dir().join("fetch-tests");
+        std::fs::create_dir_all(&dir).unwrap();
+        let jobs = JobRegistry::default();
+        let job = jobs.start("fetch", "cancel".to_string());
+        let id = job.id();
+        let canceller = tokio::spawn(async move {
+            while jobs.get(id).is_some_and(|job| job.bytes_done == 0) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            jobs.cancel(id);
+        });
+        let mut allowance = allowance_blocking(None, None, true, &tokio::sync::Mutex::new(None)).unwrap();
+        let result = tokio::time::timeout(
+            Duration::from_secs(10),
+            download(&url, &dir.join("cancel.part"), u64::MAX, &mut allowance, &job),
+        )
+        .await
+        .expect("the download should stop when cancelled");
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Interrupted);
+        canceller.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn local_addresses_are_refused_unless_allowed() {
+        let allow = ["10.1.2.3".to_string()];
+        for url in ["http://127.0.0.1/", "http://[::1]/", "http://192.168.0.1/", "http://169.254.169.254/", "http://[fd00::1]/", "http://0.0.0.0/", "http://[::ffff:127.0.0.1]/"] {
+            assert!(check_url(&Url::parse(url).unwrap(), &allow).is_err(), "{url} was allowed");
+        }
+        for url in ["http://10.1.2.3/", "https://93.184.215.14/", "http://example.com/"] {
+            assert!(check_url(&Url::parse(url).unwrap(), &allow).is_ok(), "{url} was refused");
+        }
+        assert!(check_url(&Url::parse("ftp://93.184.215.14/").unwrap(), &allow).is_err());
+
+        // Names are checked once resolved
+        let resolver = GuardedResolver { allow_hosts: Vec::new() };
+        let name: Name = "localhost".parse().unwrap();
+        assert!(resolver.resolve(name).await.is_err());
+        let resolver = GuardedResolver { allow_hosts: vec!["localhost".to_string()] };
+        assert!(resolver.resolve("localhost".parse().unwrap()).await.is_ok());
+    }
+}
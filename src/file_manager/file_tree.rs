@@ -1,9 +1,12 @@
 use serde::Serialize;
+use std::collections::HashMap;
 use std::fs;
 use tokio::sync::Mutex;
 use std::sync::Arc;
 use std::path::Path;
 use super::file_utils::*;
+use super::metadata;
+use super::transfer::is_part_file;
 
 /// Represents a file or directory in the media tree.
 #[derive(Debug, Serialize, Clone)]
@@ -16,6 +19,12 @@ pub struct FileEntry {
     pub modified: Option<String>,
     pub children: Option<Vec<FileEntry>>,
     pub is_browser_supported: bool,
+    /// Hex SHA-256 of the content, when the server has recorded one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// When the file is deleted for having expired (RFC 3339), if it does.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
     #[serde(skip_serializing)] // Used for locking, not sent to client
     pub lock: Arc<Mutex<()>>,
     in_use : bool,
@@ -27,6 +36,7 @@ pub enum FileType {
     Video,
     Audio,
     Image,
+    Archive,
     Other,
 }
 
@@ -35,13 +45,17 @@ pub fn scan_dir<P: AsRef<Path>>(root_dir: &Path, curr_path: P) -> Option<FileEnt
     let is_browser_supported = is_browser_supported(curr_path);
     let name = curr_path.file_name()?.to_str()?.to_string();
 
-    // Skip Windows alternate data streams and similar artifacts
-    if name.contains("Zone.Identifier") || name == ".gitkeep" {
+    // Skip Windows alternate data streams, similar artifacts and files being written by the server
+    if name.contains("Zone.Identifier") || name == ".gitkeep" || is_part_file(&name) {
         return None;
     }
 
     // Compute the curr_path relative to the root directory for API/UI use
     let rel_path = curr_path.strip_prefix(root_dir).unwrap_or(curr_path);
+    // The server's own data (trash, ...) isn't part of the share
+    if rel_path.starts_with(DATA_DIR) {
+        return None;
+    }
     let path_str = rel_path.display().to_string();
 
     
@@ -73,11 +87,15 @@ pub fn scan_dir<P: AsRef<Path>>(root_dir: &Path, curr_path: P) -> Option<FileEnt
             size,
             modified,
             is_browser_supported,
+            hash: None,
+            expires_at: None,
             lock: Arc::new(Mutex::new(())),
             in_use: false,
         })
     } else {
         // If file, just create the entry
+        let hash = metadata::hash_of(&path_str, &metadata);
+        let expires_at = metadata::expiry_of(&path_str);
         Some(FileEntry {
             name,
             path: path_str,
@@ -87,6 +105,8 @@ pub fn scan_dir<P: AsRef<Path>>(root_dir: &Path, curr_path: P) -> Option<FileEnt
             size,
             modified,
             is_browser_supported,
+            hash,
+            expires_at,
             lock: Arc::new(Mutex::new(())), // Per-entry lock for concurrency
             in_use: false,
         })  
@@ -94,4 +114,94 @@ pub fn scan_dir<P: AsRef<Path>>(root_dir: &Path, curr_path: P) -> Option<FileEnt
     
 }
 
+impl FileEntry {
+    /// Returns the locks of this entry and everything below it, sorted by path,
+    /// which is the order they must be acquired in to avoid deadlocks.
+    pub fn subtree_locks(&self) -> Vec<(String, Arc<Mutex<()>>)> {
+        let mut locks = vec![(self.path.clone(), self.lock.clone())];
+        for child in self.children.iter().flatten() {
+            locks.extend(child.subtree_locks());
+        }
+        locks.sort_by(|a, b| a.0.cmp(&b.0));
+        locks
+    }
+
+    /// The entry at `path`, for changing it in place.
+    pub fn entry_mut(&mut self, path: &str) -> Option<&mut FileEntry> {
+        if self.path == path {
+            return Some(self);
+        }
+        self.children
+            .as_mut()?
+            .iter_mut()
+            .find(|child| child.path == path || path.starts_with(&format!("{}/", child.path)))?
+            .entry_mut(path)
+    }
 
+    /// Takes over the locks of the entries `old` has at the same paths, so that a
+    /// rescanned entry stays in sync with whoever holds the previous one.
+    pub fn keep_locks(&mut self, old: &FileEntry) {
+        if self.path != old.path {
+            return;
+        }
+        self.lock = old.lock.clone();
+        let Some(old_children) = &old.children else { return };
+        let old_children: HashMap<&str, &FileEntry> =
+            old_children.iter().map(|child| (child.path.as_str(), child)).collect();
+        for child in self.children.iter_mut().flatten() {
+            if let Some(old_child) = old_children.get(child.path.as_str()) {
+                child.keep_locks(old_child);
+            }
+        }
+    }
+
+    /// Removes the entry at `path` from the tree and returns it.
+    pub fn detach(&mut self, path: &str) -> Option<FileEntry> {
+        let children = self.children.as_mut()?;
+        if let Some(index) = children.iter().position(|child| child.path == path) {
+            return Some(children.remove(index));
+        }
+        children
+            .iter_mut()
+            .find(|child| path.starts_with(&format!("{}/", child.path)))?
+            .detach(path)
+    }
+
+    /// Inserts `entry` as a child of the folder at `parent`, replacing a child with
+    /// the same path. Returns false when `parent` is not a folder in the tree.
+    pub fn attach(&mut self, parent: &str, entry: FileEntry) -> bool {
+        let Some(children) = self.children.as_mut() else {
+            return false;
+        };
+        if self.path == parent {
+            children.retain(|child| child.path != entry.path);
+            children.push(entry);
+            return true;
+        }
+        match children
+            .iter_mut()
+            .find(|child| child.path == parent || parent.starts_with(&format!("{}/", child.path)))
+        {
+            Some(child) => child.attach(parent, entry),
+            None => false,
+        }
+    }
+
+    /// Moves this entry from `from` to `to`: renames it and rewrites the paths of
+    /// everything below it. Locks are kept, so holders of the old entry stay in sync.
+    pub fn rebase(&mut self, from: &str, to: &str) {
+        if self.path == from {
+            self.name = to.rsplit('/').next().unwrap_or(to).to_string();
+            if !self.is_dir {
+                self.file_type = detect_file_type(&self.name, false);
+                self.is_browser_supported = is_browser_supported(&self.name);
+            }
+        }
+        if let Some(rest) = self.path.strip_prefix(from) {
+            self.path = format!("{to}{rest}");
+        }
+        for child in self.children.iter_mut().flatten() {
+            child.rebase(from, to);
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use std::fmt;
+use std::io::{self, Read};
+use std::path::Path;
+use super::archive::ArchiveKind;
+use super::folder_policy::policy_for;
+
+/// How much of a file's start [`sniff`] looks at.
+pub const SNIFF_LEN: usize = 512;
+
+/// Groups of file types that folder policies can allow or deny by name,
+/// next to plain extensions. `media` stands for images, video and audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TypeGroup {
+    Image,
+    Video,
+    Audio,
+    Document,
+    Archive,
+    Executable,
+    Script,
+}
+
+impl TypeGroup {
+    pub fn name(self) -> &'static str {
+        match self {
+            TypeGroup::Image => "image",
+            TypeGroup::Video => "video",
+            TypeGroup::Audio => "audio",
+            TypeGroup::Document => "document",
+            TypeGroup::Archive => "archive",
+            TypeGroup::Executable => "executable",
+            TypeGroup::Script => "script",
+        }
+    }
+
+    /// Whether a policy entry (a group name or `media`) covers this group.
+    fn listed_as(self, entry: &str) -> bool {
+        entry == self.name()
+            || (entry == "media" && matches!(self, TypeGroup::Image | TypeGroup::Video | TypeGroup::Audio))
+    }
+}
+
+const EXECUTABLE_EXTENSIONS: &[&str] = &[
+    "exe", "dll", "msi", "com", "scr", "cpl", "sys", "app", "apk", "jar", "so", "dylib", "deb", "rpm",
+    "appimage", "elf", "bin",
+];
+const SCRIPT_EXTENSIONS: &[&str] = &[
+    "sh", "bash", "zsh", "fish", "bat", "cmd", "ps1", "psm1", "vbs", "vbe", "js", "jse", "mjs", "wsf", "wsh",
+    "hta", "py", "pyw", "pl", "rb", "php", "lua", "command",
+];
+/// Text formats that can start with `MZ` or `#!` without being executables or scripts.
+const PLAIN_TEXT_EXTENSIONS: &[&str] = &[
+    "txt", "text", "md", "markdown", "rst", "csv", "tsv", "log", "json", "xml", "yaml", "yml", "toml", "ini",
+    "cfg", "conf", "html", "htm", "css", "tex", "srt", "vtt",
+];
+const DOCUMENT_EXTENSIONS: &[&str] = &[
+    "pdf", "txt", "md", "rtf", "csv", "doc", "docx", "xls", "xlsx", "ppt", "pptx", "odt", "ods", "odp", "epub",
+];
+
+/// The group a file belongs to going by its extension.
+fn extension_group(path: &Path, ext: &str) -> Option<TypeGroup> {
+    if EXECUTABLE_EXTENSIONS.contains(&ext) {
+        return Some(TypeGroup::Executable);
+    }
+    if SCRIPT_EXTENSIONS.contains(&ext) {
+        return Some(TypeGroup::Script);
+    }
+    if DOCUMENT_EXTENSIONS.contains(&ext) {
+        return Some(TypeGroup::Document);
+    }
+    if ArchiveKind::from_path(path).is_some() || matches!(ext, "7z" | "rar" | "xz" | "bz2" | "gz" | "zst") {
+        return Some(TypeGroup::Archive);
+    }
+    match mime_guess::from_ext(ext).first()?.type_().as_str() {
+        "image" => Some(TypeGroup::Image),
+        "video" => Some(TypeGroup::Video),
+        "audio" => Some(TypeGroup::Audio),
+        _ => None,
+    }
+}
+
+/// Recognises a file format from its first bytes, whatever the file is called.
+/// Returns the format's group and a short description for error messages.
+pub fn sniff(head: &[u8], ext: &str) -> Option<(TypeGroup, &'static str)> {
+    let starts = |magic: &[u8]| head.starts_with(magic);
+    let at = |offset: usize, magic: &[u8]| head.get(offset..offset + magic.len()) == Some(magic);
+    // Two-byte signatures are too common in text to overrule a text extension
+    let text = PLAIN_TEXT_EXTENSIONS.contains(&ext);
+    Some(match () {
+        _ if starts(b"\x7fELF") => (TypeGroup::Executable, "ELF executable"),
+        _ if starts(b"MZ") && !text => (TypeGroup::Executable, "Windows executable"),
+        _ if starts(b"\xcf\xfa\xed\xfe") || starts(b"\xce\xfa\xed\xfe") || starts(b"\xca\xfe\xba\xbe") => {
+            (TypeGroup::Executable, "Mach-O executable")
+        }
+        _ if starts(b"#!") && !text => (TypeGroup::Script, "script"),
+        _ if starts(b"\x89PNG\r\n\x1a\n") => (TypeGroup::Image, "PNG image"),
+        _ if starts(b"\xff\xd8\xff") => (TypeGroup::Image, "JPEG image"),
+        _ if starts(b"GIF87a") || starts(b"GIF89a") => (TypeGroup::Image, "GIF image"),
+        _ if starts(b"BM") && at(6, b"\0\0\0\0") => (TypeGroup::Image, "BMP image"),
+        _ if starts(b"RIFF") && at(8, b"WEBP") => (TypeGroup::Image, "WebP image"),
+        _ if starts(b"RIFF") && at(8, b"WAVE") => (TypeGroup::Audio, "WAV audio"),
+        _ if starts(b"RIFF") && at(8, b"AVI ") => (TypeGroup::Video, "AVI video"),
+        _ if at(4, b"ftyp") => match head.get(8..12) {
+            Some(b"heic" | b"heix" | b"mif1" | b"msf1" | b"avif") => (TypeGroup::Image, "HEIF image"),
+            Some(b"M4A " | b"M4B ") => (TypeGroup::Audio, "MPEG-4 audio"),
+            _ => (TypeGroup::Video, "MPEG-4 video"),
+        },
+        _ if starts(b"\x1a\x45\xdf\xa3") => (TypeGroup::Video, "Matroska/WebM video"),
+        _ if starts(b"ID3") || (head.len() > 1 && head[0] == 0xff && head[1] & 0xe0 == 0xe0) => {
+            (TypeGroup::Audio, "MP3 audio")
+        }
+        _ if starts(b"fLaC") => (TypeGroup::Audio, "FLAC audio"),
+        _ if starts(b"OggS") => (TypeGroup::Audio, "Ogg media"),
+        _ if starts(b"%PDF-") => (TypeGroup::Document, "PDF document"),
+        // Office documents, EPUBs and Java/Android packages are ZIPs too
+        _ if starts(b"PK\x03\x04") => match extension_group(Path::new(""), ext) {
+            Some(group @ (TypeGroup::Document | TypeGroup::Executable)) => (group, "ZIP-based file"),
+            _ => (TypeGroup::Archive, "ZIP archive"),
+        },
+        _ if starts(b"\x1f\x8b") => (TypeGroup::Archive, "gzip archive"),
+        _ if starts(b"\x28\xb5\x2f\xfd") => (TypeGroup::Archive, "Zstandard archive"),
+        _ if starts(b"7z\xbc\xaf\x27\x1c") => (TypeGroup::Archive, "7-Zip archive"),
+        _ if starts(b"Rar!\x1a\x07") => (TypeGroup::Archive, "RAR archive"),
+        _ if starts(b"\xfd7zXZ\x00") => (TypeGroup::Archive, "XZ archive"),
+        _ if starts(b"BZh") && head.get(3).is_some_and(|level| (b'1'..=b'9').contains(level)) => {
+            (TypeGroup::Archive, "bzip2 archive")
+        }
+        _ => return None,
+    })
+}
+
+/// A file a folder's policy doesn't accept. Answered as `415 Unsupported Media Type`.
+#[derive(Debug, Serialize)]
+pub struct TypeViolation {
+    pub error: &'static str,
+    pub message: String,
+    pub path: String,
+    /// The file's extension, lowercased.
+    pub extension: String,
+    /// What the content looks like, if it was recognised.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detected: Option<&'static str>,
+}
+
+impl fmt::Display for TypeViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl IntoResponse for TypeViolation {
+    fn into_response(self) -> Response {
+        (StatusCode::UNSUPPORTED_MEDIA_TYPE, Json(self)).into_response()
+    }
+}
+
+/// Checks a file about to be written at `rel` against the `allow_types` /
+/// `deny_types` of its folder. `head` is the start of its content (see
+/// [`SNIFF_LEN`]); with an empty `head` only the name is checked.
+///
+/// A file is refused when its extension or its content matches a denied entry,
+/// or, if the folder has an allow list, when its extension isn't listed or its
+/// content is recognised as something that isn't.
+pub fn check_type(rel: &str, head: &[u8]) -> Result<(), TypeViolation> {
+    let parent = rel.rsplit_once('/').map_or("", |(parent, _)| parent);
+    let policy = policy_for(parent);
+    let (allow, deny) = (policy.allow_types(), policy.deny_types());
+    if allow.is_none() && deny.is_empty() {
+        return Ok(());
+    }
+
+    let path = Path::new(rel);
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase();
+    let by_name = extension_group(path, &ext);
+    let sniffed = sniff(head, &ext);
+    let listed = |list: &[String], group: Option<TypeGroup>, ext: Option<&str>| {
+        list.iter().any(|entry| {
+            let entry = entry.trim_start_matches('.').to_lowercase();
+            ext.is_some_and(|ext| !ext.is_empty() && ext == entry) || group.is_some_and(|g| g.listed_as(&entry))
+        })
+    };
+    let violation = |message: String| TypeViolation {
+        error: "file_type_not_allowed",
+        message,
+        path: rel.to_string(),
+        extension: ext.clone(),
+        detected: sniffed.map(|(_, label)| label),
+    };
+    let folder = if parent.is_empty() { "the shared folder".to_string() } else { format!("'{parent}'") };
+
+    if listed(deny, by_name, Some(&ext)) {
+        return Err(violation(format!("Files like '{rel}' are not allowed in {folder}")));
+    }
+    if let Some((group, label)) = sniffed {
+        if listed(deny, Some(group), None) {
+            return Err(violation(format!("'{rel}' was detected as {label}, which is not allowed in {folder}")));
+        }
+    }
+    if let Some(allow) = allow {
+        if !listed(allow, by_name, Some(&ext)) {
+            return Err(violation(format!("Only {} files are allowed in {folder}", allow.join(", "))));
+        }
+        if let Some((group, label)) = sniffed {
+            if !listed(allow, Some(group), None) && by_name != Some(group) {
+                return Err(violation(format!("'{rel}' was detected as {label}, which is not allowed in {folder}")));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks everything that arrives at `rel` when the file or folder at `source` is
+/// moved, copied or restored there, each file as [`check_type`] does. Links are
+/// left out. Blocking.
+pub fn check_tree(source: &Path, rel: &str) -> Result<(), TypeViolation> {
+    let Ok(meta) = std::fs::symlink_metadata(source) else {
+        return Ok(());
+    };
+    if meta.is_dir() {
+        for entry in std::fs::read_dir(source).into_iter().flatten().flatten() {
+            if let Some(name) = entry.file_name().to_str() {
+                check_tree(&entry.path(), &format!("{rel}/{name}"))?;
+            }
+        }
+        return Ok(());
+    }
+    if !meta.is_file() {
+        return Ok(());
+    }
+    // The content is only read for folders that have rules
+    let parent = rel.rsplit_once('/').map_or("", |(parent, _)| parent);
+    let policy = policy_for(parent);
+    if policy.allow_types().is_none() && policy.deny_types().is_empty() {
+        return Ok(());
+    }
+    check_type(rel, &read_head(source).unwrap_or_default())
+}
+
+/// Reads up to [`SNIFF_LEN`] bytes from the start of a file. Blocking.
+pub fn read_head(path: &Path) -> io::Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    std::fs::File::open(path)?.take(SNIFF_LEN as u64).read_to_end(&mut head)?;
+    Ok(head)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_signatures_do_not_overrule_text_extensions() {
+        assert_eq!(sniff(b"MZ notes", "txt"), None);
+        assert_eq!(sniff(b"#!/bin/sh\n", "md"), None);
+        assert!(matches!(sniff(b"MZ\x90\x00", "dat"), Some((TypeGroup::Executable, _))));
+        assert!(matches!(sniff(b"#!/bin/sh\n", ""), Some((TypeGroup::Script, _))));
+        // Longer signatures still count
+        assert!(matches!(sniff(b"\x7fELF\x02", "txt"), Some((TypeGroup::Executable, _))));
+    }
+}
@@ -1,19 +1,23 @@
+use super::archive::ArchiveKind;
 use super::file_tree::{FileEntry, FileType};
 use std::path::{Path, PathBuf};
 use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
 use chrono::{DateTime, Utc};
 use axum::{
     response::{IntoResponse, Response},
     http::StatusCode,
 };
 use mime_guess::{Mime,from_path};
-use crate::utils::config::Config;
-use tokio::fs::File;
+use serde::{Deserialize, Serialize};
+use crate::utils::config::{ActiveContentPolicy, Config};
 
 pub fn detect_file_type<P : AsRef<Path>>(path: P, is_dir: bool) -> FileType {
     let path = path.as_ref();
     if is_dir {
         FileType::Other
+    } else if ArchiveKind::from_path(path).is_some() {
+        FileType::Archive
     } else if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
         match ext.to_lowercase().as_str() {
             "mp4" | "mkv" | "avi" | "mov" => FileType::Video,
@@ -46,15 +50,56 @@ pub fn get_mime_type<P: AsRef<Path>>(path: P) -> Mime {
     from_path(path.as_ref()).first_or_octet_stream()
 }
 
-/// Async: Returns the file size for an open file handle.
-pub async fn file_size(f :&File) -> u64 {
-    match f.metadata().await {
-        Ok(meta) => meta.len(),
-        Err(_) => 0,
-    }
+/// Returns an opaque version tag for a file built from its size, modification time and inode.
+/// The tag changes whenever the file is rewritten or replaced.
+pub fn file_version_tag(meta: &fs::Metadata) -> String {
+    let modified = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    format!("{:x}-{:x}-{:x}", meta.len(), modified, inode(meta))
+}
+
+#[cfg(unix)]
+fn inode(meta: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    meta.ino()
+}
+
+#[cfg(not(unix))]
+fn inode(_meta: &fs::Metadata) -> u64 {
+    0
+}
+
+/// Returns the most recent modification time found anywhere in the tree.
+pub fn latest_modified(entry: &FileEntry) -> Option<SystemTime> {
+    let own = entry
+        .modified
+        .as_deref()
+        .and_then(|m| DateTime::parse_from_rfc3339(m).ok())
+        .map(SystemTime::from);
+    let children = entry
+        .children
+        .iter()
+        .flatten()
+        .filter_map(latest_modified);
+    own.into_iter().chain(children).max()
+}
+
+/// Hidden folder at the root of the share holding the server's own data (trash, ...).
+/// It is left out of the tree and can't be reached through [`safe_path`].
+pub const DATA_DIR: &str = ".rustyshare";
+
+/// Returns the folder `sub` inside [`DATA_DIR`].
+pub fn data_dir(sub: &str) -> PathBuf {
+    PathBuf::from(Config::from_env().file_dir()).join(DATA_DIR).join(sub)
 }
 
 /// Ensures a given path is safe and within the configured master directory.
+/// The server's own [`DATA_DIR`] counts as outside.
+#[allow(clippy::result_large_err)]
 pub fn safe_path<P: AsRef<Path>>(path: P) -> Result<PathBuf, Response> {
     let config = Config::from_env();
     let master_dir = PathBuf::from(config.file_dir());
@@ -63,20 +108,29 @@ pub fn safe_path<P: AsRef<Path>>(path: P) -> Result<PathBuf, Response> {
     let master_canon = master_dir.canonicalize().map_err(|_| {
         (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response()
     })?;
+    let data_canon = master_canon.join(DATA_DIR);
 
     // If the folder exists, great — but use full path canonicalization if available
     match joined_path.canonicalize() {
         Ok(p) => {
-            if p.starts_with(&master_canon) {
+            if p.starts_with(&master_canon) && !p.starts_with(&data_canon) {
                 Ok(joined_path)
             } else {
                 Err((StatusCode::FORBIDDEN, "Path escape blocked").into_response())
             }
         }
         Err(_) => {
-            // If canonicalization fails (e.g., file doesn't exist yet), still allow it if the parent exists
+            // If canonicalization fails (e.g., file doesn't exist yet), still allow it if the parent exists.
+            // The parent is canonicalized too, so `..` segments and symlinked folders can't lead outside.
             if let Some(parent) = joined_path.parent() {
-                if parent.exists() && parent.starts_with(&master_dir) {
+                if let Ok(parent) = parent.canonicalize() {
+                    let name = joined_path.file_name().unwrap_or_default();
+                    if !parent.starts_with(&master_canon)
+                        || parent.starts_with(&data_canon)
+                        || (parent == master_canon && name == DATA_DIR)
+                    {
+                        return Err((StatusCode::FORBIDDEN, "Path escape blocked").into_response());
+                    }
                     return Ok(joined_path);
                 }
             }
@@ -85,9 +139,23 @@ pub fn safe_path<P: AsRef<Path>>(path: P) -> Result<PathBuf, Response> {
     }
 }
 
+/// Checks if a file can run script when rendered by a browser (HTML, SVG, XML).
+pub fn is_active_content<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref();
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    matches!(
+        ext.as_str(),
+        "html" | "htm" | "xhtml" | "shtml" | "svg" | "svgz" | "xml" | "xsl" | "xslt"
+    )
+}
+
 /// Checks if a file extension is supported for browser preview.
+/// Active content is only previewable when it is served under a sandbox.
 pub fn is_browser_supported<P: AsRef<Path>>(path: P) -> bool {
     let path = path.as_ref();
+    if is_active_content(path) && Config::from_env().active_content_policy() == ActiveContentPolicy::Attachment {
+        return false;
+    }
     let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
     matches!(
         ext.as_str(),
@@ -104,6 +172,54 @@ pub fn is_browser_supported<P: AsRef<Path>>(path: P) -> bool {
     )
 }
 
+/// Returns `name` with a ` (n)` counter inserted before the extension, e.g. `photo (1).jpg`.
+pub fn numbered_name(name: &str, n: usize) -> String {
+    let path = Path::new(name);
+    match (path.file_stem().and_then(|s| s.to_str()), path.extension().and_then(|e| e.to_str())) {
+        (Some(stem), Some(ext)) if !stem.is_empty() => format!("{stem} ({n}).{ext}"),
+        _ => format!("{name} ({n})"),
+    }
+}
+
+/// Returns `name`, or the first numbered variant of it that does not exist in `dir` yet.
+pub fn free_name(dir: &Path, name: &str) -> String {
+    let mut candidate = name.to_string();
+    let mut n = 1;
+    while fs::symlink_metadata(dir.join(&candidate)).is_ok() {
+        candidate = numbered_name(name, n);
+        n += 1;
+    }
+    candidate
+}
+
+/// What to do when a file being written already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    /// Keep the existing file and leave the new one out.
+    #[default]
+    Skip,
+    /// Replace the existing file.
+    Overwrite,
+    /// Write the new file under a numbered name, e.g. `photo (1).jpg`.
+    Rename,
+}
+
+/// What to do when an upload has the name of a file that already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CollisionPolicy {
+    /// Refuse the upload with `409 Conflict`.
+    #[default]
+    Reject,
+    /// Replace the existing file, keeping it as a version.
+    Overwrite,
+    /// Save the upload under a numbered name, e.g. `IMG_0001 (1).jpg`.
+    Rename,
+    /// Drop the upload if the existing file has the same content, otherwise rename it.
+    SkipIdentical,
+}
+
 /// Recursively searches for a FileEntry by path in the tree.
 /// Returns a clone of the entry if found.
 pub fn find_entry(file_entry : &mut FileEntry, path: &str) -> Option<FileEntry> {
@@ -119,4 +235,19 @@ pub fn find_entry(file_entry : &mut FileEntry, path: &str) -> Option<FileEntry>
         }
     }
     None
+}
+
+/// Splits a path that leads into an archive in the tree (`bundle.zip/docs/a.pdf`)
+/// into the archive's entry and the member path inside it.
+pub fn find_archive_member(file_entry: &mut FileEntry, path: &str) -> Option<(FileEntry, String)> {
+    let mut end = path.len();
+    while let Some(split) = path[..end].rfind('/') {
+        // The longest prefix present in the tree decides: it must be an archive file
+        if let Some(entry) = find_entry(file_entry, &path[..split]) {
+            let is_archive = !entry.is_dir && entry.file_type == FileType::Archive;
+            return is_archive.then(|| (entry, path[split + 1..].to_string()));
+        }
+        end = split;
+    }
+    None
 }
\ No newline at end of file
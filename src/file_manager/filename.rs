@@ -0,0 +1,158 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use unicode_normalization::UnicodeNormalization;
+use crate::utils::config::Config;
+
+/// Why a name can't be written to the share.
+#[derive(Debug)]
+pub enum NameError {
+    Invalid(String),
+    TooLong(String),
+}
+
+impl fmt::Display for NameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NameError::Invalid(message) | NameError::TooLong(message) => f.write_str(message),
+        }
+    }
+}
+
+impl IntoResponse for NameError {
+    fn into_response(self) -> Response {
+        (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+    }
+}
+
+/// Checks one file or folder name given explicitly by a client and returns it in
+/// NFC form. Names that are empty, `.` or `..`, contain control characters, slashes
+/// or backslashes, end in a dot or a space, or are longer than `MAX_NAME_BYTES` are refused.
+pub fn check_name(name: &str) -> Result<String, NameError> {
+    let name: String = name.nfc().collect();
+    let invalid = |reason: &str| Err(NameError::Invalid(format!("Invalid name '{}': {reason}", name.escape_debug())));
+    if matches!(name.as_str(), "" | "." | "..") {
+        return invalid("not a file name");
+    }
+    if name.chars().any(|c| c.is_control() || c == '/' || c == '\\') {
+        return invalid("contains a control character or a slash");
+    }
+    if name.ends_with(['.', ' ']) {
+        return invalid("ends in a dot or a space");
+    }
+    let max = Config::from_env().max_name_bytes();
+    if name.len() > max {
+        return Err(NameError::TooLong(format!("Name is longer than {max} bytes")));
+    }
+    Ok(name)
+}
+
+/// Turns a name that came with content (an uploaded file's name, an archive member)
+/// into one that passes [`check_name`]: only the last path component is kept,
+/// control characters are dropped, trailing dots and spaces are trimmed and an
+/// overlong name is shortened, keeping its extension.
+pub fn clean_name(name: &str) -> Result<String, NameError> {
+    let name: String = name
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .nfc()
+        .filter(|c| !c.is_control())
+        .collect();
+    let mut name = name.trim().trim_end_matches(['.', ' ']).to_string();
+
+    let max = Config::from_env().max_name_bytes();
+    if name.len() > max {
+        let ext = Path::new(&name)
+            .extension()
+            .and_then(|e| e.to_str())
+            .filter(|ext| ext.len() < max / 2)
+            .map(|ext| format!(".{ext}"))
+            .unwrap_or_default();
+        let mut cut = max - ext.len();
+        while !name.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        name = format!("{}{ext}", name[..cut].trim_end_matches(['.', ' ']));
+    }
+    check_name(&name)
+}
+
+/// Checks a path inside the share that is about to be written, and returns the
+/// path to write. Components that exist are kept as they are; new ones must pass
+/// [`check_name`] and are normalized to NFC. A new name that only differs from an
+/// existing sibling in its Unicode form (or in case, with `CASE_INSENSITIVE_NAMES`)
+/// resolves to that sibling, so the usual "already exists" handling applies instead
+/// of a near-duplicate being created. The result is at most `MAX_PATH_BYTES` long.
+pub fn new_path(rel: &str) -> Result<String, NameError> {
+    let config = Config::from_env();
+    let rel = rel.trim_matches('/');
+    if rel.is_empty() {
+        return Err(NameError::Invalid("A name is required".to_string()));
+    }
+
+    let mut dir = PathBuf::from(config.file_dir());
+    let mut parts: Vec<String> = Vec::new();
+    // Once a folder is new, nothing below it can exist yet
+    let mut existing = true;
+    for part in rel.split('/') {
+        // Checked before the existence shortcut: `a//b` and `a/./b` exist as `a` and `a/b`
+        if matches!(part, "" | "." | "..") {
+            return Err(NameError::Invalid(format!("Invalid path '{}': empty, '.' and '..' components are not allowed", rel.escape_debug())));
+        }
+        let name = if existing && fs::symlink_metadata(dir.join(part)).is_ok() {
+            part.to_string()
+        } else {
+            let name = check_name(part)?;
+            match existing.then(|| sibling_twin(&dir, &name, config.case_insensitive_names())).flatten() {
+                Some(twin) => twin,
+                None => {
+                    existing = false;
+                    name
+                }
+            }
+        };
+        dir.push(&name);
+        parts.push(name);
+    }
+
+    let path = parts.join("/");
+    let max = config.max_path_bytes();
+    if path.len() > max {
+        return Err(NameError::TooLong(format!("Path is longer than {max} bytes")));
+    }
+    Ok(path)
+}
+
+/// An entry of `dir` whose name matches `name` once both are in NFC (and lowercased,
+/// if `ignore_case`).
+fn sibling_twin(dir: &Path, name: &str, ignore_case: bool) -> Option<String> {
+    let fold = |s: &str| -> String {
+        let s: String = s.nfc().collect();
+        if ignore_case { s.to_lowercase() } else { s }
+    };
+    let wanted = fold(name);
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .find(|sibling| fold(sibling) == wanted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_manager::test_support::share_dir;
+
+    #[test]
+    fn paths_with_dot_or_empty_components_are_refused() {
+        fs::create_dir_all(share_dir().join("new-path/sub")).unwrap();
+        for rel in ["new-path//sub", "new-path/./sub", "new-path/sub/..", "new-path/../new-path/x", "./new-path"] {
+            assert!(matches!(new_path(rel), Err(NameError::Invalid(_))), "{rel} was accepted");
+        }
+        assert_eq!(new_path("/new-path/sub/x.txt/").unwrap(), "new-path/sub/x.txt");
+    }
+}
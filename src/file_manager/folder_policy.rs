@@ -0,0 +1,137 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use tracing::warn;
+use super::file_utils::{data_dir, CollisionPolicy};
+use crate::utils::config::Config;
+
+/// Settings that can differ between folders. They are read from
+/// `{FILE_DIR}/.rustyshare/policies.json`, a map from folder path (`""` is the
+/// share itself) to the settings for that folder and everything below it:
+///
+/// ```json
+/// {
+///   "": { "keep_versions": 5, "deny_types": ["executable", "script"] },
+///   "Documents": { "keep_versions": 20, "version_max_age_days": 90 },
+///   "Camera": { "on_collision": "skip_identical" },
+///   "Kids": { "allow_types": ["media"] },
+///   "tmp": { "ttl_hours": 24 }
+/// }
+/// ```
+///
+/// Each setting comes from the nearest folder that sets it, falling back to the
+/// environment defaults.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FolderPolicy {
+    /// Previous versions kept for each file; 0 turns versioning off.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_versions: Option<u32>,
+    /// Days after which previous versions are dropped; 0 keeps them regardless of age.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version_max_age_days: Option<u64>,
+    /// What uploads do when a file with the same name exists; `reject` by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_collision: Option<CollisionPolicy>,
+    /// If set, only files matching one of these extensions or type groups
+    /// (see `file_types::TypeGroup`) may be written.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_types: Option<Vec<String>>,
+    /// Extensions or type groups that may not be written.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deny_types: Option<Vec<String>>,
+    /// Bytes each top-level folder at or below this one may hold; 0 means no limit.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota_bytes: Option<u64>,
+    /// Files each top-level folder at or below this one may hold; 0 means no limit.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota_files: Option<u64>,
+    /// Hours after which files uploaded here without a TTL of their own are deleted;
+    /// 0 keeps them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl_hours: Option<u64>,
+}
+
+impl FolderPolicy {
+    /// Fills settings this policy leaves open from `parent`.
+    fn inherit(&mut self, parent: &FolderPolicy) {
+        self.keep_versions = self.keep_versions.or(parent.keep_versions);
+        self.version_max_age_days = self.version_max_age_days.or(parent.version_max_age_days);
+        self.on_collision = self.on_collision.or(parent.on_collision);
+        self.quota_bytes = self.quota_bytes.or(parent.quota_bytes);
+        self.quota_files = self.quota_files.or(parent.quota_files);
+        self.ttl_hours = self.ttl_hours.or(parent.ttl_hours);
+        if self.allow_types.is_none() {
+            self.allow_types.clone_from(&parent.allow_types);
+        }
+        if self.deny_types.is_none() {
+            self.deny_types.clone_from(&parent.deny_types);
+        }
+    }
+
+    pub fn keep_versions(&self) -> u32 {
+        self.keep_versions.unwrap_or_else(|| Config::from_env().versions_keep())
+    }
+
+    pub fn version_max_age_days(&self) -> u64 {
+        self.version_max_age_days.unwrap_or_else(|| Config::from_env().versions_max_age_days())
+    }
+
+    pub fn on_collision(&self) -> CollisionPolicy {
+        self.on_collision.unwrap_or_default()
+    }
+
+    pub fn allow_types(&self) -> Option<&[String]> {
+        self.allow_types.as_deref()
+    }
+
+    pub fn deny_types(&self) -> &[String] {
+        self.deny_types.as_deref().unwrap_or_default()
+    }
+
+    pub fn quota_bytes(&self) -> u64 {
+        self.quota_bytes.unwrap_or_else(|| Config::from_env().quota_folder_bytes())
+    }
+
+    pub fn quota_files(&self) -> u64 {
+        self.quota_files.unwrap_or_else(|| Config::from_env().quota_folder_files())
+    }
+
+    pub fn ttl_hours(&self) -> u64 {
+        self.ttl_hours.unwrap_or(0)
+    }
+}
+
+/// Returns the policy that applies to the file or folder at `rel`.
+pub fn policy_for(rel: &str) -> FolderPolicy {
+    let policies = load_policies();
+    let mut policy = FolderPolicy::default();
+    // Walk from `rel` up to the share root; nearer folders win
+    let mut current = rel.trim_matches('/');
+    loop {
+        if let Some(folder) = policies.get(current) {
+            policy.inherit(folder);
+        }
+        if current.is_empty() {
+            return policy;
+        }
+        current = current.rsplit_once('/').map_or("", |(parent, _)| parent);
+    }
+}
+
+fn load_policies() -> HashMap<String, FolderPolicy> {
+    let path = data_dir("policies.json");
+    let Ok(content) = fs::read(&path) else {
+        return HashMap::new();
+    };
+    match serde_json::from_slice::<HashMap<String, FolderPolicy>>(&content) {
+        Ok(policies) => policies
+            .into_iter()
+            .map(|(folder, policy)| (folder.trim_matches('/').to_string(), policy))
+            .collect(),
+        Err(e) => {
+            warn!("Ignoring {}: {e}", path.display());
+            HashMap::new()
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use chrono::Utc;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+
+/// Finished jobs kept around so clients can still read their outcome.
+const FINISHED_JOBS_KEPT: usize = 100;
+/// Minimum time between two progress events of the same job.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Lifecycle of a background job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+/// Point-in-time view of a job, as returned by the API and sent over `/events/jobs`.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobSnapshot {
+    pub id: u64,
+    /// Short operation name, e.g. `extract`.
+    pub kind: &'static str,
+    pub description: String,
+    pub status: JobStatus,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub items_done: u64,
+    pub items_total: u64,
+    /// Entries that were left out, with the reason.
+    pub skipped: Vec<String>,
+    /// Failure reason, or a summary once the job is done.
+    pub message: Option<String>,
+    pub started_at: String,
+    pub finished_at: Option<String>,
+}
+
+/// Keeps track of long-running file operations (extraction, copies, ...)
+/// and broadcasts their progress.
+pub struct JobRegistry {
+    jobs: Mutex<BTreeMap<u64, Arc<Job>>>,
+    next_id: Mutex<u64>,
+    events: broadcast::Sender<JobSnapshot>,
+}
+
+impl Default for JobRegistry {
+    fn default() -> Self {
+        Self {
+            jobs: Mutex::new(BTreeMap::new()),
+            next_id: Mutex::new(1),
+            events: broadcast::channel(64).0,
+        }
+    }
+}
+
+impl JobRegistry {
+    /// Registers a new running job and returns the handle its worker reports through.
+    pub fn start(&self, kind: &'static str, description: String) -> Arc<Job> {
+        let id = {
+            let mut next_id = self.next_id.lock().unwrap();
+            let id = *next_id;
+            *next_id += 1;
+            id
+        };
+        let job = Arc::new(Job {
+            state: Mutex::new(JobState {
+                snapshot: JobSnapshot {
+                    id,
+                    kind,
+                    description,
+                    status: JobStatus::Running,
+                    bytes_done: 0,
+                    bytes_total: 0,
+                    items_done: 0,
+                    items_total: 0,
+                    skipped: Vec::new(),
+                    message: None,
+                    started_at: Utc::now().to_rfc3339(),
+                    finished_at: None,
+                },
+                last_event: Instant::now(),
+            }),
+            cancel: CancellationToken::new(),
+            events: self.events.clone(),
+        });
+
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.insert(id, job.clone());
+        // Forget the oldest finished jobs once there are too many
+        let finished: Vec<u64> = jobs
+            .iter()
+            .filter(|(_, job)| job.snapshot().status != JobStatus::Running)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in finished.iter().take(finished.len().saturating_sub(FINISHED_JOBS_KEPT)) {
+            jobs.remove(id);
+        }
+        drop(jobs);
+
+        job.emit();
+        job
+    }
+
+    pub fn list(&self) -> Vec<JobSnapshot> {
+        self.jobs.lock().unwrap().values().map(|job| job.snapshot()).collect()
+    }
+
+    pub fn get(&self, id: u64) -> Option<JobSnapshot> {
+        self.jobs.lock().unwrap().get(&id).map(|job| job.snapshot())
+    }
+
+    /// Asks a running job to stop. Returns false for unknown or already finished jobs.
+    pub fn cancel(&self, id: u64) -> bool {
+        match self.jobs.lock().unwrap().get(&id) {
+            Some(job) if job.snapshot().status == JobStatus::Running => {
+                job.cancel.cancel();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<JobSnapshot> {
+        self.events.subscribe()
+    }
+}
+
+struct JobState {
+    snapshot: JobSnapshot,
+    last_event: Instant,
+}
+
+/// Handle a worker uses to report progress and observe cancellation.
+pub struct Job {
+    state: Mutex<JobState>,
+    cancel: CancellationToken,
+    events: broadcast::Sender<JobSnapshot>,
+}
+
+impl Job {
+    pub fn id(&self) -> u64 {
+        self.state.lock().unwrap().snapshot.id
+    }
+
+    pub fn snapshot(&self) -> JobSnapshot {
+        self.state.lock().unwrap().snapshot.clone()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// Completes once the job is asked to stop, for async workers waiting on I/O.
+    pub async fn cancelled(&self) {
+        self.cancel.cancelled().await
+    }
+
+    /// Sets the amount of work the job expects to do.
+    pub fn set_totals(&self, bytes_total: u64, items_total: u64) {
+        self.update(true, |s| {
+            s.bytes_total = bytes_total;
+            s.items_total = items_total;
+        });
+    }
+
+    /// Records bytes processed within the current item.
+    pub fn add_bytes(&self, bytes: u64) {
+        self.update(false, |s| s.bytes_done += bytes);
+    }
+
+    /// Sets the bytes processed so far, for work that has to start over.
+    pub fn set_bytes_done(&self, bytes: u64) {
+        self.update(true, |s| s.bytes_done = bytes);
+    }
+
+    /// Records a finished item.
+    pub fn item_done(&self) {
+        self.update(false, |s| s.items_done += 1);
+    }
+
+    /// Records an item that was left out and why.
+    pub fn skip(&self, item: String) {
+        self.update(false, |s| s.skipped.push(item));
+    }
+
+    /// Marks the job as finished with `status` and an optional message.
+    pub fn finish(&self, status: JobStatus, message: Option<String>) {
+        self.update(true, |s| {
+            s.status = status;
+            s.message = message;
+            s.finished_at = Some(Utc::now().to_rfc3339());
+        });
+    }
+
+    /// Applies `change` and broadcasts the new state, at most every
+    /// [`PROGRESS_INTERVAL`] unless `force` is set.
+    fn update(&self, force: bool, change: impl FnOnce(&mut JobSnapshot)) {
+        let mut state = self.state.lock().unwrap();
+        change(&mut state.snapshot);
+        if force || state.last_event.elapsed() >= PROGRESS_INTERVAL {
+            state.last_event = Instant::now();
+            let _ = self.events.send(state.snapshot.clone());
+        }
+    }
+
+    fn emit(&self) {
+        self.update(true, |_| {});
+    }
+}
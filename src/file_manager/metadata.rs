@@ -0,0 +1,195 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::time::Duration;
+use tracing::{error, warn};
+use super::file_utils::{data_dir, file_version_tag};
+
+/// How long changes are collected before the store is written.
+const FLUSH_DELAY: Duration = Duration::from_millis(500);
+
+/// What the server knows about a file beyond what the filesystem records.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FileMeta {
+    /// Hex SHA-256 of the content.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Version tag of the file the hash was computed for (see `file_version_tag`).
+    /// A file rewritten behind the server's back no longer matches, so its hash is ignored.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hashed_version: Option<String>,
+    /// Client that uploaded the file, for per-user quotas.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    /// When the file is deleted by the expiry sweeper (RFC 3339, UTC).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+}
+
+/// Sidecar metadata for the share, keyed by path and kept in
+/// `{FILE_DIR}/.rustyshare/metadata.json`. Loaded on first use.
+fn store() -> &'static Mutex<BTreeMap<String, FileMeta>> {
+    static STORE: OnceLock<Mutex<BTreeMap<String, FileMeta>>> = OnceLock::new();
+    STORE.get_or_init(|| Mutex::new(load()))
+}
+
+fn load() -> BTreeMap<String, FileMeta> {
+    let path = data_dir("metadata.json");
+    match fs::read(&path) {
+        Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|e| {
+            warn!("Ignoring {}: {e}", path.display());
+            BTreeMap::new()
+        }),
+        Err(_) => BTreeMap::new(),
+    }
+}
+
+/// Marks the store as changed. A background thread writes it out after [`FLUSH_DELAY`],
+/// so a burst of changes costs one write and callers never wait on the disk.
+fn save() {
+    static FLUSHER: OnceLock<()> = OnceLock::new();
+    FLUSHER.get_or_init(|| {
+        std::thread::spawn(|| loop {
+            let (dirty, changed) = dirty();
+            drop(changed.wait_while(dirty.lock().unwrap(), |dirty| !*dirty).unwrap());
+            std::thread::sleep(FLUSH_DELAY);
+            flush();
+        });
+    });
+    let (dirty, changed) = dirty();
+    *dirty.lock().unwrap() = true;
+    changed.notify_one();
+}
+
+fn dirty() -> &'static (Mutex<bool>, Condvar) {
+    static DIRTY: OnceLock<(Mutex<bool>, Condvar)> = OnceLock::new();
+    DIRTY.get_or_init(|| (Mutex::new(false), Condvar::new()))
+}
+
+/// Writes pending changes now, through a temporary file so a crash never leaves the
+/// store half written. Called on shutdown; otherwise the background thread does it.
+pub fn flush() {
+    static WRITING: Mutex<()> = Mutex::new(());
+    let _writing = WRITING.lock().unwrap();
+    {
+        let mut dirty = dirty().0.lock().unwrap();
+        if !*dirty {
+            return;
+        }
+        *dirty = false;
+    }
+    let content = match serde_json::to_vec(&*store().lock().unwrap()) {
+        Ok(content) => content,
+        Err(e) => {
+            error!("Failed to save file metadata: {e}");
+            return;
+        }
+    };
+    let path = data_dir("metadata.json");
+    let tmp = path.with_extension("json.tmp");
+    let written = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| fs::write(&tmp, content))
+        .and_then(|()| fs::rename(&tmp, &path));
+    if let Err(e) = written {
+        error!("Failed to save file metadata: {e}");
+    }
+}
+
+/// Records the SHA-256 of the file at `rel`, as it is on disk now.
+pub fn record_hash(rel: &str, meta: &fs::Metadata, sha256: String) {
+    let mut entries = store().lock().unwrap();
+    let entry = entries.entry(rel.to_string()).or_default();
+    entry.sha256 = Some(sha256);
+    entry.hashed_version = Some(file_version_tag(meta));
+    save();
+}
+
+/// Records who uploaded the file at `rel`.
+pub fn record_owner(rel: &str, owner: &str) {
+    let mut entries = store().lock().unwrap();
+    entries.entry(rel.to_string()).or_default().owner = Some(owner.to_string());
+    save();
+}
+
+/// Records when the file at `rel` expires; `None` means it doesn't.
+pub fn record_expiry(rel: &str, expires_at: Option<String>) {
+    let mut entries = store().lock().unwrap();
+    let entry = entries.entry(rel.to_string()).or_default();
+    if entry.expires_at != expires_at {
+        entry.expires_at = expires_at;
+        save();
+    }
+}
+
+/// When the file at `rel` expires, if it does.
+pub fn expiry_of(rel: &str) -> Option<String> {
+    store().lock().unwrap().get(rel)?.expires_at.clone()
+}
+
+/// Paths of the files that expired before `now`.
+pub fn expired(now: DateTime<Utc>) -> Vec<String> {
+    let entries = store().lock().unwrap();
+    entries
+        .iter()
+        .filter(|(_, meta)| {
+            let at = meta.expires_at.as_deref().and_then(|at| DateTime::parse_from_rfc3339(at).ok());
+            at.is_some_and(|at| at <= now)
+        })
+        .map(|(rel, _)| rel.clone())
+        .collect()
+}
+
+/// Paths of the files uploaded by `owner`.
+pub fn owned_by(owner: &str) -> Vec<String> {
+    let entries = store().lock().unwrap();
+    entries
+        .iter()
+        .filter(|(_, meta)| meta.owner.as_deref() == Some(owner))
+        .map(|(rel, _)| rel.clone())
+        .collect()
+}
+
+/// Returns the recorded SHA-256 of `rel` if it still describes the file with `meta`.
+pub fn hash_of(rel: &str, meta: &fs::Metadata) -> Option<String> {
+    let entries = store().lock().unwrap();
+    let entry = entries.get(rel)?;
+    (entry.hashed_version.as_deref() == Some(file_version_tag(meta).as_str()))
+        .then(|| entry.sha256.clone())
+        .flatten()
+}
+
+/// Moves the metadata of `from`, and of everything below it, to `to`.
+pub fn rename(from: &str, to: &str) {
+    let mut entries = store().lock().unwrap();
+    let moved: Vec<String> = entries.keys().filter(|key| is_within(key, from)).cloned().collect();
+    if moved.is_empty() {
+        return;
+    }
+    // Whatever was recorded for the destination belonged to a file that is gone
+    entries.retain(|key, _| !is_within(key, to));
+    for key in moved {
+        if let Some(meta) = entries.remove(&key) {
+            entries.insert(format!("{to}{}", &key[from.len()..]), meta);
+        }
+    }
+    save();
+}
+
+/// Forgets `rel` and everything below it.
+pub fn remove(rel: &str) {
+    let mut entries = store().lock().unwrap();
+    let before = entries.len();
+    entries.retain(|key, _| !is_within(key, rel));
+    if entries.len() != before {
+        save();
+    }
+}
+
+fn is_within(key: &str, rel: &str) -> bool {
+    key == rel || key.strip_prefix(rel).is_some_and(|rest| rest.starts_with('/'))
+}
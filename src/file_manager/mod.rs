@@ -1,8 +1,18 @@
 pub mod file_tree;
 pub mod tree_watcher; 
 pub mod file_utils;
-
-
-
-
-
+pub mod archive;
+pub mod jobs;
+pub mod transfer;
+pub mod trash;
+pub mod folder_policy;
+pub mod versions;
+pub mod checksum;
+pub mod metadata;
+pub mod filename;
+pub mod file_types;
+pub mod quota;
+pub mod fetch;
+pub mod expiry;
+#[cfg(test)]
+pub mod test_support;
@@ -0,0 +1,329 @@
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex as StdMutex, MutexGuard, OnceLock};
+use tokio::sync::Mutex;
+use super::file_tree::FileEntry;
+use super::file_utils::{find_entry, safe_path};
+use super::folder_policy::policy_for;
+use super::metadata;
+use crate::utils::config::Config;
+
+/// Space and file count taken, against the limits that apply (`None` for no limit).
+#[derive(Debug, Clone, Serialize)]
+pub struct QuotaUsage {
+    pub bytes_used: u64,
+    pub files_used: u64,
+    pub bytes_limit: Option<u64>,
+    pub files_limit: Option<u64>,
+}
+
+impl QuotaUsage {
+    fn new(bytes_used: u64, files_used: u64, bytes_limit: u64, files_limit: u64) -> Self {
+        Self {
+            bytes_used,
+            files_used,
+            bytes_limit: (bytes_limit > 0).then_some(bytes_limit),
+            files_limit: (files_limit > 0).then_some(files_limit),
+        }
+    }
+
+    fn bytes_left(&self) -> Option<u64> {
+        self.bytes_limit.map(|limit| limit.saturating_sub(self.bytes_used))
+    }
+
+    fn files_full(&self) -> bool {
+        self.files_limit.is_some_and(|limit| self.files_used >= limit)
+    }
+
+    /// How many more files fit, `None` for no limit.
+    pub fn files_left(&self) -> Option<u64> {
+        self.files_limit.map(|limit| limit.saturating_sub(self.files_used))
+    }
+}
+
+/// How much one write may still store, and which limit says so.
+///
+/// The bytes a write stores are reserved with [`Allowance::take`] while they arrive,
+/// so concurrent writes count against the same limits before anything reaches the
+/// share. They are released when the allowance is dropped, or, once the file is in
+/// place, handed over to the usage measured on disk by [`Allowance::commit`].
+#[derive(Debug)]
+pub struct Allowance {
+    pub bytes: u64,
+    pub limited_by: String,
+    limits: Vec<Limit>,
+    held: u64,
+}
+
+/// One limit a write is held to: at most `left` bytes beyond what was measured,
+/// counting what other writes reserved or committed since.
+#[derive(Debug)]
+struct Limit {
+    scope: Scope,
+    left: u64,
+    committed_before: u64,
+    what: String,
+}
+
+/// What a limit applies to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Scope {
+    Disk,
+    User(String),
+    Folder(String),
+}
+
+/// Bytes of writes in progress, and running totals of what finished writes added,
+/// per scope. Totals only grow; limits compare them with a snapshot.
+#[derive(Default)]
+struct InFlight {
+    reserved: HashMap<Scope, u64>,
+    committed: HashMap<Scope, u64>,
+}
+
+impl InFlight {
+    fn get() -> MutexGuard<'static, InFlight> {
+        static IN_FLIGHT: OnceLock<StdMutex<InFlight>> = OnceLock::new();
+        IN_FLIGHT.get_or_init(Default::default).lock().unwrap()
+    }
+
+    fn reserved(&self, scope: &Scope) -> u64 {
+        self.reserved.get(scope).copied().unwrap_or(0)
+    }
+
+    fn committed(&self, scope: &Scope) -> u64 {
+        self.committed.get(scope).copied().unwrap_or(0)
+    }
+
+    /// Bytes counting against `limit` that its measurement didn't see.
+    fn unseen(&self, limit: &Limit) -> u64 {
+        self.reserved(&limit.scope) + self.committed(&limit.scope).saturating_sub(limit.committed_before)
+    }
+}
+
+/// The top-level folder `rel` is in. Files directly in the share belong to none.
+pub fn top_folder(rel: &str) -> Option<&str> {
+    rel.trim_matches('/').split_once('/').map(|(top, _)| top)
+}
+
+/// Usage of the top-level folder `folder`, counted from the tree.
+pub fn folder_usage(tree: &mut FileEntry, folder: &str) -> QuotaUsage {
+    let (bytes, files) = find_entry(tree, folder).map_or((0, 0), |entry| count(&entry));
+    let policy = policy_for(folder);
+    QuotaUsage::new(bytes, files, policy.quota_bytes(), policy.quota_files())
+}
+
+/// Bytes and number of files of `entry`, with everything below a folder.
+pub fn count(entry: &FileEntry) -> (u64, u64) {
+    if !entry.is_dir {
+        return (entry.size.unwrap_or(0), 1);
+    }
+    entry.children.iter().flatten().map(count).fold((0, 0), |(b, f), (cb, cf)| (b + cb, f + cf))
+}
+
+/// Usage of the files `user` uploaded that are still in the share. Blocking.
+pub fn user_usage(user: &str) -> QuotaUsage {
+    let (mut bytes, mut files) = (0, 0);
+    for rel in metadata::owned_by(user) {
+        let Ok(path) = safe_path(&rel) else { continue };
+        if let Ok(meta) = fs::metadata(path) {
+            if meta.is_file() {
+                bytes += meta.len();
+                files += 1;
+            }
+        }
+    }
+    let config = Config::from_env();
+    QuotaUsage::new(bytes, files, config.quota_user_bytes(), config.quota_user_files())
+}
+
+/// Free space on the share's filesystem beyond `FREE_SPACE_RESERVE_BYTES`.
+pub fn space_left() -> io::Result<u64> {
+    let config = Config::from_env();
+    let available = fs4::available_space(config.file_dir())?;
+    Ok(available.saturating_sub(config.free_space_reserve_bytes()))
+}
+
+/// Works out how many bytes `user` may still write into the top-level `folder`
+/// (`None` for the share itself), or why nothing may be written. `new_file` also
+/// checks the file count limits. Writes that belong to no user (copies, extractions)
+/// pass `None` and are only held to the folder's quota and the free space.
+pub async fn allowance(
+    user: Option<&str>,
+    folder: Option<&str>,
+    new_file: bool,
+    tree: &Arc<Mutex<Option<FileEntry>>>,
+) -> Result<Allowance, String> {
+    let (user, folder, tree) = (user.map(str::to_string), folder.map(str::to_string), tree.clone());
+    tokio::task::spawn_blocking(move || allowance_blocking(user.as_deref(), folder.as_deref(), new_file, &tree))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// [`allowance`] for code that already runs on a blocking thread.
+pub fn allowance_blocking(
+    user: Option<&str>,
+    folder: Option<&str>,
+    new_file: bool,
+    tree: &Mutex<Option<FileEntry>>,
+) -> Result<Allowance, String> {
+    let mut allowance = Allowance { bytes: u64::MAX, limited_by: String::new(), limits: Vec::new(), held: 0 };
+    // Snapshot the running totals before measuring, so a write finishing in between
+    // is counted twice rather than not at all
+    let committed = InFlight::get().committed(&Scope::Disk);
+    let left = space_left().map_err(|e| format!("Failed to check free space: {e}"))?;
+    allowance.add(Scope::Disk, left, committed, "the free disk space")?;
+    if let Some(user) = user {
+        let scope = Scope::User(user.to_string());
+        let committed = InFlight::get().committed(&scope);
+        allowance.limit(scope, committed, &user_usage(user), "your upload quota", new_file)?;
+    }
+    if let Some(folder) = folder {
+        allowance.limit_folder(folder, new_file, tree)?;
+    }
+    Ok(allowance)
+}
+
+/// Like [`allowance`], for the limits of the top-level `folder` only.
+pub async fn folder_allowance(
+    folder: &str,
+    new_file: bool,
+    tree: &Arc<Mutex<Option<FileEntry>>>,
+) -> Result<Allowance, String> {
+    let (folder, tree) = (folder.to_string(), tree.clone());
+    tokio::task::spawn_blocking(move || {
+        let mut allowance = Allowance { bytes: u64::MAX, limited_by: String::new(), limits: Vec::new(), held: 0 };
+        allowance.limit_folder(&folder, new_file, &tree)?;
+        Ok(allowance)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+impl Allowance {
+    /// Holds the write to what `usage` leaves, or fails if it leaves nothing.
+    fn limit(&mut self, scope: Scope, committed: u64, usage: &QuotaUsage, what: &str, new_file: bool) -> Result<(), String> {
+        if let Some(files) = usage.files_limit.filter(|_| new_file && usage.files_full()) {
+            return Err(format!("Not enough space: {what} allows {files} files"));
+        }
+        match usage.bytes_left() {
+            Some(left) => self.add(scope, left, committed, what),
+            None => Ok(()),
+        }
+    }
+
+    fn limit_folder(&mut self, folder: &str, new_file: bool, tree: &Mutex<Option<FileEntry>>) -> Result<(), String> {
+        let scope = Scope::Folder(folder.to_string());
+        let committed = InFlight::get().committed(&scope);
+        let usage = {
+            let mut tree = tree.blocking_lock();
+            match tree.as_mut() {
+                Some(tree) => folder_usage(tree, folder),
+                None => return Ok(()),
+            }
+        };
+        self.limit(scope, committed, &usage, &format!("the quota of folder '{folder}'"), new_file)
+    }
+
+    /// Adds a limit of `left` bytes, lowering [`Allowance::bytes`] to what it leaves
+    /// after the writes in progress.
+    fn add(&mut self, scope: Scope, left: u64, committed_before: u64, what: &str) -> Result<(), String> {
+        let limit = Limit { scope, left, committed_before, what: what.to_string() };
+        let available = limit.left.saturating_sub(InFlight::get().unseen(&limit));
+        if available < self.bytes {
+            self.bytes = available;
+            self.limited_by = limit.what.clone();
+        }
+        self.limits.push(limit);
+        if self.bytes == 0 {
+            return Err(format!("Not enough space: {} is used up", self.limited_by));
+        }
+        Ok(())
+    }
+
+    /// Reserves `n` more bytes against every limit, failing with
+    /// `ErrorKind::QuotaExceeded` if one of them would be exceeded.
+    pub fn take(&mut self, n: u64) -> io::Result<()> {
+        let mut in_flight = InFlight::get();
+        if let Some(limit) = self.limits.iter().find(|limit| in_flight.unseen(limit) + n > limit.left) {
+            self.limited_by = limit.what.clone();
+            return Err(io::Error::new(io::ErrorKind::QuotaExceeded, self.exceeded()));
+        }
+        for limit in &self.limits {
+            *in_flight.reserved.entry(limit.scope.clone()).or_default() += n;
+        }
+        self.held += n;
+        Ok(())
+    }
+
+    /// Marks the bytes taken so far as stored: they now count as usage, and later
+    /// writes are measured with them.
+    pub fn commit(&mut self) {
+        let mut in_flight = InFlight::get();
+        for limit in &self.limits {
+            if let Some(reserved) = in_flight.reserved.get_mut(&limit.scope) {
+                *reserved = reserved.saturating_sub(self.held);
+            }
+            *in_flight.committed.entry(limit.scope.clone()).or_default() += self.held;
+        }
+        self.held = 0;
+    }
+
+    /// Gives back what was taken without storing it, e.g. when a write starts over.
+    pub fn release(&mut self) {
+        if self.held == 0 {
+            return;
+        }
+        let mut in_flight = InFlight::get();
+        for limit in &self.limits {
+            if let Some(reserved) = in_flight.reserved.get_mut(&limit.scope) {
+                *reserved = reserved.saturating_sub(self.held);
+            }
+        }
+        self.held = 0;
+    }
+
+    /// Message for a write that turned out larger than the allowance.
+    pub fn exceeded(&self) -> String {
+        format!("Not enough space: the upload is larger than what is left of {}", self.limited_by)
+    }
+}
+
+impl Drop for Allowance {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limited(folder: &str, left: u64) -> Allowance {
+        let scope = Scope::Folder(folder.to_string());
+        let committed = InFlight::get().committed(&scope);
+        let mut allowance = Allowance { bytes: u64::MAX, limited_by: String::new(), limits: Vec::new(), held: 0 };
+        allowance.add(scope, left, committed, "the test quota").unwrap();
+        allowance
+    }
+
+    #[test]
+    fn concurrent_writes_share_one_limit() {
+        let mut first = limited("reservation-test", 100);
+        let mut second = limited("reservation-test", 100);
+        first.take(60).unwrap();
+        assert_eq!(second.take(50).unwrap_err().kind(), io::ErrorKind::QuotaExceeded);
+        second.take(40).unwrap();
+
+        // Stored bytes keep counting for writes measured before they were stored
+        first.commit();
+        assert!(second.take(1).is_err());
+        // A failed write gives its bytes back
+        second.release();
+        second.take(40).unwrap();
+        drop(second);
+        assert_eq!(limited("reservation-test", 100).bytes, 100);
+    }
+}
@@ -0,0 +1,15 @@
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// A scratch share for unit tests, set as `FILE_DIR` for the whole test run.
+/// Tests work in their own subfolder of it, since they run concurrently.
+pub fn share_dir() -> PathBuf {
+    static DIR: OnceLock<PathBuf> = OnceLock::new();
+    DIR.get_or_init(|| {
+        let dir = std::env::temp_dir().join(format!("rustyshare-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create test share");
+        std::env::set_var("FILE_DIR", &dir);
+        dir
+    })
+    .clone()
+}
@@ -0,0 +1,518 @@
+use bytes::Bytes;
+use futures_core::Stream;
+use futures_util::StreamExt;
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::info;
+use super::checksum::Digests;
+use super::expiry;
+use super::file_tree::FileEntry;
+use super::file_types::{check_type, read_head};
+use super::filename::new_path;
+use super::file_utils::{data_dir, find_entry, free_name, safe_path, ConflictPolicy};
+use super::jobs::{Job, JobStatus};
+use super::quota::{allowance_blocking, top_folder, Allowance};
+use super::versions::save_version;
+
+const COPY_BUFFER_SIZE: usize = 128 * 1024;
+
+/// Moves `from` to `to` with a rename, falling back to copy + delete when the
+/// two paths are on different filesystems. An existing file at `to` is replaced.
+pub async fn move_path(from: PathBuf, to: PathBuf) -> io::Result<()> {
+    match tokio::fs::rename(&from, &to).await {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            tokio::task::spawn_blocking(move || move_across_devices(&from, &to))
+                .await
+                .map_err(io::Error::other)?
+        }
+        result => result,
+    }
+}
+
+/// Moves the file `from` to `to` without ever replacing a file at `to`: fails with
+/// `AlreadyExists` if one is there, even when it appeared a moment ago.
+/// The file is hard-linked into place, so the name is claimed atomically.
+pub async fn move_new_file(from: PathBuf, to: PathBuf) -> io::Result<()> {
+    tokio::task::spawn_blocking(move || link_new_file(&from, &to))
+        .await
+        .map_err(io::Error::other)?
+}
+
+/// [`move_new_file`] for code that already runs on a blocking thread.
+pub fn link_new_file(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::hard_link(from, to) {
+        Ok(()) => fs::remove_file(from),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(e),
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            let staging = part_path(to, "move");
+            let linked = fs::copy(from, &staging).and_then(|_| fs::hard_link(&staging, to));
+            let _ = fs::remove_file(&staging);
+            linked.and_then(|()| fs::remove_file(from))
+        }
+        // Filesystems without hard links: the check and the rename are two steps there
+        Err(_) if fs::symlink_metadata(to).is_ok() => Err(io::ErrorKind::AlreadyExists.into()),
+        Err(_) => fs::rename(from, to),
+    }
+}
+
+fn move_across_devices(from: &Path, to: &Path) -> io::Result<()> {
+    // Copy next to the destination first, so a failed copy never leaves a half-written target
+    let staging = part_path(to, "move");
+    let copied = copy_recursive(from, &staging).and_then(|()| fs::rename(&staging, to));
+    if let Err(e) = copied {
+        let _ = remove_path(&staging);
+        return Err(e);
+    }
+    remove_path(from)
+}
+
+/// Copies a file or folder tree, keeping modification times.
+/// Symlinks are recreated as links rather than followed.
+pub fn copy_recursive(from: &Path, to: &Path) -> io::Result<()> {
+    let meta = fs::symlink_metadata(from)?;
+    if meta.file_type().is_symlink() {
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(fs::read_link(from)?, to)?;
+        return Ok(());
+    }
+
+    if meta.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_recursive(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else {
+        fs::copy(from, to)?;
+    }
+    preserve_modified(to, &meta);
+    Ok(())
+}
+
+/// Gives `path` the modification time recorded in `meta`, on a best-effort basis.
+pub fn preserve_modified(path: &Path, meta: &fs::Metadata) {
+    if let Ok(modified) = meta.modified() {
+        let file = if meta.is_dir() { File::open(path) } else { File::options().write(true).open(path) };
+        if let Ok(file) = file {
+            let _ = file.set_modified(modified);
+        }
+    }
+}
+
+/// Removes a file, symlink or whole folder.
+pub fn remove_path(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) => Err(e),
+    }
+}
+
+/// Creates the folder `rel` and any missing parents one level at a time, so each
+/// level is checked by `safe_path` before anything is created in it.
+/// Returns a reason instead when something other than a folder is in the way.
+pub fn ensure_dir(rel: &str) -> io::Result<Option<String>> {
+    if rel.trim_matches('/').is_empty() {
+        return Ok(None);
+    }
+    let rel = match new_path(rel) {
+        Ok(rel) => rel,
+        Err(e) => return Ok(Some(e.to_string())),
+    };
+    let mut current = String::new();
+    for part in rel.split('/').filter(|part| !part.is_empty()) {
+        current = if current.is_empty() { part.to_string() } else { format!("{current}/{part}") };
+        let Ok(path) = safe_path(&current) else {
+            return Ok(Some("path escapes the shared folder".to_string()));
+        };
+        match fs::metadata(&path) {
+            Ok(meta) if meta.is_dir() => continue,
+            Ok(_) => return Ok(Some(format!("'{current}' exists and is not a folder"))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => fs::create_dir(&path)?,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(None)
+}
+
+/// Where a new file goes once conflicts have been resolved.
+pub enum Placement {
+    /// Write the file to `path`, known in the tree as `rel`. `replace` is set when a
+    /// file there is to be overwritten; otherwise the name must still be free.
+    Write { rel: String, path: PathBuf, replace: bool },
+    /// Leave the file out, for this reason.
+    Skip(String),
+}
+
+/// Decides where a new file at `rel` is written under `policy`.
+/// `reading` is the file the caller copies from; it is never replaced.
+pub fn place_file(rel: &str, policy: ConflictPolicy, reading: &str) -> io::Result<Placement> {
+    let rel = match new_path(rel) {
+        Ok(rel) => rel,
+        Err(e) => return Ok(Placement::Skip(e.to_string())),
+    };
+    let rel = rel.as_str();
+    let Ok(path) = safe_path(rel) else {
+        return Ok(Placement::Skip("path escapes the shared folder".to_string()));
+    };
+    let skip = |reason: &str| Ok(Placement::Skip(reason.to_string()));
+    match fs::symlink_metadata(&path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Placement::Write { rel: rel.to_string(), path, replace: false }),
+        Err(e) => Err(e),
+        Ok(meta) if meta.file_type().is_symlink() => skip("refusing to replace a symlink"),
+        Ok(meta) if meta.is_dir() => skip("a folder with this name exists"),
+        Ok(_) => match policy {
+            ConflictPolicy::Skip => skip("already exists"),
+            ConflictPolicy::Overwrite if rel == reading => skip("would replace the file being read"),
+            ConflictPolicy::Overwrite => Ok(Placement::Write { rel: rel.to_string(), path, replace: true }),
+            ConflictPolicy::Rename => {
+                let dir = path.parent().unwrap_or(Path::new("."));
+                let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+                let free = free_name(dir, name);
+                let rel = match rel.rsplit_once('/') {
+                    Some((parent, _)) => format!("{parent}/{free}"),
+                    None => free.clone(),
+                };
+                Ok(Placement::Write { rel, path: dir.join(free), replace: false })
+            }
+        },
+    }
+}
+
+/// Hidden file next to `path` that new content is written to before [`commit_part`].
+pub fn part_path(path: &Path, tag: impl Display) -> PathBuf {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("entry");
+    path.with_file_name(format!(".{name}.{tag}.part"))
+}
+
+/// Whether `name` is a [`part_path`] file, which only the server writes to.
+pub fn is_part_file(name: &str) -> bool {
+    name.starts_with('.') && name.ends_with(".part")
+}
+
+/// Folder where request bodies are received before they are moved into the share.
+pub fn staging_dir() -> PathBuf {
+    data_dir("tmp")
+}
+
+/// Returns a fresh file name in [`staging_dir`], creating the folder if needed.
+pub async fn staging_file(tag: &str) -> io::Result<PathBuf> {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let dir = staging_dir();
+    tokio::fs::create_dir_all(&dir).await?;
+    Ok(dir.join(format!("{tag}-{}.part", NEXT.fetch_add(1, Ordering::Relaxed))))
+}
+
+/// Writes a request body (or one multipart field) to `path`, feeding every chunk
+/// to `digests` and taking its bytes from `allowance`. A body that breaks off fails
+/// with `ErrorKind::ConnectionAborted`, one larger than the allowance with
+/// `ErrorKind::QuotaExceeded`.
+pub async fn receive_stream<S, E>(mut stream: S, path: &Path, digests: &mut Digests, allowance: &mut Allowance) -> io::Result<u64>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Display,
+{
+    let mut file = tokio::fs::File::create(path).await?;
+    let mut size = 0u64;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| io::Error::new(io::ErrorKind::ConnectionAborted, e.to_string()))?;
+        allowance.take(chunk.len() as u64)?;
+        size += chunk.len() as u64;
+        digests.update(&chunk);
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+    Ok(size)
+}
+
+/// Removes uploads left half-received by a restart.
+pub fn clear_staging() -> io::Result<()> {
+    match fs::remove_dir_all(staging_dir()) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Moves a finished part file to where [`place_file`] put `requested`. A file being
+/// replaced is kept as a version and swapped under its tree lock, so readers never
+/// see a partially written file. A new name is claimed without ever replacing a file
+/// (see [`move_new_file`]); if one appeared there meanwhile, `policy` decides again.
+/// Returns where the file ended up, or why it was left out (the part file is then
+/// removed). The new file expires as its folder's policy says, not when a replaced
+/// one would have.
+pub fn commit_part(
+    part: &Path,
+    requested: &str,
+    placement: Placement,
+    policy: ConflictPolicy,
+    reading: &str,
+    tree: &Mutex<Option<FileEntry>>,
+) -> io::Result<Placement> {
+    let mut placement = placement;
+    loop {
+        let (rel, path, replace) = match placement {
+            Placement::Write { rel, path, replace } => (rel, path, replace),
+            Placement::Skip(reason) => {
+                let _ = fs::remove_file(part);
+                return Ok(Placement::Skip(reason));
+            }
+        };
+        let committed = if replace {
+            let lock = {
+                let mut tree = tree.blocking_lock();
+                tree.as_mut().and_then(|tree| find_entry(tree, &rel)).map(|entry| entry.lock)
+            };
+            let _file_guard = lock.as_ref().map(|lock| lock.blocking_lock());
+            save_version(&path, &rel).and_then(|_| fs::rename(part, &path))
+        } else {
+            link_new_file(part, &path)
+        };
+        match committed {
+            Ok(()) => {
+                expiry::record(&rel, None);
+                return Ok(Placement::Write { rel, path, replace });
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && !replace => {
+                placement = place_file(requested, policy, reading).inspect_err(|_| {
+                    let _ = fs::remove_file(part);
+                })?;
+            }
+            Err(e) => {
+                let _ = fs::remove_file(part);
+                return Err(e);
+            }
+        }
+    }
+}
+
+/// Copies the file or folder `from` to `to` (both relative to the share), reporting
+/// progress through `job`. Existing folders are merged; existing files are handled
+/// by `policy`. Blocks the calling thread; run it with `spawn_blocking`.
+pub fn copy_tree(from: &str, to: &str, policy: ConflictPolicy, tree: &Mutex<Option<FileEntry>>, job: &Job) {
+    let mut files = 0;
+    let result = safe_path(from)
+        .map_err(|_| io::Error::other("Source is outside the shared folder"))
+        .and_then(|source| Ok((measure(&source)?, source.is_dir())))
+        .and_then(|((bytes, items), is_dir)| {
+            job.set_totals(bytes, items);
+            // A folder copied to the top level gets the quota of the folder it becomes
+            let quota_path = if is_dir { format!("{to}/_") } else { to.to_string() };
+            let allowance = allowance_blocking(None, top_folder(&quota_path), true, tree).map_err(io::Error::other)?;
+            if bytes > allowance.bytes {
+                return Err(io::Error::new(io::ErrorKind::QuotaExceeded, allowance.exceeded()));
+            }
+            let mut copier = Copier {
+                policy,
+                tree,
+                job,
+                allowance,
+                files: 0,
+                buf: vec![0u8; COPY_BUFFER_SIZE],
+            };
+            let copied = copier.copy(from, to);
+            files = copier.files;
+            copied
+        });
+
+    match result {
+        _ if job.is_cancelled() => job.finish(JobStatus::Cancelled, Some(format!("Cancelled after {files} file(s)"))),
+        Ok(()) => {
+            info!("📋 Copied {files} file(s) from '{from}' to '{to}'");
+            job.finish(JobStatus::Completed, Some(format!("Copied {files} file(s)")));
+        }
+        Err(e) => job.finish(JobStatus::Failed, Some(e.to_string())),
+    }
+}
+
+/// Counts the bytes and entries (files and folders, not links) below `path`.
+pub fn measure(path: &Path) -> io::Result<(u64, u64)> {
+    let meta = fs::symlink_metadata(path)?;
+    if meta.file_type().is_symlink() {
+        return Ok((0, 0));
+    }
+    if !meta.is_dir() {
+        return Ok((meta.len(), 1));
+    }
+    let mut totals = (0, 1);
+    for entry in fs::read_dir(path)? {
+        let (bytes, items) = measure(&entry?.path())?;
+        totals.0 += bytes;
+        totals.1 += items;
+    }
+    Ok(totals)
+}
+
+struct Copier<'a> {
+    policy: ConflictPolicy,
+    tree: &'a Mutex<Option<FileEntry>>,
+    job: &'a Job,
+    allowance: Allowance,
+    files: u64,
+    buf: Vec<u8>,
+}
+
+impl Copier<'_> {
+    fn copy(&mut self, from: &str, to: &str) -> io::Result<()> {
+        if self.job.is_cancelled() {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "Cancelled"));
+        }
+        let source = safe_path(from).map_err(|_| io::Error::other(format!("'{from}' is outside the shared folder")))?;
+        let meta = fs::metadata(&source)?;
+        if meta.is_dir() {
+            if let Some(reason) = ensure_dir(to)? {
+                self.job.skip(format!("{from}: {reason}"));
+                self.job.item_done();
+                return Ok(());
+            }
+            let mut names = fs::read_dir(&source)?
+                .map(|entry| entry.map(|entry| entry.file_name()))
+                .collect::<io::Result<Vec<_>>>()?;
+            names.sort();
+            for name in names {
+                let Some(name) = name.to_str() else {
+                    self.job.skip(format!("{from}/{}: name is not valid UTF-8", name.to_string_lossy()));
+                    continue;
+                };
+                // Links are left out before `safe_path` resolves them
+                if fs::symlink_metadata(source.join(name))?.file_type().is_symlink() {
+                    self.job.skip(format!("{from}/{name}: links are not copied"));
+                    continue;
+                }
+                self.copy(&format!("{from}/{name}"), &format!("{to}/{name}"))?;
+            }
+            if let Ok(dest) = safe_path(to) {
+                preserve_modified(&dest, &meta);
+            }
+        } else {
+            match place_file(to, self.policy, from)? {
+                Placement::Skip(reason) => self.job.skip(format!("{from}: {reason}")),
+                placement @ Placement::Write { .. } => match check_type(to, &read_head(&source)?) {
+                    Err(violation) => self.job.skip(format!("{from}: {violation}")),
+                    Ok(()) => match self.copy_file(from, &source, to, placement, &meta)? {
+                        Placement::Skip(reason) => self.job.skip(format!("{from}: {reason}")),
+                        Placement::Write { .. } => self.files += 1,
+                    },
+                },
+            }
+        }
+        self.job.item_done();
+        Ok(())
+    }
+
+    fn copy_file(&mut self, from: &str, source: &Path, to: &str, placement: Placement, meta: &fs::Metadata) -> io::Result<Placement> {
+        let Placement::Write { path: dest, .. } = &placement else {
+            return Ok(placement);
+        };
+        // Hold the source's tree lock while it is read
+        let lock = {
+            let mut tree = self.tree.blocking_lock();
+            tree.as_mut().and_then(|tree| find_entry(tree, from)).map(|entry| entry.lock)
+        };
+        let file_guard = lock.as_ref().map(|lock| lock.blocking_lock());
+
+        let part = part_path(dest, self.job.id());
+        let written = File::open(source).and_then(|mut reader| {
+            let mut out = File::create(&part)?;
+            loop {
+                if self.job.is_cancelled() {
+                    return Err(io::Error::new(io::ErrorKind::Interrupted, "Cancelled"));
+                }
+                let n = reader.read(&mut self.buf)?;
+                if n == 0 {
+                    break;
+                }
+                self.allowance.take(n as u64)?;
+                out.write_all(&self.buf[..n])?;
+                self.job.add_bytes(n as u64);
+            }
+            if let Ok(modified) = meta.modified() {
+                let _ = out.set_modified(modified);
+            }
+            Ok(())
+        });
+        // Let go of the source before the destination's lock is taken in `commit_part`:
+        // holding both would deadlock against a copy running the other way
+        drop(file_guard);
+        if let Err(e) = written {
+            let _ = fs::remove_file(&part);
+            return Err(e);
+        }
+        let placed = commit_part(&part, to, placement, self.policy, from, self.tree)?;
+        match placed {
+            Placement::Write { .. } => self.allowance.commit(),
+            Placement::Skip(_) => self.allowance.release(),
+        }
+        Ok(placed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_manager::metadata;
+    use crate::file_manager::test_support::share_dir;
+
+    #[test]
+    fn replaced_content_does_not_keep_the_old_expiry() {
+        let rel = "commit-expiry/a.txt";
+        let path = share_dir().join(rel);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "expiring").unwrap();
+        metadata::record_expiry(rel, Some("2000-01-01T00:00:00Z".to_string()));
+        let part = part_path(&path, "test");
+        fs::write(&part, "copied over it").unwrap();
+        let placement = place_file(rel, ConflictPolicy::Overwrite, "").unwrap();
+        commit_part(&part, rel, placement, ConflictPolicy::Overwrite, "", &Mutex::new(None)).unwrap();
+        assert_eq!(metadata::expiry_of(rel), None);
+    }
+
+    #[test]
+    fn a_file_that_appears_before_the_commit_is_not_replaced() {
+        let rel = "commit-race/a.txt";
+        let path = share_dir().join(rel);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let tree = Mutex::new(None);
+        for (policy, expected) in [(ConflictPolicy::Rename, Some("commit-race/a (1).txt")), (ConflictPolicy::Skip, None)] {
+            let _ = fs::remove_file(&path);
+            let placement = place_file(rel, policy, "").unwrap();
+            // An upload claims the name while the copy is still being written
+            fs::write(&path, "uploaded").unwrap();
+            let part = part_path(&path, "test");
+            fs::write(&part, "copied").unwrap();
+            let placed = commit_part(&part, rel, placement, policy, "", &tree).unwrap();
+
+            assert_eq!(fs::read_to_string(&path).unwrap(), "uploaded");
+            assert!(!part.exists());
+            match (placed, expected) {
+                (Placement::Write { rel, path, .. }, Some(expected)) => {
+                    assert_eq!(rel, expected);
+                    assert_eq!(fs::read_to_string(path).unwrap(), "copied");
+                }
+                (Placement::Skip(_), None) => {}
+                _ => panic!("unexpected placement under {policy:?}"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn new_files_never_replace_existing_ones() {
+        let dir = share_dir().join("move-new");
+        fs::create_dir_all(&dir).unwrap();
+        let (tmp, target) = (dir.join("upload.tmp"), dir.join("a.txt"));
+        fs::write(&tmp, "first").unwrap();
+        move_new_file(tmp.clone(), target.clone()).await.unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "first");
+        assert!(!tmp.exists());
+
+        fs::write(&tmp, "second").unwrap();
+        let err = move_new_file(tmp.clone(), target.clone()).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&target).unwrap(), "first");
+        assert!(tmp.exists());
+    }
+}
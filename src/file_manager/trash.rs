@@ -0,0 +1,218 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::{error, info};
+use super::file_utils::data_dir;
+use super::transfer::{measure, move_path, remove_path};
+use super::versions::{history_dir, merge_history, prune_all, take_history};
+use crate::utils::config::Config;
+
+/// How often the retention policy is applied.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Metadata file kept in each trash slot.
+const META_FILE: &str = "meta.json";
+/// Name the deleted file or folder is stored under inside its slot.
+const ITEM_NAME: &str = "item";
+/// Previous versions of the item, kept in its slot until it is restored or purged.
+const VERSIONS_NAME: &str = "versions";
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A deleted file or folder waiting in the trash.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashItem {
+    pub id: String,
+    pub name: String,
+    /// Where the item was in the share when it was deleted.
+    pub original_path: String,
+    pub is_dir: bool,
+    /// Total size in bytes, including everything inside a folder.
+    pub size: u64,
+    /// Client that deleted the item.
+    pub deleted_by: String,
+    pub deleted_at: String,
+}
+
+/// Folder holding one slot per trashed item: `{FILE_DIR}/.rustyshare/trash`.
+pub fn trash_dir() -> PathBuf {
+    data_dir("trash")
+}
+
+/// Moves the file or folder at `path` (known in the tree as `rel`) into the trash.
+pub async fn move_to_trash(path: PathBuf, rel: &str, deleted_by: &str) -> io::Result<TrashItem> {
+    let now = Utc::now();
+    let id = format!("{}-{}", now.timestamp_millis(), NEXT_ID.fetch_add(1, Ordering::Relaxed));
+    let measured = path.clone();
+    let (size, _) = tokio::task::spawn_blocking(move || measure(&measured))
+        .await
+        .map_err(io::Error::other)??;
+    let item = TrashItem {
+        name: rel.rsplit('/').next().unwrap_or(rel).to_string(),
+        original_path: rel.to_string(),
+        is_dir: fs::symlink_metadata(&path)?.is_dir(),
+        size,
+        deleted_by: deleted_by.to_string(),
+        deleted_at: now.to_rfc3339_opts(SecondsFormat::Millis, true),
+        id,
+    };
+
+    let slot = trash_dir().join(&item.id);
+    tokio::fs::create_dir_all(&slot).await?;
+    let moved = async {
+        tokio::fs::write(slot.join(META_FILE), serde_json::to_vec_pretty(&item)?).await?;
+        move_path(path, slot.join(ITEM_NAME)).await
+    };
+    if let Err(e) = moved.await {
+        let _ = tokio::fs::remove_dir_all(&slot).await;
+        return Err(e);
+    }
+    take_history(&history_dir(rel), &slot.join(VERSIONS_NAME))?;
+    Ok(item)
+}
+
+/// Lists the trash, most recently deleted first.
+/// Blocks the calling thread; run it with `spawn_blocking`.
+pub fn list_items() -> io::Result<Vec<TrashItem>> {
+    let entries = match fs::read_dir(trash_dir()) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut items: Vec<TrashItem> = entries
+        .flatten()
+        .filter_map(|entry| read_item(&entry.path()))
+        .collect();
+    items.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at));
+    Ok(items)
+}
+
+/// Returns the trashed item `id`, if there is one.
+pub fn get_item(id: &str) -> Option<TrashItem> {
+    slot_path(id).and_then(|slot| read_item(&slot))
+}
+
+/// Where the content of a trashed item is stored.
+pub fn item_path(item: &TrashItem) -> PathBuf {
+    trash_dir().join(&item.id).join(ITEM_NAME)
+}
+
+/// Moves a trashed item back into the share at `destination` (known in the tree
+/// as `rel`), together with its previous versions, and drops its slot. The versions
+/// of a file it replaces are kept alongside its own.
+pub async fn restore_item(item: &TrashItem, destination: PathBuf, rel: &str) -> io::Result<()> {
+    let slot = trash_dir().join(&item.id);
+    move_path(item_path(item), destination).await?;
+    merge_history(&slot.join(VERSIONS_NAME), rel)?;
+    tokio::fs::remove_dir_all(slot).await
+}
+
+/// Permanently deletes the trashed item `id` and its previous versions.
+/// Returns false when there is no such item.
+pub fn purge_item(id: &str) -> io::Result<bool> {
+    match slot_path(id) {
+        Some(slot) if slot.is_dir() => remove_path(&slot).map(|()| true),
+        _ => Ok(false),
+    }
+}
+
+/// Permanently deletes everything in the trash and returns how many items were removed.
+pub fn purge_all() -> io::Result<usize> {
+    let items = list_items()?;
+    for item in &items {
+        purge_item(&item.id)?;
+    }
+    Ok(items.len())
+}
+
+/// Applies the retention policy: items older than `max_age` go first, then the
+/// oldest items until the trash fits in `max_bytes`. Zero disables either limit.
+pub fn sweep(max_age: Duration, max_bytes: u64) -> io::Result<usize> {
+    let cutoff = (!max_age.is_zero())
+        .then(|| chrono::Duration::from_std(max_age).ok())
+        .flatten()
+        .map(|age| Utc::now() - age);
+    let mut kept_bytes = 0u64;
+    let mut purged = 0;
+    // Newest first, so the size limit keeps the most recent deletions
+    for item in list_items()? {
+        let deleted_at = DateTime::parse_from_rfc3339(&item.deleted_at).ok();
+        let expired = matches!((cutoff, deleted_at), (Some(cutoff), Some(at)) if at < cutoff);
+        let over_size = max_bytes > 0 && kept_bytes + item.size > max_bytes;
+        if expired || over_size {
+            purge_item(&item.id)?;
+            purged += 1;
+        } else {
+            kept_bytes += item.size;
+        }
+    }
+    Ok(purged)
+}
+
+/// Applies the retention policy from `TRASH_RETENTION_DAYS` / `TRASH_MAX_BYTES` every hour,
+/// and the version limits of each folder.
+pub async fn run_sweeper() {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let config = Config::from_env();
+        let max_age = Duration::from_secs(config.trash_retention_days() * 24 * 60 * 60);
+        let max_bytes = config.trash_max_bytes();
+        match tokio::task::spawn_blocking(move || sweep(max_age, max_bytes)).await {
+            Ok(Ok(0)) => {}
+            Ok(Ok(purged)) => info!("🧹 Purged {purged} item(s) from the trash"),
+            Ok(Err(e)) => error!("Trash sweep failed: {e}"),
+            Err(e) => error!("Trash sweep panicked: {e}"),
+        }
+        match tokio::task::spawn_blocking(prune_all).await {
+            Ok(Ok(0)) => {}
+            Ok(Ok(pruned)) => info!("🧹 Dropped {pruned} expired version(s)"),
+            Ok(Err(e)) => error!("Version sweep failed: {e}"),
+            Err(e) => error!("Version sweep panicked: {e}"),
+        }
+    }
+}
+
+/// Slot folder of item `id`; ids are generated by the server, anything else is refused.
+fn slot_path(id: &str) -> Option<PathBuf> {
+    let valid = !id.is_empty() && id.chars().all(|c| c.is_ascii_digit() || c == '-');
+    valid.then(|| trash_dir().join(id))
+}
+
+fn read_item(slot: &Path) -> Option<TrashItem> {
+    let item: TrashItem = serde_json::from_slice(&fs::read(slot.join(META_FILE)).ok()?).ok()?;
+    // A slot whose move never completed has nothing to restore
+    fs::symlink_metadata(slot.join(ITEM_NAME)).ok()?;
+    Some(item)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_manager::test_support::share_dir;
+    use crate::file_manager::versions::{list_versions, save_version};
+
+    #[tokio::test]
+    async fn restoring_over_a_file_keeps_both_histories() {
+        let rel = "trash-restore/a.txt";
+        let path = share_dir().join(rel);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "old").unwrap();
+        save_version(&path, rel).unwrap().unwrap();
+        let item = move_to_trash(path.clone(), rel, "test").await.unwrap();
+
+        fs::write(&path, "new").unwrap();
+        save_version(&path, rel).unwrap().unwrap();
+        fs::write(&path, "newer").unwrap();
+        // What a restore with `overwrite` does: the replaced file becomes a version first
+        save_version(&path, rel).unwrap().unwrap();
+        restore_item(&item, path.clone(), rel).await.unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+        assert_eq!(list_versions(rel).unwrap().len(), 3);
+        assert!(!trash_dir().join(&item.id).exists());
+    }
+}
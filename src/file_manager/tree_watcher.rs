@@ -1,29 +1,140 @@
+use notify::event::ModifyKind;
 use notify::{RecommendedWatcher, RecursiveMode, Watcher, EventKind};
-use std::sync::{Arc, mpsc::channel};
-use tokio::sync::{Mutex, broadcast};
-use crate::file_manager::{file_tree::scan_dir, file_tree::FileEntry};
-use std::path::Path;
-use tracing::{info, error};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use tokio::sync::{Mutex, broadcast, mpsc};
+use crate::file_manager::{file_tree::scan_dir, file_tree::FileEntry, file_utils::{get_file_size, get_modified_time, DATA_DIR}};
+use crate::file_manager::transfer::is_part_file;
+use crate::utils::config::Config;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{info, error, warn};
+
+/// Changes waiting between the notify thread and the tree; the thread waits when it is full.
+const QUEUE_LEN: usize = 4096;
+/// Changes applied one by one at most per batch; a bigger batch rescans the folders
+/// it touches instead.
+const MAX_BATCH: usize = 1024;
+
+/// What is announced on `tree_tx`.
+#[derive(Clone, Debug)]
+pub enum TreeNotice {
+    /// The server changed these paths itself; the watcher reads them into the tree.
+    Changed(TreeChanges),
+    /// The watcher has applied these changes to the tree.
+    Updated(TreeChanges),
+}
+
+/// Paths relative to the share that were created, modified or removed.
+/// A rescanned folder counts as modified; `""` is the share itself.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct TreeChanges {
+    pub created: Vec<String>,
+    pub modified: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl TreeChanges {
+    pub fn created(path: impl Into<String>) -> Self {
+        Self { created: vec![path.into()], ..Self::default() }
+    }
+
+    pub fn modified(path: impl Into<String>) -> Self {
+        Self { modified: vec![path.into()], ..Self::default() }
+    }
+
+    pub fn removed(paths: Vec<String>) -> Self {
+        Self { removed: paths, ..Self::default() }
+    }
+
+    pub fn moved(from: impl Into<String>, to: impl Into<String>) -> Self {
+        Self { created: vec![to.into()], removed: vec![from.into()], ..Self::default() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.created.is_empty() && self.modified.is_empty() && self.removed.is_empty()
+    }
+
+    /// Adds `other`, whose account of a path wins over the one already here.
+    fn merge(&mut self, other: TreeChanges) {
+        let known: BTreeSet<&String> = other.created.iter().chain(&other.modified).chain(&other.removed).collect();
+        for list in [&mut self.created, &mut self.modified, &mut self.removed] {
+            list.retain(|path| !known.contains(path));
+        }
+        self.created.extend(other.created);
+        self.modified.extend(other.modified);
+        self.removed.extend(other.removed);
+    }
+
+    /// The changes as the watcher applies them: whatever was created may be a folder to scan.
+    fn to_batch(&self) -> Vec<TreeChange> {
+        let mut batch = Vec::new();
+        if !self.created.is_empty() {
+            batch.push(TreeChange::Paths { paths: self.created.clone(), structural: true });
+        }
+        let paths: Vec<String> = self.modified.iter().chain(&self.removed).cloned().collect();
+        if !paths.is_empty() {
+            batch.push(TreeChange::Paths { paths, structural: false });
+        }
+        batch
+    }
+}
+
+/// A change the watcher saw, with paths relative to the watched directory.
+#[derive(Debug)]
+enum TreeChange {
+    /// Something at these paths was created, modified or removed. `structural` is set
+    /// for creations and renames, after which a folder's content has to be scanned.
+    Paths { paths: Vec<String>, structural: bool },
+    /// Events were lost: the folder (or the whole share, for `None`) must be rescanned.
+    Rescan(Option<String>),
+}
+
+/// What the disk says about a changed path, worked out before the tree is locked.
+enum Update {
+    /// Nothing is there (anymore).
+    Gone(String),
+    /// The entry, scanned with everything below it.
+    Entry(String, FileEntry),
+    /// A folder that only needs its own size and modification time refreshed.
+    Stat(String, Option<u64>, Option<String>),
+}
 
 /// Starts a background watcher on the media directory.
-/// 
-/// When a file or directory is created, modified, or removed,
-/// the entire media tree is rescanned and updated in memory.
-/// 
+///
+/// Events are gathered until none arrive for `WATCH_DEBOUNCE_MS`, or for at most
+/// `WATCH_MAX_LATENCY_MS`, and applied together, each path once. Files the server
+/// is still writing (`.part` files, `.rustyshare`) are ignored.
+///
+/// Created, modified and removed files and folders are applied to the in-memory
+/// tree one by one: the changed entry is (re)scanned, inserted or detached, and its
+/// parent folders get their size and modification time refreshed. When events were
+/// lost, or too many arrive at once, only the folders they touch are rescanned.
+/// Paths the server changed itself come as [`TreeNotice::Changed`] on `tree_tx` and
+/// are read into the tree the same way, picking up the hash and expiry recorded
+/// for them. Every applied batch is announced once as [`TreeNotice::Updated`], with
+/// the paths it created, modified and removed.
+///
 /// # Arguments
 /// * `files_tree` - Shared, mutable reference to the in-memory file tree.
 /// * `dir` - Path to the directory to watch.
 pub async fn start_watcher(
     tree: Arc<Mutex<Option<FileEntry>>>,
     dir: &str,
-    tree_tx: broadcast::Sender<()>,
+    tree_tx: broadcast::Sender<TreeNotice>,
 ) {
     let dir = dir.to_string();
     let dir_for_thread = dir.clone();
-    let (tx, rx) = channel();
+    let (tx, rx) = std::sync::mpsc::channel();
+    let (changes_tx, mut changes_rx) = mpsc::channel(QUEUE_LEN);
 
-    // Clone tree_tx for the thread
-    let tree_tx_thread = tree_tx.clone();
+    // Event paths start with the directory as given, or canonicalized on some platforms
+    let roots: Vec<PathBuf> = {
+        let canonical = Path::new(&dir).canonicalize().ok();
+        std::iter::once(PathBuf::from(&dir)).chain(canonical).collect()
+    };
 
     // Spawn the blocking watcher in a separate thread
     std::thread::spawn(move || {
@@ -42,34 +153,348 @@ pub async fn start_watcher(
 
         info!("📡 Watching media directory for changes...");
 
+        let relative = |path: &Path| {
+            roots
+                .iter()
+                .find_map(|root| path.strip_prefix(root).ok())
+                .map(|rel| rel.display().to_string())
+        };
         for res in rx {
-            if let Ok(event) = res {
-                if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)) {
-                    info!("🔄 Change detected: rescanning media directory...");
-                    // Instead of updating the tree here, just notify the async runtime
-                    if let Err(e) = tree_tx_thread.send(()) {
-                        error!("Failed to send tree update notification: {e}");
+            let change = match res {
+                Ok(event) if event.need_rescan() => {
+                    let dir = event.paths.first().and_then(|path| relative(path));
+                    TreeChange::Rescan(dir)
+                }
+                Ok(event) => {
+                    let structural = matches!(
+                        event.kind,
+                        EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(_) | ModifyKind::Any)
+                    );
+                    if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)) {
+                        continue;
+                    }
+                    // Changes to the server's own data (trash, uploads being received,
+                    // files being written) don't affect the tree
+                    let paths: Vec<String> = event
+                        .paths
+                        .iter()
+                        .filter(|path| !path.file_name().and_then(|name| name.to_str()).is_some_and(is_part_file))
+                        .filter_map(|path| relative(path))
+                        .filter(|rel| !Path::new(rel).starts_with(DATA_DIR))
+                        .collect();
+                    if paths.is_empty() {
+                        continue;
                     }
+                    TreeChange::Paths { paths, structural }
+                }
+                Err(e) => {
+                    error!("Watcher channel error: {:?}", e);
+                    continue;
                 }
-            } else if let Err(e) = res {
-                error!("Watcher channel error: {:?}", e);
+            };
+            if changes_tx.blocking_send(change).is_err() {
+                break;
             }
         }
     });
 
-    // Async task: listen for notifications and update the tree
+    // Async task: apply the watcher's and the server's changes to the tree
+    let root = PathBuf::from(&dir);
+    let config = Config::from_env();
+    let debounce = Duration::from_millis(config.watch_debounce_ms());
+    let max_latency = Duration::from_millis(config.watch_max_latency_ms());
     let mut rx = tree_tx.subscribe();
     loop {
-        if rx.recv().await.is_ok() {
-            let new_tree = scan_dir(Path::new(&dir), Path::new(&dir));
-            let mut tree_lock = tree.lock().await;
-            *tree_lock = new_tree;
-            info!("✅ Media tree updated.");
+        // What the server says it changed: handlers may have updated the tree themselves
+        let mut declared = TreeChanges::default();
+        let first = tokio::select! {
+            change = changes_rx.recv() => match change {
+                Some(change) => vec![change],
+                None => break,
+            },
+            notice = rx.recv() => match notice {
+                Ok(TreeNotice::Changed(changes)) => {
+                    declared = changes;
+                    declared.to_batch()
+                }
+                Ok(TreeNotice::Updated(_)) => continue,
+                // Changes made by the server were missed
+                Err(broadcast::error::RecvError::Lagged(_)) => vec![TreeChange::Rescan(None)],
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        };
+        let batch = gather_burst(first, &mut changes_rx, debounce, max_latency).await;
+        let mut changes = apply_changes(&tree, &root, batch).await.unwrap_or_default();
+        changes.merge(declared);
+        if !changes.is_empty() {
+            let _ = tree_tx.send(TreeNotice::Updated(changes));
         }
     }
 }
 
+/// Gathers the changes that follow `first` until none arrive for `debounce`, or
+/// until `max_latency` has passed since `first`.
+async fn gather_burst(
+    first: Vec<TreeChange>,
+    changes: &mut mpsc::Receiver<TreeChange>,
+    debounce: Duration,
+    max_latency: Duration,
+) -> Vec<TreeChange> {
+    let deadline = Instant::now() + max_latency;
+    let mut batch = first;
+    loop {
+        let wait = debounce.min(deadline.saturating_duration_since(Instant::now()));
+        match tokio::time::timeout(wait, changes.recv()).await {
+            Ok(Some(change)) => batch.push(change),
+            Ok(None) | Err(_) => return batch,
+        }
+    }
+}
 
+/// Applies a batch of changes to the tree, each path once. Returns what was applied, if anything.
+async fn apply_changes(tree: &Mutex<Option<FileEntry>>, root: &Path, batch: Vec<TreeChange>) -> Option<TreeChanges> {
+    let mut paths = BTreeSet::new();
+    let mut structural = BTreeSet::new();
+    let mut rescans = BTreeSet::new();
+    for change in batch {
+        match change {
+            TreeChange::Paths { paths: changed, structural: is_structural } => {
+                if is_structural {
+                    structural.extend(changed.iter().cloned());
+                }
+                paths.extend(changed);
+            }
+            TreeChange::Rescan(None) => {
+                warn!("Watcher lost events; rescanning the media directory");
+                rescans.insert(String::new());
+            }
+            TreeChange::Rescan(Some(dir)) => {
+                warn!("Watcher lost events; rescanning '{dir}'");
+                rescans.insert(dir);
+            }
+        }
+    }
+    if paths.len() > MAX_BATCH {
+        info!("🔄 {} changes at once: rescanning the folders they are in", paths.len());
+        rescans.extend(paths.iter().map(|path| parent_of(path).to_string()));
+        paths.clear();
+    }
+    if paths.is_empty() && rescans.is_empty() {
+        return None;
+    }
+    let mut applied = TreeChanges::default();
 
+    // Folders below another rescanned folder are covered by it
+    let mut covered: Vec<String> = Vec::new();
+    for dir in rescans {
+        if !covered.iter().any(|outer| is_within(&dir, outer)) {
+            covered.push(dir);
+        }
+    }
+    for dir in &covered {
+        if rescan(tree, root, dir).await {
+            applied.modified.push(dir.clone());
+        } else {
+            applied.removed.push(dir.clone());
+        }
+    }
+    let paths: Vec<String> = paths.into_iter().filter(|path| !covered.iter().any(|dir| is_within(path, dir))).collect();
+    if paths.is_empty() {
+        return Some(applied);
+    }
+    info!("🔄 Change detected: updating {} path(s) in the media tree", paths.len());
+
+    // Read the disk before taking the lock, so readers of the tree aren't held up
+    let (updates, parents) = {
+        let root = root.to_path_buf();
+        let task = tokio::task::spawn_blocking(move || {
+            let updates: Vec<Update> = paths.iter().map(|path| read_update(&root, path, structural.contains(path))).collect();
+            let parents = parent_stats(&root, &paths);
+            (updates, parents)
+        });
+        match task.await {
+            Ok(read) => read,
+            Err(e) => {
+                error!("Failed to read changes: {e}");
+                return None;
+            }
+        }
+    };
+
+    let mut orphans = Vec::new();
+    {
+        let mut tree_guard = tree.lock().await;
+        let tree = tree_guard.as_mut()?;
+        for update in updates {
+            match update {
+                Update::Gone(path) => {
+                    if tree.detach(&path).is_some() {
+                        applied.removed.push(path);
+                    }
+                }
+                Update::Entry(path, mut entry) => {
+                    if let Some(existing) = tree.entry_mut(&path) {
+                        entry.keep_locks(existing);
+                        *existing = entry;
+                        applied.modified.push(path);
+                    } else if tree.attach(parent_of(&path), entry) {
+                        applied.created.push(path);
+                    } else {
+                        orphans.push(path);
+                    }
+                }
+                Update::Stat(path, size, modified) => match tree.entry_mut(&path) {
+                    Some(dir) => {
+                        dir.size = size;
+                        dir.modified = modified;
+                        applied.modified.push(path);
+                    }
+                    None => orphans.push(path),
+                },
+            }
+        }
+        for (path, size, modified) in parents {
+            if let Some(dir) = tree.entry_mut(&path) {
+                dir.size = size;
+                dir.modified = modified;
+            }
+        }
+    }
+
+    // An entry whose folder isn't in the tree yet comes with the folder's rescan
+    for path in orphans {
+        let dir = {
+            let mut tree_guard = tree.lock().await;
+            let Some(tree) = tree_guard.as_mut() else { break };
+            let mut dir = parent_of(&path);
+            while tree.entry_mut(dir).is_none() {
+                dir = parent_of(dir);
+            }
+            dir.to_string()
+        };
+        rescan(tree, root, &dir).await;
+        applied.created.push(path);
+    }
+    Some(applied)
+}
 
+/// Looks at what is at `path` on disk now. Blocking.
+fn read_update(root: &Path, path: &str, structural: bool) -> Update {
+    let full = root.join(path);
+    match std::fs::metadata(&full) {
+        Ok(meta) if meta.is_dir() && !structural => Update::Stat(path.to_string(), get_file_size(&full), get_modified_time(&full)),
+        Ok(_) => match scan_dir(root, &full) {
+            Some(entry) => Update::Entry(path.to_string(), entry),
+            None => Update::Gone(path.to_string()),
+        },
+        Err(_) => Update::Gone(path.to_string()),
+    }
+}
 
+/// Size and modification time of every folder above `paths`, up to the share itself. Blocking.
+fn parent_stats(root: &Path, paths: &[String]) -> Vec<(String, Option<u64>, Option<String>)> {
+    let mut parents = BTreeSet::new();
+    for path in paths {
+        let mut dir = path.as_str();
+        while !dir.is_empty() {
+            dir = parent_of(dir);
+            parents.insert(dir);
+        }
+    }
+    parents
+        .into_iter()
+        .map(|dir| {
+            let full = root.join(dir);
+            (dir.to_string(), get_file_size(&full), get_modified_time(&full))
+        })
+        .collect()
+}
+
+/// Rescans the folder `dir` (`""` for the whole share) and puts it in the tree.
+/// Returns whether the folder is (still) there.
+async fn rescan(tree: &Mutex<Option<FileEntry>>, root: &Path, dir: &str) -> bool {
+    let scanned = {
+        let (root, full) = (root.to_path_buf(), root.join(dir));
+        tokio::task::spawn_blocking(move || scan_dir(&root, &full)).await.ok().flatten()
+    };
+    let mut tree_guard = tree.lock().await;
+    if dir.is_empty() {
+        let mut scanned = scanned;
+        if let (Some(scanned), Some(old)) = (scanned.as_mut(), tree_guard.as_ref()) {
+            scanned.keep_locks(old);
+        }
+        let found = scanned.is_some();
+        *tree_guard = scanned;
+        return found;
+    }
+    let Some(tree) = tree_guard.as_mut() else { return false };
+    match scanned {
+        Some(mut scanned) => {
+            if let Some(existing) = tree.entry_mut(dir) {
+                scanned.keep_locks(existing);
+                *existing = scanned;
+            } else {
+                tree.attach(parent_of(dir), scanned);
+            }
+            true
+        }
+        None => {
+            tree.detach(dir);
+            false
+        }
+    }
+}
+
+fn parent_of(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(parent, _)| parent)
+}
+
+fn is_within(path: &str, dir: &str) -> bool {
+    dir.is_empty() || path == dir || path.strip_prefix(dir).is_some_and(|rest| rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_manager::file_utils::find_entry;
+    use crate::file_manager::metadata;
+    use crate::file_manager::test_support::share_dir;
+
+    #[tokio::test]
+    async fn changes_sent_by_the_server_are_read_into_the_tree() {
+        let dir = share_dir().join("watcher-notices");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("expiring.txt"), "soon gone").unwrap();
+        let tree = Arc::new(Mutex::new(scan_dir(&dir, &dir)));
+        let (tree_tx, mut rx) = broadcast::channel(16);
+        tokio::spawn({
+            let (tree, dir, tree_tx) = (tree.clone(), dir.display().to_string(), tree_tx.clone());
+            async move { start_watcher(tree, &dir, tree_tx).await }
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        // Only the metadata changes, so nothing but the notice tells the watcher
+        metadata::record_expiry("expiring.txt", Some("2030-01-01T00:00:00Z".to_string()));
+        tree_tx.send(TreeNotice::Changed(TreeChanges::modified("expiring.txt"))).unwrap();
+        let updated = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let Ok(TreeNotice::Updated(changes)) = rx.recv().await {
+                    return changes;
+                }
+            }
+        });
+        let changes = updated.await.expect("the watcher announces the update");
+        assert_eq!(changes, TreeChanges::modified("expiring.txt"));
+
+        let entry = find_entry(tree.lock().await.as_mut().unwrap(), "expiring.txt").unwrap();
+        assert_eq!(entry.expires_at.as_deref(), Some("2030-01-01T00:00:00Z"));
+    }
+
+    #[test]
+    fn the_server_account_of_a_path_wins() {
+        // The watcher found the moved entry already in place and saw nothing at its old path
+        let mut seen = TreeChanges::modified("b.txt");
+        seen.merge(TreeChanges::moved("a.txt", "b.txt"));
+        assert_eq!(seen, TreeChanges::moved("a.txt", "b.txt"));
+    }
+}
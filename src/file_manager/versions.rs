@@ -0,0 +1,261 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+use super::file_utils::data_dir;
+use super::folder_policy::policy_for;
+use super::transfer::{preserve_modified, remove_path};
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A previous version of a file.
+#[derive(Debug, Clone, Serialize)]
+pub struct Version {
+    pub id: String,
+    pub size: u64,
+    /// Modification time of the file when this content was current.
+    pub modified: Option<String>,
+    /// When the content was replaced and became a version.
+    pub saved_at: Option<String>,
+}
+
+/// Folder holding the versions of the file at `rel`: `{FILE_DIR}/.rustyshare/versions/{rel}`.
+/// Versions are plain files named by id; the folder mirrors the tree, so the
+/// histories of everything inside a folder live below that folder's own.
+pub fn history_dir(rel: &str) -> PathBuf {
+    data_dir("versions").join(rel)
+}
+
+/// Keeps the current content of `path` (known in the tree as `rel`) as a version
+/// before it is replaced, then applies the folder's limits. Does nothing when
+/// versioning is off for the folder. Returns the new version's id.
+pub fn save_version(path: &Path, rel: &str) -> io::Result<Option<String>> {
+    let policy = policy_for(rel);
+    if policy.keep_versions() == 0 {
+        return Ok(None);
+    }
+    let meta = match fs::metadata(path) {
+        Ok(meta) if meta.is_file() => meta,
+        Ok(_) => return Ok(None),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let dir = history_dir(rel);
+    fs::create_dir_all(&dir)?;
+    let id = format!("{}-{}", Utc::now().timestamp_millis(), NEXT_ID.fetch_add(1, Ordering::Relaxed));
+    let version = dir.join(&id);
+    // Writers always replace files rather than rewrite them, so a hard link is a safe snapshot
+    if fs::hard_link(path, &version).is_err() {
+        fs::copy(path, &version)?;
+        preserve_modified(&version, &meta);
+    }
+    prune(rel)?;
+    Ok(Some(id))
+}
+
+/// Lists the versions of `rel`, newest first.
+pub fn list_versions(rel: &str) -> io::Result<Vec<Version>> {
+    let entries = match fs::read_dir(history_dir(rel)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut versions = Vec::new();
+    for entry in entries.flatten() {
+        let Some(id) = entry.file_name().to_str().filter(|id| is_version_id(id)).map(str::to_string) else {
+            continue;
+        };
+        let Ok(meta) = entry.metadata() else { continue };
+        if !meta.is_file() {
+            continue;
+        }
+        let saved_at = version_time(&id).map(|t| DateTime::<Utc>::from(t).to_rfc3339_opts(SecondsFormat::Millis, true));
+        versions.push(Version {
+            size: meta.len(),
+            modified: meta.modified().ok().map(|t| DateTime::<Utc>::from(t).to_rfc3339()),
+            saved_at,
+            id,
+        });
+    }
+    versions.sort_by_key(|v| std::cmp::Reverse(version_order(&v.id)));
+    Ok(versions)
+}
+
+/// Where version `id` of `rel` is stored, if it exists.
+pub fn version_path(rel: &str, id: &str) -> Option<PathBuf> {
+    let path = history_dir(rel).join(id);
+    (is_version_id(id) && path.is_file()).then_some(path)
+}
+
+/// Drops the versions of `rel` beyond the folder's count and age limits.
+/// Returns how many were dropped.
+pub fn prune(rel: &str) -> io::Result<usize> {
+    let policy = policy_for(rel);
+    let keep = policy.keep_versions() as usize;
+    let max_age = Duration::from_secs(policy.version_max_age_days() * 24 * 60 * 60);
+    let mut pruned = 0;
+    for (index, version) in list_versions(rel)?.into_iter().enumerate() {
+        let too_old = !max_age.is_zero()
+            && version_time(&version.id).is_some_and(|t| t.elapsed().is_ok_and(|age| age > max_age));
+        if index >= keep || too_old {
+            fs::remove_file(history_dir(rel).join(&version.id))?;
+            pruned += 1;
+        }
+    }
+    Ok(pruned)
+}
+
+/// Applies the count and age limits to every history, so versions also expire for
+/// files that are no longer written to. Returns how many versions were dropped.
+pub fn prune_all() -> io::Result<usize> {
+    fn walk(dir: &Path, rel: &str) -> io::Result<usize> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        let mut pruned = 0;
+        let mut has_versions = false;
+        for entry in entries.flatten() {
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else { continue };
+            match entry.file_type() {
+                Ok(kind) if kind.is_dir() => {
+                    let child = if rel.is_empty() { name } else { format!("{rel}/{name}") };
+                    pruned += walk(&entry.path(), &child)?;
+                }
+                Ok(kind) if kind.is_file() && is_version_id(&name) => has_versions = true,
+                _ => {}
+            }
+        }
+        if has_versions {
+            pruned += prune(rel)?;
+        }
+        Ok(pruned)
+    }
+    walk(&history_dir(""), "")
+}
+
+/// Moves the history of `from` (and of everything below it) to belong to `to`.
+/// Versions already kept for `to`, including the one saved for a file just replaced
+/// there, stay; the two histories are merged and then pruned.
+pub fn move_history(from: &str, to: &str) -> io::Result<()> {
+    merge_history(&history_dir(from), to)
+}
+
+/// Moves the history folder `source` (e.g. one kept in the trash) to belong to `to`,
+/// merged with the versions `to` already has, then prunes it.
+pub fn merge_history(source: &Path, to: &str) -> io::Result<()> {
+    let destination = history_dir(to);
+    if fs::symlink_metadata(&destination).is_err() {
+        return take_history(source, &destination);
+    }
+    merge_dir(source, &destination)?;
+    prune(to).map(drop)
+}
+
+/// Moves everything in `source` into `destination`, recursing into folders both have.
+/// A version already at the destination wins over one with the same id.
+fn merge_dir(source: &Path, destination: &Path) -> io::Result<()> {
+    let entries = match fs::read_dir(source) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    fs::create_dir_all(destination)?;
+    for entry in entries {
+        let entry = entry?;
+        let target = destination.join(entry.file_name());
+        match fs::symlink_metadata(&target) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => fs::rename(entry.path(), &target)?,
+            Err(e) => return Err(e),
+            Ok(meta) if meta.is_dir() && entry.file_type()?.is_dir() => merge_dir(&entry.path(), &target)?,
+            Ok(_) => {}
+        }
+    }
+    remove_path(source)
+}
+
+/// Moves a history folder to `destination`, e.g. into or out of the trash.
+pub fn take_history(source: &Path, destination: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(source).is_err() {
+        return Ok(());
+    }
+    if fs::symlink_metadata(destination).is_ok() {
+        remove_path(destination)?;
+    }
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(source, destination)
+}
+
+/// Permanently deletes the history of `rel` and everything below it.
+pub fn remove_history(rel: &str) -> io::Result<()> {
+    match remove_path(&history_dir(rel)) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+fn is_version_id(id: &str) -> bool {
+    id.split_once('-').is_some_and(|(millis, n)| {
+        !millis.is_empty() && !n.is_empty() && millis.chars().chain(n.chars()).all(|c| c.is_ascii_digit())
+    })
+}
+
+/// Ids sort by creation: the save time, then the counter for saves within one millisecond.
+fn version_order(id: &str) -> (u64, u64) {
+    let (millis, n) = id.split_once('-').unwrap_or((id, "0"));
+    (millis.parse().unwrap_or(0), n.parse().unwrap_or(0))
+}
+
+fn version_time(id: &str) -> Option<SystemTime> {
+    let (millis, _) = version_order(id);
+    SystemTime::UNIX_EPOCH.checked_add(Duration::from_millis(millis))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_manager::test_support::share_dir;
+
+    #[test]
+    fn moving_over_a_file_keeps_both_histories() {
+        let dir = share_dir().join("version-merge");
+        fs::create_dir_all(&dir).unwrap();
+        let (from, to) = ("version-merge/a.txt", "version-merge/b.txt");
+        for (rel, content) in [(from, "a1"), (to, "b1")] {
+            fs::write(share_dir().join(rel), content).unwrap();
+            save_version(&share_dir().join(rel), rel).unwrap().unwrap();
+        }
+        // What a move with overwrite does: the replaced file becomes a version, then the histories merge
+        let replaced = save_version(&share_dir().join(to), to).unwrap().unwrap();
+        move_history(from, to).unwrap();
+
+        let ids: Vec<String> = list_versions(to).unwrap().into_iter().map(|v| v.id).collect();
+        assert_eq!(ids.len(), 3);
+        assert_eq!(ids[0], replaced);
+        assert!(list_versions(from).unwrap().is_empty());
+        assert!(fs::symlink_metadata(history_dir(from)).is_err());
+    }
+
+    #[test]
+    fn prune_all_finds_nested_histories() {
+        let rel = "version-prune/deep/c.txt";
+        let path = share_dir().join(rel);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "c").unwrap();
+        // More versions than the default limit of five, written straight into the history
+        let dir = history_dir(rel);
+        fs::create_dir_all(&dir).unwrap();
+        for n in 0..8 {
+            fs::write(dir.join(format!("1000-{n}")), "old").unwrap();
+        }
+        assert!(prune_all().unwrap() >= 3);
+        assert_eq!(list_versions(rel).unwrap().len(), 5);
+    }
+}
@@ -2,7 +2,6 @@
 mod server;
 mod file_manager;
 mod utils;
-use tracing_subscriber;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::layer::SubscriberExt;
 use utils::config;
@@ -0,0 +1,106 @@
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use headers::{ETag, HeaderMapExt, IfMatch, IfModifiedSince, IfNoneMatch, IfRange, IfUnmodifiedSince, LastModified};
+use std::time::SystemTime;
+use crate::file_manager::checksum::sha256_hex;
+use crate::file_manager::file_utils::file_version_tag;
+use crate::file_manager::metadata;
+
+/// Validators describing the current version of a served resource.
+#[derive(Debug, Clone)]
+pub struct Validators {
+    pub etag: ETag,
+    pub last_modified: Option<SystemTime>,
+}
+
+/// Outcome of evaluating a request's conditional headers against [`Validators`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum Precondition {
+    /// The client already holds the current version (`304 Not Modified`).
+    NotModified,
+    /// Serve the resource. `use_range` is false when `If-Range` no longer matches,
+    /// in which case the full representation must be sent instead of a partial one.
+    Proceed { use_range: bool },
+}
+
+impl Validators {
+    /// Builds validators from an opaque tag (without quotes) and an optional modification time.
+    pub fn new(tag: &str, last_modified: Option<SystemTime>) -> Self {
+        let etag = format!("\"{tag}\"")
+            .parse::<ETag>()
+            .expect("entity tags are built from hex digits and dashes");
+        Self { etag, last_modified }
+    }
+
+    /// Builds validators for an in-memory body from its SHA-256, which stays the same
+    /// across restarts and toolchain upgrades.
+    pub fn for_bytes(body: &[u8], last_modified: Option<SystemTime>) -> Self {
+        Self::new(&sha256_hex(body), last_modified)
+    }
+
+    /// Builds validators for the file at `rel` from its metadata. The tag is the file's
+    /// recorded SHA-256 when there is one, so identical content has the same `ETag`.
+    pub fn for_file(rel: &str, meta: &std::fs::Metadata) -> Self {
+        let tag = metadata::hash_of(rel, meta).unwrap_or_else(|| file_version_tag(meta));
+        Self::new(&tag, meta.modified().ok())
+    }
+
+    fn last_modified_header(&self) -> Option<LastModified> {
+        self.last_modified.map(LastModified::from)
+    }
+
+    /// Evaluates `If-None-Match`, `If-Modified-Since` and `If-Range` for a GET or HEAD request.
+    pub fn evaluate(&self, headers: &HeaderMap) -> Precondition {
+        // If-None-Match takes precedence; If-Modified-Since is only consulted without it
+        let not_modified = match headers.typed_get::<IfNoneMatch>() {
+            Some(if_none_match) => !if_none_match.precondition_passes(&self.etag),
+            None => match (headers.typed_get::<IfModifiedSince>(), self.last_modified) {
+                (Some(since), Some(modified)) => !since.is_modified(modified),
+                _ => false,
+            },
+        };
+
+        if not_modified {
+            return Precondition::NotModified;
+        }
+
+        let use_range = headers
+            .typed_get::<IfRange>()
+            .is_none_or(|if_range| {
+                !if_range.is_modified(Some(&self.etag), self.last_modified_header().as_ref())
+            });
+
+        Precondition::Proceed { use_range }
+    }
+
+    /// Evaluates `If-Match` and `If-Unmodified-Since` for a request that changes the
+    /// resource. Returns false when the client's copy is stale (`412 Precondition Failed`).
+    pub fn write_allowed(&self, headers: &HeaderMap) -> bool {
+        // If-Unmodified-Since is only consulted without If-Match
+        match headers.typed_get::<IfMatch>() {
+            Some(if_match) => if_match.precondition_passes(&self.etag),
+            None => match (headers.typed_get::<IfUnmodifiedSince>(), self.last_modified) {
+                (Some(since), Some(modified)) => since.precondition_passes(modified),
+                _ => true,
+            },
+        }
+    }
+
+    /// Adds `ETag`, `Last-Modified` and a revalidating `Cache-Control` to a response.
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        headers.typed_insert(self.etag.clone());
+        if let Some(last_modified) = self.last_modified_header() {
+            headers.typed_insert(last_modified);
+        }
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    }
+
+    /// Builds the `304 Not Modified` response for these validators.
+    pub fn not_modified_response(&self) -> Response {
+        let mut response = StatusCode::NOT_MODIFIED.into_response();
+        self.apply(response.headers_mut());
+        response
+    }
+}
@@ -0,0 +1,46 @@
+use axum::http::HeaderValue;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+/// Characters allowed unescaped in an RFC 5987 `ext-value` (the `attr-char` set).
+const ATTR_CHAR: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
+
+/// Content-Security-Policy for user files that could run script (HTML, SVG, XML).
+/// `sandbox` gives the document an opaque origin and disables scripts, forms and plugins,
+/// so it cannot act with the user's session even when rendered inline.
+pub const SANDBOX_CSP: &str = "sandbox; default-src 'none'; img-src 'self' data:; media-src 'self'; style-src 'unsafe-inline'";
+
+/// How the browser should present a served file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Disposition {
+    Inline,
+    Attachment,
+}
+
+/// Builds a `Content-Disposition` value carrying both an ASCII fallback `filename`
+/// and the exact UTF-8 name as an RFC 5987 `filename*` parameter.
+pub fn content_disposition(disposition: Disposition, filename: &str) -> HeaderValue {
+    let kind = match disposition {
+        Disposition::Inline => "inline",
+        Disposition::Attachment => "attachment",
+    };
+    let fallback: String = filename
+        .chars()
+        .map(|c| if c.is_ascii_graphic() && c != '"' && c != '\\' || c == ' ' { c } else { '_' })
+        .collect();
+    let encoded = utf8_percent_encode(filename, ATTR_CHAR);
+
+    HeaderValue::from_str(&format!("{kind}; filename=\"{fallback}\"; filename*=UTF-8''{encoded}"))
+        .unwrap_or_else(|_| HeaderValue::from_static("attachment"))
+}
@@ -0,0 +1,8 @@
+pub mod streaming;
+pub mod conditional;
+pub mod disposition;
+
+// Re-export the helpers used by the file handlers
+pub use streaming::{build_range_response, build_slice_range_response, first_range, without_body};
+pub use conditional::{Validators, Precondition};
+pub use disposition::{content_disposition, Disposition, SANDBOX_CSP};
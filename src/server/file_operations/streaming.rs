@@ -0,0 +1,88 @@
+use axum::{
+    body::Body,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use headers::Range;
+use mime_guess::mime;
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt, SeekFrom},
+};
+use tokio_util::io::ReaderStream;
+
+/// Resolves the first satisfiable range of a `Range` header to inclusive byte offsets.
+pub fn first_range(range: &Range, size: u64) -> Option<(u64, u64)> {
+    let (start_bound, end_bound) = range.satisfiable_ranges(size).next()?;
+
+    let start = match start_bound {
+        std::ops::Bound::Included(s) => s,
+        std::ops::Bound::Excluded(s) => s + 1,
+        std::ops::Bound::Unbounded => 0,
+    };
+
+    let end = match end_bound {
+        std::ops::Bound::Included(e) => e,
+        std::ops::Bound::Excluded(e) => e.checked_sub(1)?,
+        std::ops::Bound::Unbounded => size.checked_sub(1)?,
+    };
+
+    if start > end || end >= size {
+        return None;
+    }
+    Some((start, end))
+}
+
+/// Streams the first satisfiable range of `file` as a `206 Partial Content` response.
+pub async fn build_range_response(
+    file: File,
+    file_size: u64,
+    mime: &mime::Mime,
+    range: Range,
+) -> Response {
+    build_slice_range_response(file, 0, file_size, mime, range).await
+}
+
+/// Like [`build_range_response`], for `len` bytes starting at `offset` of `file`
+/// (e.g. a member stored uncompressed inside an archive). Ranges are relative to the slice.
+pub async fn build_slice_range_response(
+    mut file: File,
+    offset: u64,
+    len: u64,
+    mime: &mime::Mime,
+    range: Range,
+) -> Response {
+    let Some((start, end)) = first_range(&range, len) else {
+        // No valid range found
+        return (StatusCode::RANGE_NOT_SATISFIABLE, "Invalid range").into_response();
+    };
+
+    let chunk_size = end - start + 1;
+
+    if file.seek(SeekFrom::Start(offset + start)).await.is_err() {
+        return (StatusCode::RANGE_NOT_SATISFIABLE, "Invalid range").into_response();
+    }
+
+    // Stream the requested chunk with a buffer size of 128KB
+    let stream = ReaderStream::with_capacity(file.take(chunk_size), 128 * 1024);
+
+    Response::builder()
+        .status(StatusCode::PARTIAL_CONTENT)
+        .header(header::CONTENT_TYPE, mime.as_ref())
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::CONTENT_LENGTH, chunk_size)
+        .header(
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", start, end, len),
+        )
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(Body::from_stream(stream))
+        .unwrap()
+}
+
+/// Drops the body of a response while keeping its status and headers,
+/// so `HEAD` requests report the same `Content-Length` as the matching `GET`.
+pub fn without_body(response: Response) -> Response {
+    let (parts, _) = response.into_parts();
+    Response::from_parts(parts, Body::empty())
+}
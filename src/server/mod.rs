@@ -1,3 +1,4 @@
 pub mod routing;
 pub mod startup;
+pub mod file_operations;
 pub use startup::start_server;
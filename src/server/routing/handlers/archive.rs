@@ -0,0 +1,401 @@
+use axum::{
+    body::Body,
+    extract::{Extension, Json, Query},
+    extract::Path as UrlPath,
+    http::{header, HeaderMap, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::TypedHeader;
+use headers::Range;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::{broadcast::Sender, Mutex};
+use tokio_util::io::ReaderStream;
+use tracing::{error, info};
+use crate::file_manager::{
+    archive::{
+        build_listing, collect_items, collect_items_as, list_members, method_for, unique_entry_name,
+        unpack_archive, write_archive, ArchiveFormat, ArchiveItem, ArchiveKind, CrcCache, ZipLayout,
+        ZipMethod,
+    },
+    file_tree::{FileEntry, FileType},
+    file_utils::{file_version_tag, find_entry, safe_path, ConflictPolicy},
+    filename::new_path,
+    jobs::JobRegistry,
+    transfer::ensure_dir,
+    tree_watcher::{TreeChanges, TreeNotice},
+};
+use crate::server::file_operations::{
+    content_disposition, first_range, without_body, Disposition, Precondition, Validators,
+};
+
+/// Size of the in-memory pipe between the archive writer and the response body.
+const ARCHIVE_PIPE_CAPACITY: usize = 256 * 1024;
+
+#[derive(Deserialize)]
+pub struct ArchiveQuery {
+    /// Folder to archive, relative to the shared directory (empty for the whole share).
+    #[serde(default)]
+    pub path: String,
+    /// `zip` (default), `tar`, `tar.gz` or `tar.zst`.
+    #[serde(default)]
+    pub format: ArchiveFormat,
+    /// `?store=1` forces an uncompressed, resumable ZIP even for compressible files.
+    #[serde(default)]
+    pub store: Option<String>,
+}
+
+impl ArchiveQuery {
+    fn wants_store(&self) -> bool {
+        matches!(self.store.as_deref(), Some("1" | "true" | "yes" | ""))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ExtractRequest {
+    /// Archive to unpack, as a `FileEntry` path.
+    pub path: String,
+    /// Destination folder; defaults to a folder named after the archive, next to it.
+    #[serde(default)]
+    pub target: Option<String>,
+    /// `skip` (default), `overwrite` or `rename` for files that already exist.
+    #[serde(default)]
+    pub conflict: ConflictPolicy,
+}
+
+#[derive(Deserialize)]
+pub struct SelectionArchiveRequest {
+    /// Files and folders to include, as `FileEntry` paths.
+    pub paths: Vec<String>,
+    #[serde(default)]
+    pub format: ArchiveFormat,
+    /// Download name without extension.
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+/// Streams a folder as an archive (ZIP by default) generated on the fly.
+/// Nothing is buffered beyond a small pipe, so archives of any size start immediately.
+///
+/// ZIPs whose members are all stored (media folders, or `?store=1`) are planned
+/// up front: they are byte-for-byte deterministic, carry `Content-Length` and an
+/// ETag, and honour `Range`/`If-Range` so interrupted downloads can resume.
+pub async fn archive_folder(
+    method: Method,
+    headers: HeaderMap,
+    range: Option<TypedHeader<Range>>,
+    Query(query): Query<ArchiveQuery>,
+    Extension(file_tree): Extension<Arc<Mutex<Option<FileEntry>>>>,
+    Extension(crcs): Extension<Arc<CrcCache>>,
+) -> Response {
+    let path = query.path.trim_matches('/').to_string();
+    if let Err(resp) = safe_path(&path) {
+        return resp;
+    }
+
+    let entry = {
+        let mut tree_guard = file_tree.lock().await;
+        let tree = tree_guard.as_mut().unwrap();
+        find_entry(tree, &path)
+    };
+    let Some(entry) = entry else {
+        return (StatusCode::NOT_FOUND, "Folder not found").into_response();
+    };
+
+    let mut items = Vec::new();
+    collect_items(&entry, "", &mut items);
+
+    let all_stored = items
+        .iter()
+        .all(|item| item.is_dir || method_for(Path::new(&item.rel_path)) == ZipMethod::Stored);
+    if query.format == ArchiveFormat::Zip && (all_stored || query.wants_store()) {
+        let layout = ZipLayout::plan(items).await;
+        info!("📦 Serving store-mode archive of {:?} ({} bytes)", entry.path, layout.total_len);
+        return serve_layout(method, &headers, range, layout, &entry.name, crcs);
+    }
+
+    info!("📦 Streaming archive of {:?} ({} entries)", entry.path, items.len());
+    stream_archive(items, Vec::new(), query.format, &entry.name)
+}
+
+/// Serves a planned store-mode ZIP, answering conditional and range requests.
+fn serve_layout(
+    method: Method,
+    headers: &HeaderMap,
+    range: Option<TypedHeader<Range>>,
+    layout: ZipLayout,
+    name: &str,
+    crcs: Arc<CrcCache>,
+) -> Response {
+    let total = layout.total_len;
+    let validators = Validators::new(&layout.tag, layout.last_modified);
+    let use_range = match validators.evaluate(headers) {
+        Precondition::NotModified => return validators.not_modified_response(),
+        Precondition::Proceed { use_range } => use_range,
+    };
+
+    let (status, start, end) = match range {
+        Some(TypedHeader(range)) if use_range => match first_range(&range, total) {
+            Some((start, end)) => (StatusCode::PARTIAL_CONTENT, start, end),
+            None => {
+                return Response::builder()
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(header::CONTENT_RANGE, format!("bytes */{total}"))
+                    .body(Body::from("Invalid range"))
+                    .unwrap();
+            }
+        },
+        _ => (StatusCode::OK, 0, total - 1),
+    };
+
+    let mut builder = Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, ArchiveFormat::Zip.mime_type())
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::CONTENT_LENGTH, end - start + 1)
+        .header(
+            header::CONTENT_DISPOSITION,
+            content_disposition(Disposition::Attachment, &format!("{name}.zip")),
+        );
+    if status == StatusCode::PARTIAL_CONTENT {
+        builder = builder.header(header::CONTENT_RANGE, format!("bytes {start}-{end}/{total}"));
+    }
+
+    let body = if method == Method::HEAD {
+        Body::empty()
+    } else {
+        let (mut writer, reader) = tokio::io::duplex(ARCHIVE_PIPE_CAPACITY);
+        tokio::spawn(async move {
+            if let Err(e) = layout.write_range(&crcs, start, end, &mut writer).await {
+                error!("Archive stream aborted: {e}");
+            }
+        });
+        Body::from_stream(ReaderStream::new(reader))
+    };
+
+    let mut response = builder.body(body).unwrap();
+    validators.apply(response.headers_mut());
+    response
+}
+
+/// Streams several selected files and folders as one archive.
+/// Entries that share a name are numbered (`photo (1).jpg`) so none overwrite each other,
+/// and paths already covered by a selected folder are not added twice.
+pub async fn archive_selection(
+    Extension(file_tree): Extension<Arc<Mutex<Option<FileEntry>>>>,
+    Json(request): Json<SelectionArchiveRequest>,
+) -> Response {
+    if request.paths.is_empty() {
+        return (StatusCode::BAD_REQUEST, "No paths selected").into_response();
+    }
+
+    let mut paths: Vec<String> = request
+        .paths
+        .iter()
+        .map(|p| p.trim_matches('/').to_string())
+        .collect();
+    paths.sort();
+    paths.dedup();
+    let covered = |path: &str, others: &[String]| {
+        others
+            .iter()
+            .any(|other| other != path && (other.is_empty() || path.starts_with(&format!("{other}/"))))
+    };
+    let selected: Vec<String> = paths
+        .iter()
+        .filter(|p| !covered(p, &paths))
+        .cloned()
+        .collect();
+
+    let mut items: Vec<ArchiveItem> = Vec::new();
+    let mut skipped = Vec::new();
+    let mut used_names = HashSet::new();
+    {
+        let mut tree_guard = file_tree.lock().await;
+        let tree = tree_guard.as_mut().unwrap();
+        for path in &selected {
+            if safe_path(path).is_err() {
+                skipped.push(format!("{path}: not a valid path"));
+                continue;
+            }
+            match find_entry(tree, path) {
+                Some(entry) => {
+                    let name = unique_entry_name(&entry.name, &mut used_names);
+                    collect_items_as(&entry, &name, "", &mut items);
+                }
+                None => skipped.push(format!("{path}: not found")),
+            }
+        }
+    }
+
+    let name = request
+        .name
+        .filter(|n| !n.trim().is_empty())
+        .unwrap_or_else(|| "rustyshare-selection".to_string());
+    info!("📦 Streaming selection archive {:?} ({} entries)", name, items.len());
+
+    stream_archive(items, skipped, request.format, &name)
+}
+
+/// Spawns the archive writer and returns a response streaming its output.
+fn stream_archive(
+    items: Vec<ArchiveItem>,
+    skipped: Vec<String>,
+    format: ArchiveFormat,
+    name: &str,
+) -> Response {
+    let (writer, reader) = tokio::io::duplex(ARCHIVE_PIPE_CAPACITY);
+    tokio::spawn(async move {
+        if let Err(e) = write_archive(items, skipped, format, writer).await {
+            error!("Archive stream aborted: {e}");
+        }
+    });
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, format.mime_type())
+        .header(
+            header::CONTENT_DISPOSITION,
+            content_disposition(
+                Disposition::Attachment,
+                &format!("{name}.{}", format.extension()),
+            ),
+        )
+        .body(Body::from_stream(ReaderStream::new(reader)))
+        .unwrap()
+}
+
+/// Lists the contents of a ZIP or TAR archive as a virtual folder tree shaped like
+/// `/api/master.json`. Member paths can be opened through the regular file route.
+pub async fn archive_entries(
+    method: Method,
+    UrlPath(path): UrlPath<String>,
+    headers: HeaderMap,
+    Extension(file_tree): Extension<Arc<Mutex<Option<FileEntry>>>>,
+) -> Response {
+    let entry = {
+        let mut tree_guard = file_tree.lock().await;
+        let tree = tree_guard.as_mut().unwrap();
+        find_entry(tree, &path)
+    };
+    let Some(entry) = entry else {
+        return (StatusCode::NOT_FOUND, "File not found").into_response();
+    };
+    if entry.is_dir || entry.file_type != FileType::Archive {
+        return (StatusCode::BAD_REQUEST, "Not a browsable archive").into_response();
+    }
+
+    let archive_path = match safe_path(&entry.path) {
+        Ok(p) => p,
+        Err(resp) => return resp,
+    };
+    let Some(kind) = ArchiveKind::from_path(&archive_path) else {
+        return (StatusCode::BAD_REQUEST, "Not a browsable archive").into_response();
+    };
+
+    let _file_guard = entry.lock.lock().await;
+    let meta = match tokio::fs::metadata(&archive_path).await {
+        Ok(meta) => meta,
+        Err(_) => return (StatusCode::NOT_FOUND, "File not found").into_response(),
+    };
+    let validators = Validators::new(&format!("{}-entries", file_version_tag(&meta)), meta.modified().ok());
+    if validators.evaluate(&headers) == Precondition::NotModified {
+        return validators.not_modified_response();
+    }
+
+    let members = match list_members(archive_path, kind).await {
+        Ok(members) => members,
+        Err(e) => return (StatusCode::UNPROCESSABLE_ENTITY, format!("Failed to read archive: {e}")).into_response(),
+    };
+    let listing = build_listing(&entry.path, &entry.name, &members);
+    let body = match serde_json::to_vec(&listing) {
+        Ok(body) => body,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to serialize listing: {e}")).into_response(),
+    };
+
+    let mut response = (
+        [
+            (header::CONTENT_TYPE, "application/json".to_string()),
+            (header::CONTENT_LENGTH, body.len().to_string()),
+        ],
+        body,
+    ).into_response();
+    validators.apply(response.headers_mut());
+
+    if method == Method::HEAD {
+        return without_body(response);
+    }
+    response
+}
+
+/// Starts a background job that unpacks a ZIP or TAR archive into a folder.
+/// Answers `202 Accepted` with the job; progress is available from `/api/jobs`
+/// and `/events/jobs`.
+pub async fn extract_archive(
+    Extension(file_tree): Extension<Arc<Mutex<Option<FileEntry>>>>,
+    Extension(jobs): Extension<Arc<JobRegistry>>,
+    Extension(tree_tx): Extension<Sender<TreeNotice>>,
+    Json(request): Json<ExtractRequest>,
+) -> Response {
+    let entry = {
+        let mut tree_guard = file_tree.lock().await;
+        let tree = tree_guard.as_mut().unwrap();
+        find_entry(tree, &request.path)
+    };
+    let Some(entry) = entry else {
+        return (StatusCode::NOT_FOUND, "File not found").into_response();
+    };
+    let archive_path = match safe_path(&entry.path) {
+        Ok(p) => p,
+        Err(resp) => return resp,
+    };
+    let Some(kind) = ArchiveKind::from_path(&archive_path).filter(|_| !entry.is_dir) else {
+        return (StatusCode::BAD_REQUEST, "Not an extractable archive").into_response();
+    };
+
+    let target = match request.target {
+        Some(target) => target.trim_matches('/').to_string(),
+        None => {
+            let stem = kind.stem(&entry.name);
+            match entry.path.rsplit_once('/') {
+                Some((parent, _)) => format!("{parent}/{stem}"),
+                None => stem.to_string(),
+            }
+        }
+    };
+    // The share itself is a valid target; anything else is held to the naming rules
+    let target = if target.is_empty() {
+        target
+    } else {
+        match new_path(&target) {
+            Ok(target) => target,
+            Err(e) => return e.into_response(),
+        }
+    };
+    // Each missing level is checked by `safe_path` as it is created
+    let created = {
+        let target = target.clone();
+        tokio::task::spawn_blocking(move || ensure_dir(&target)).await
+    };
+    match created {
+        Ok(Ok(None)) => {}
+        Ok(Ok(Some(reason))) => return (StatusCode::CONFLICT, reason).into_response(),
+        Ok(Err(e)) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create folder: {e}")).into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+
+    let description = format!("Extract {} into /{target}", entry.path);
+    let job = jobs.start("extract", description);
+    let snapshot = job.snapshot();
+    info!("📦 Job {}: extracting {} into '{target}'", snapshot.id, entry.path);
+
+    tokio::task::spawn_blocking(move || {
+        // The archive stays locked while it is being read
+        let _file_guard = entry.lock.blocking_lock();
+        unpack_archive(&archive_path, &entry.path, kind, &target, request.conflict, &file_tree, &job);
+        let _ = tree_tx.send(TreeNotice::Changed(TreeChanges::created(target)));
+    });
+
+    (StatusCode::ACCEPTED, Json(snapshot)).into_response()
+}
@@ -1,10 +1,12 @@
 use axum::{
-    extract::Form,
-    http::StatusCode,
+    extract::{ConnectInfo, Form, FromRequestParts},
+    http::{request::Parts, StatusCode},
     response::{Html, IntoResponse, Redirect, Json},
 };
 use tower_cookies::{Cookie, Cookies};
 use serde::Deserialize;
+use std::convert::Infallible;
+use std::net::SocketAddr;
 use crate::utils::config::Config;
 
 /// Form data for login requests.
@@ -15,7 +17,7 @@ pub struct LoginForm {
 
 /// Password-protected login route and create authentication cookie.
 pub async fn login(cookies: Cookies, Form(form): Form<LoginForm>) -> impl IntoResponse {
-    if form.password == Config::from_env().password().to_owned() {
+    if form.password == Config::from_env().password() {
         let mut cookie = Cookie::new("auth", "1");
         cookie.set_path("/");
         cookie.set_max_age(cookie::time::Duration::hours(12)); // 12 hours
@@ -47,3 +49,16 @@ pub async fn password_required() -> Json<bool> {
     let required = !Config::from_env().password().is_empty();
     Json(required)
 }
+
+/// Who is making a request. There are no user accounts, so clients are told
+/// apart by their IP address.
+pub struct Requester(pub String);
+
+impl<S: Send + Sync> FromRequestParts<S> for Requester {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let addr = parts.extensions.get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| addr.ip().to_string());
+        Ok(Requester(addr.unwrap_or_else(|| "unknown".to_string())))
+    }
+}
@@ -0,0 +1,180 @@
+use axum::{
+    body::to_bytes,
+    extract::{Extension, Json},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast::Sender, Mutex};
+use tracing::info;
+use crate::file_manager::expiry::{self, parse_ttl};
+use crate::file_manager::fetch::{download, name_from_url, parse_url};
+use crate::file_manager::file_tree::FileEntry;
+use crate::file_manager::tree_watcher::{TreeChanges, TreeNotice};
+use crate::file_manager::file_types::{check_type, read_head};
+use crate::file_manager::file_utils::{safe_path, CollisionPolicy};
+use crate::file_manager::filename::{check_name, clean_name, new_path};
+use crate::file_manager::folder_policy::policy_for;
+use crate::file_manager::jobs::{Job, JobRegistry, JobStatus};
+use crate::file_manager::metadata;
+use crate::file_manager::quota::{allowance, top_folder, Allowance};
+use crate::file_manager::transfer::staging_file;
+use crate::utils::config::Config;
+use super::auth::Requester;
+use super::file_operations::{place_upload, UploadOutcome, UploadResult};
+
+#[derive(Deserialize)]
+pub struct FetchRequest {
+    /// `http` or `https` URL to download.
+    pub url: String,
+    /// Folder to save the file in; the share itself by default.
+    #[serde(default)]
+    pub target: String,
+    /// Name to save the file under, instead of the one the server or URL suggests.
+    pub name: Option<String>,
+    /// What to do if the name is taken, as for uploads; the folder's policy by default.
+    pub on_collision: Option<CollisionPolicy>,
+    /// Time-to-live of the saved file (`90`, `30m`, `12h`, `7d`); the folder's by default.
+    pub ttl: Option<String>,
+}
+
+/// What a fetch job needs once the request has been checked.
+struct Fetch {
+    url: reqwest::Url,
+    target: String,
+    name: Option<String>,
+    on_collision: Option<CollisionPolicy>,
+    ttl: Option<Duration>,
+    user: String,
+    max_bytes: u64,
+    allowance: Allowance,
+    tmp: PathBuf,
+}
+
+/// Starts a background job downloading a URL into the share.
+/// The file is received into `.rustyshare/tmp` (resuming if the connection breaks),
+/// limited by `FETCH_MAX_BYTES` and the quotas, and only then placed in the target
+/// folder under the same name, type and collision rules as uploads.
+/// Answers `202 Accepted` with the job; progress is on `/events/jobs`.
+pub async fn fetch_url(
+    Requester(user): Requester,
+    Extension(file_tree): Extension<Arc<Mutex<Option<FileEntry>>>>,
+    Extension(jobs): Extension<Arc<JobRegistry>>,
+    Extension(tree_tx): Extension<Sender<TreeNotice>>,
+    Json(request): Json<FetchRequest>,
+) -> Response {
+    let url = match parse_url(&request.url) {
+        Ok(url) => url,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+    let target = request.target.trim_matches('/').to_string();
+    match safe_path(&target) {
+        Ok(path) if path.is_dir() => {}
+        Ok(_) => return (StatusCode::BAD_REQUEST, "Target folder does not exist").into_response(),
+        Err(resp) => return resp,
+    }
+    if let Some(Err(e)) = request.name.as_deref().map(check_name) {
+        return e.into_response();
+    }
+    let ttl = match request.ttl.as_deref().map(parse_ttl).transpose() {
+        Ok(ttl) => ttl,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+
+    let folder = top_folder(&format!("{target}/_")).map(str::to_string);
+    let allowance = match allowance(Some(&user), folder.as_deref(), true, &file_tree).await {
+        Ok(allowance) => allowance,
+        Err(message) => return (StatusCode::INSUFFICIENT_STORAGE, message).into_response(),
+    };
+    let max_bytes = Config::from_env().fetch_max_bytes();
+    let tmp = match staging_file("fetch").await {
+        Ok(tmp) => tmp,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to prepare download: {e}")).into_response(),
+    };
+
+    let job = jobs.start("fetch", format!("Fetch {url} into /{target}"));
+    let snapshot = job.snapshot();
+    info!("🌐 Job {}: fetching {url} into '{target}'", snapshot.id);
+    let fetch = Fetch {
+        url,
+        target,
+        name: request.name,
+        on_collision: request.on_collision,
+        ttl,
+        user,
+        max_bytes,
+        allowance,
+        tmp,
+    };
+    tokio::spawn(async move {
+        let tmp = fetch.tmp.clone();
+        let result = run_fetch(fetch, &job, &file_tree).await;
+        let _ = tokio::fs::remove_file(&tmp).await;
+        match result {
+            _ if job.is_cancelled() => job.finish(JobStatus::Cancelled, Some("Cancelled".to_string())),
+            Ok(placed) => {
+                let message = match placed.outcome {
+                    UploadOutcome::Skipped => format!("Already there as {}", placed.path),
+                    _ => format!("Saved as {}", placed.path),
+                };
+                job.item_done();
+                job.finish(JobStatus::Completed, Some(message));
+                let changes = match placed.outcome {
+                    UploadOutcome::Skipped => None,
+                    UploadOutcome::Replaced => Some(TreeChanges::modified(placed.path)),
+                    _ => Some(TreeChanges::created(placed.path)),
+                };
+                if let Some(changes) = changes {
+                    let _ = tree_tx.send(TreeNotice::Changed(changes));
+                }
+            }
+            Err(message) => job.finish(JobStatus::Failed, Some(message)),
+        }
+    });
+
+    (StatusCode::ACCEPTED, Json(snapshot)).into_response()
+}
+
+async fn run_fetch(mut fetch: Fetch, job: &Job, file_tree: &Mutex<Option<FileEntry>>) -> Result<UploadResult, String> {
+    let downloaded = download(&fetch.url, &fetch.tmp, fetch.max_bytes, &mut fetch.allowance, job)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let name = fetch
+        .name
+        .or(downloaded.suggested_name)
+        .unwrap_or_else(|| name_from_url(&fetch.url));
+    let name = clean_name(&name).map_err(|e| e.to_string())?;
+    let rel = if fetch.target.is_empty() { name } else { format!("{}/{name}", fetch.target) };
+    let rel = new_path(&rel).map_err(|e| e.to_string())?;
+    let head = read_head(&fetch.tmp).map_err(|e| e.to_string())?;
+    check_type(&rel, &head).map_err(|violation| violation.to_string())?;
+
+    let policy = fetch.on_collision.unwrap_or_else(|| policy_for(&rel).on_collision());
+    let placed = match place_upload(&rel, &fetch.tmp, policy, &downloaded.sha256, file_tree).await {
+        Ok(placed) => placed,
+        Err(resp) => return Err(response_message(resp).await),
+    };
+    if placed.outcome != UploadOutcome::Skipped {
+        if let Ok(Ok(meta)) = safe_path(&placed.path).map(std::fs::metadata) {
+            metadata::record_hash(&placed.path, &meta, downloaded.sha256);
+        }
+        metadata::record_owner(&placed.path, &fetch.user);
+        expiry::record(&placed.path, fetch.ttl);
+        fetch.allowance.commit();
+    }
+    info!("🌐 Fetched {} into '{}' ({} bytes)", fetch.url, placed.path, downloaded.size);
+    Ok(placed)
+}
+
+/// The text of an error response, for the job's message.
+async fn response_message(resp: Response) -> String {
+    let status = resp.status();
+    match to_bytes(resp.into_body(), 4096).await {
+        Ok(body) if !body.is_empty() => String::from_utf8_lossy(&body).into_owned(),
+        _ => status.to_string(),
+    }
+}
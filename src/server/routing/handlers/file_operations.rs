@@ -1,31 +1,121 @@
 use axum::{
     body::Body,
-    extract::{Extension, Path},
-    http::{header, StatusCode},
+    extract::{Extension, Path, Query},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
     response::{IntoResponse, Response, Json},
 };
 use axum_extra::extract::{Multipart, TypedHeader};
 use headers::Range;
 use tokio::{
     fs::File, 
+    io::{AsyncReadExt, AsyncSeekExt, SeekFrom},
     sync::Mutex,
-    io::{AsyncSeekExt, SeekFrom, AsyncReadExt},
-    sync::broadcast::Sender
+    sync::broadcast::{error::RecvError, Sender}
 };
 use tokio_util::io::ReaderStream;
+use std::path::PathBuf;
 use std::sync::Arc;
-use serde::Deserialize;
-use tracing::info;
-use mime_guess::mime;
+use serde::{de::IntoDeserializer, Deserialize, Serialize};
+use tracing::{info, warn};
 use axum::response::sse::{Sse, Event};
-use futures_util::stream;
 use std::convert::Infallible;
 use futures_core::Stream;
 use crate::file_manager::{file_tree::*, file_utils::*};
+use crate::file_manager::jobs::JobRegistry;
+use crate::file_manager::tree_watcher::{TreeChanges, TreeNotice};
+use crate::file_manager::metadata;
+use crate::file_manager::expiry::{self, parse_ttl, ttl_from_headers};
+use crate::file_manager::checksum::{parse_sha256, sha256_file, sha256_hex, Digests, ExpectedDigests};
+use crate::file_manager::file_types::{check_tree, check_type, read_head};
+use crate::file_manager::filename::{clean_name, new_path};
+use crate::file_manager::folder_policy::policy_for;
+use crate::file_manager::quota::{allowance, count, folder_allowance, folder_usage, top_folder, Allowance};
+use crate::file_manager::transfer::{copy_tree, move_new_file, move_path, receive_stream, staging_file};
+use crate::file_manager::trash::move_to_trash;
+use crate::file_manager::versions::{move_history, remove_history, save_version};
+use super::auth::Requester;
+use super::raw_upload::{checksum_mismatch, receive_error};
+use crate::file_manager::archive::{list_members, stream_member, ArchiveKind, MemberData};
+use crate::server::file_operations::{
+    build_range_response, build_slice_range_response, content_disposition, without_body, Disposition, Precondition, Validators,
+    SANDBOX_CSP,
+};
 
 #[derive(Deserialize)]
 pub struct DeleteRequest {
+    /// A single entry to delete; answered with a plain-text message.
+    #[serde(default)]
+    pub path: Option<String>,
+    /// Several entries to delete at once; answered with per-path JSON results.
+    #[serde(default)]
+    pub paths: Vec<String>,
+    /// Required to delete folders that are not empty.
+    #[serde(default)]
+    pub recursive: bool,
+    /// Delete right away instead of moving to the trash.
+    #[serde(default)]
+    pub permanent: bool,
+}
+
+/// How the entries of one delete request are removed.
+struct DeleteOptions {
+    permanent: bool,
+    /// Recorded in the trash.
+    deleted_by: String,
+}
+
+/// Outcome of deleting one path.
+#[derive(Serialize)]
+pub struct DeleteResult {
+    pub path: String,
+    /// HTTP status this path would have had on its own.
+    pub status: u16,
+    pub message: String,
+    /// Id of the trash item the entry became, for restoring it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trash_id: Option<String>,
+}
+
+/// What became of an upload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadOutcome {
+    Created,
+    /// An existing file was replaced and kept as a version.
+    Replaced,
+    /// The name was taken, so the file was saved under a numbered name.
+    Renamed,
+    /// A file with the same content was already there; nothing was written.
+    Skipped,
+}
+
+#[derive(Serialize)]
+pub struct UploadResult {
+    /// Where the file is in the share, which differs from the requested path after a rename.
     pub path: String,
+    pub outcome: UploadOutcome,
+}
+
+#[derive(Deserialize)]
+pub struct MoveRequest {
+    /// Entry to move or rename.
+    pub from: String,
+    /// New path, including the new name.
+    pub to: String,
+    /// Replace an existing file at `to`.
+    #[serde(default)]
+    pub overwrite: bool,
+}
+
+#[derive(Deserialize)]
+pub struct CopyRequest {
+    /// File or folder to copy.
+    pub from: String,
+    /// Path of the copy, including its name.
+    pub to: String,
+    /// `skip` (default), `overwrite` or `rename` for files that already exist.
+    #[serde(default)]
+    pub conflict: ConflictPolicy,
 }
 
 #[derive(Deserialize)]
@@ -33,99 +123,394 @@ pub struct CreateFolderRequest {
     pub path: String,
 }
 
+/// Query parameters accepted by the file route.
+#[derive(Deserialize, Default)]
+pub struct OpenQuery {
+    /// `?download=1` forces `Content-Disposition: attachment`.
+    #[serde(default)]
+    pub download: Option<String>,
+}
+
+impl OpenQuery {
+    fn wants_download(&self) -> bool {
+        matches!(self.download.as_deref(), Some("1" | "true" | "yes" | ""))
+    }
+}
+
 /// Returns the current file tree as JSON.
+/// Supports `If-None-Match` / `If-Modified-Since` revalidation against the serialized tree.
 pub async fn master_json(
-    Extension(file_tree): Extension<Arc<Mutex<Option<FileEntry>>>>) 
-    -> Json<Option<FileEntry>> {
-    let tree = file_tree.lock().await;
-    Json(tree.clone())
+    method: Method,
+    headers: HeaderMap,
+    Extension(file_tree): Extension<Arc<Mutex<Option<FileEntry>>>>,
+) -> Response {
+    let tree = file_tree.lock().await.clone();
+    let body = match serde_json::to_vec(&tree) {
+        Ok(body) => body,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to serialize tree: {e}")).into_response(),
+    };
+
+    let validators = Validators::for_bytes(&body, tree.as_ref().and_then(latest_modified));
+    if validators.evaluate(&headers) == Precondition::NotModified {
+        return validators.not_modified_response();
+    }
+
+    let mut response = (
+        [
+            (header::CONTENT_TYPE, "application/json".to_string()),
+            (header::CONTENT_LENGTH, body.len().to_string()),
+        ],
+        body,
+    ).into_response();
+    validators.apply(response.headers_mut());
+
+    if method == Method::HEAD {
+        return without_body(response);
+    }
+    response
 }
 
 /// Opens a file for browser viewing or streaming (supports range requests).
+/// Files are served inline only when the browser can preview them; `?download=1`
+/// always serves them as an attachment.
 pub async fn open(
+    method: Method,
     Path(path): Path<String>,
+    Query(query): Query<OpenQuery>,
     range: Option<TypedHeader<Range>>,
+    headers: HeaderMap,
     Extension(file_tree): Extension<Arc<Mutex<Option<FileEntry>>>>,
 ) -> Response {
-    let safe_path = match safe_path(&path) {
-        Ok(p) => p,
-        Err(resp) => return resp,
-    };
+    serve_file(method, path, query.wants_download(), range, headers, file_tree).await
+}
 
-    // Find the file entry in the in-memory tree
-    let entry_arc = {
+/// Serves a file as an attachment under its original filename.
+pub async fn download(
+    method: Method,
+    Path(path): Path<String>,
+    range: Option<TypedHeader<Range>>,
+    headers: HeaderMap,
+    Extension(file_tree): Extension<Arc<Mutex<Option<FileEntry>>>>,
+) -> Response {
+    serve_file(method, path, true, range, headers, file_tree).await
+}
+
+/// Streams a file from the tree (supports range requests).
+/// Sends `ETag`/`Last-Modified` and honours `If-None-Match`, `If-Modified-Since` and `If-Range`.
+/// `HEAD` requests get the same headers, including `Content-Length`, without a body.
+/// Paths leading into a ZIP or TAR archive (`bundle.zip/docs/a.pdf`) serve that member.
+async fn serve_file(
+    method: Method,
+    path: String,
+    force_download: bool,
+    range: Option<TypedHeader<Range>>,
+    headers: HeaderMap,
+    file_tree: Arc<Mutex<Option<FileEntry>>>,
+) -> Response {
+    // Find the file entry in the in-memory tree, or the archive the path leads into
+    let (entry, member) = {
         let mut tree_guard = file_tree.lock().await;
         let tree = tree_guard.as_mut().unwrap();
-        if let Some(entry) = find_entry(tree, &path) {
-            Some(entry.clone())
-        } else {
-            None
+        match find_entry(tree, &path) {
+            Some(entry) => (entry, None),
+            None => match find_archive_member(tree, &path) {
+                Some((entry, member)) => (entry, Some(member)),
+                None => return (StatusCode::NOT_FOUND, "File not found").into_response(),
+            },
         }
     };
 
-    if let Some(entry) = entry_arc {
-        // Acquire the file lock before reading
-        let _file_guard = entry.lock.lock().await;
-        // Now open the file as before
-        let file_path = safe_path.clone();
-        let mime = get_mime_type(&file_path);
-        let file = match File::open(&file_path).await {
-            Ok(f) => f,
-            Err(_) => return (StatusCode::NOT_FOUND, "File not found").into_response(),
-        };
-        let file_size = file_size(&file).await;
-        if let Some(TypedHeader(range)) = range {
-            return build_range_response(file, file_size, &mime, range).await;
-        } else {
-            let stream = ReaderStream::new(file);
+    let safe_path = match safe_path(&entry.path) {
+        Ok(p) => p,
+        Err(resp) => return resp,
+    };
+
+    if let Some(member) = member {
+        return serve_archive_member(method, entry, safe_path, member, force_download, range, headers).await;
+    }
+
+    // Acquire the file lock before reading
+    let _file_guard = entry.lock.lock().await;
+    let mime = get_mime_type(&safe_path);
+    let file = match File::open(&safe_path).await {
+        Ok(f) => f,
+        Err(_) => return (StatusCode::NOT_FOUND, "File not found").into_response(),
+    };
+    let meta = match file.metadata().await {
+        Ok(meta) => meta,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read file metadata: {e}")).into_response(),
+    };
+
+    let validators = Validators::for_file(&entry.path, &meta);
+    let use_range = match validators.evaluate(&headers) {
+        Precondition::NotModified => return validators.not_modified_response(),
+        Precondition::Proceed { use_range } => use_range,
+    };
+
+    let mut response = match range {
+        // A stale If-Range falls through to a full response of the current version
+        Some(TypedHeader(range)) if use_range => {
+            build_range_response(file, meta.len(), &mime, range).await
+        }
+        _ => {
+            let body = if method == Method::HEAD {
+                Body::empty()
+            } else {
+                Body::from_stream(ReaderStream::new(file))
+            };
             Response::builder()
                 .header(header::CONTENT_TYPE, mime.as_ref())
                 .header(header::ACCEPT_RANGES, "bytes")
-                .body(Body::from_stream(stream))
+                .header(header::CONTENT_LENGTH, meta.len())
+                .body(body)
                 .unwrap()
         }
-    } else {
+    };
+    if response.status().is_success() {
+        validators.apply(response.headers_mut());
+        apply_file_headers(&mut response, &safe_path, &entry.name, force_download);
+    }
+
+    if method == Method::HEAD {
+        return without_body(response);
+    }
+    response
+}
+
+/// Streams one member of an archive without extracting it.
+/// Members stored uncompressed are read straight from the archive file and support
+/// ranges; compressed members are decoded on the fly and always sent in full.
+async fn serve_archive_member(
+    method: Method,
+    archive: FileEntry,
+    archive_path: PathBuf,
+    member_name: String,
+    force_download: bool,
+    range: Option<TypedHeader<Range>>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(kind) = ArchiveKind::from_path(&archive_path) else {
         return (StatusCode::NOT_FOUND, "File not found").into_response();
+    };
+
+    // Hold the archive's lock until the member has been sent
+    let archive_guard = archive.lock.clone().lock_owned().await;
+    let meta = match tokio::fs::metadata(&archive_path).await {
+        Ok(meta) => meta,
+        Err(_) => return (StatusCode::NOT_FOUND, "File not found").into_response(),
+    };
+
+    // A member's version is its archive's version plus its name
+    let tag = format!("{}-{}", file_version_tag(&meta), &sha256_hex(member_name.as_bytes())[..16]);
+    let validators = Validators::new(&tag, meta.modified().ok());
+    let use_range = match validators.evaluate(&headers) {
+        Precondition::NotModified => return validators.not_modified_response(),
+        Precondition::Proceed { use_range } => use_range,
+    };
+
+    let members = match list_members(archive_path.clone(), kind).await {
+        Ok(members) => members,
+        Err(e) => return (StatusCode::UNPROCESSABLE_ENTITY, format!("Failed to read archive: {e}")).into_response(),
+    };
+    let Some(member) = members.into_iter().rev().find(|m| m.name == member_name && !m.is_dir) else {
+        return (StatusCode::NOT_FOUND, "File not found").into_response();
+    };
+
+    let member_path = PathBuf::from(&member.name);
+    let file_name = member.name.rsplit('/').next().unwrap_or(&member.name).to_string();
+    let mime = get_mime_type(&member_path);
+
+    let mut response = match member.data {
+        MemberData::Slice { offset } => {
+            let mut file = match File::open(&archive_path).await {
+                Ok(f) => f,
+                Err(_) => return (StatusCode::NOT_FOUND, "File not found").into_response(),
+            };
+            match range {
+                Some(TypedHeader(range)) if use_range => {
+                    build_slice_range_response(file, offset, member.size, &mime, range).await
+                }
+                _ => {
+                    let body = if method == Method::HEAD {
+                        Body::empty()
+                    } else if file.seek(SeekFrom::Start(offset)).await.is_ok() {
+                        Body::from_stream(ReaderStream::with_capacity(file.take(member.size), 128 * 1024))
+                    } else {
+                        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read archive").into_response();
+                    };
+                    Response::builder()
+                        .header(header::CONTENT_TYPE, mime.as_ref())
+                        .header(header::ACCEPT_RANGES, "bytes")
+                        .header(header::CONTENT_LENGTH, member.size)
+                        .body(body)
+                        .unwrap()
+                }
+            }
+        }
+        MemberData::Encoded => {
+            let size = member.size;
+            let body = if method == Method::HEAD {
+                Body::empty()
+            } else {
+                Body::from_stream(stream_member(archive_path, kind, member, archive_guard))
+            };
+            Response::builder()
+                .header(header::CONTENT_TYPE, mime.as_ref())
+                .header(header::ACCEPT_RANGES, "none")
+                .header(header::CONTENT_LENGTH, size)
+                .body(body)
+                .unwrap()
+        }
+    };
+    if response.status().is_success() {
+        validators.apply(response.headers_mut());
+        apply_file_headers(&mut response, &member_path, &file_name, force_download);
+    }
+
+    if method == Method::HEAD {
+        return without_body(response);
+    }
+    response
+}
+
+/// Sets `Content-Disposition` (and a sandbox CSP for active content) on a file response.
+fn apply_file_headers(response: &mut Response, path: &std::path::Path, name: &str, force_download: bool) {
+    // Inline viewing is limited to previewable types; active content only previews sandboxed
+    let disposition = if is_browser_supported(path) && !force_download {
+        Disposition::Inline
+    } else {
+        Disposition::Attachment
+    };
+    response.headers_mut().insert(
+        header::CONTENT_DISPOSITION,
+        content_disposition(disposition, name),
+    );
+    if is_active_content(path) {
+        response.headers_mut().insert(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static(SANDBOX_CSP),
+        );
     }
 }
 
 /// Handles file uploads via multipart form data.
+/// The file is streamed into `.rustyshare/tmp` while its SHA-256 is computed, and
+/// only moved into place when it matches the `sha256` field or `X-Content-SHA256`
+/// header, if the client sent one. The hash is recorded for the file.
+///
+/// When the name is taken, the `on_collision` field (or else the folder's policy)
+/// decides: `reject` (409), `overwrite`, `rename` or `skip_identical`. The answer
+/// tells where the file ended up. Quotas and the free-space reserve are checked
+/// before and while receiving (`507 Insufficient Storage`).
+///
+/// A `ttl` field or `X-Expires-In` header (`90`, `30m`, `12h`, `7d`) makes the file
+/// delete itself after that long; otherwise the folder's `ttl_hours` applies.
 pub async fn upload_file(
-    Extension(tree_tx): Extension<Sender<()>>,
+    Extension(file_tree): Extension<Arc<Mutex<Option<FileEntry>>>>,
+    Extension(tree_tx): Extension<Sender<TreeNotice>>,
+    Requester(user): Requester,
+    headers: HeaderMap,
     mut multipart: Multipart
 ) -> impl IntoResponse {
+    let mut expected = match ExpectedDigests::from_headers(&headers) {
+        Ok(expected) => expected,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
     let mut target_path: Option<String> = None;
-    let mut file_data: Option<(String, bytes::Bytes)> = None;
+    let mut on_collision: Option<CollisionPolicy> = None;
+    let mut ttl = match ttl_from_headers(&headers) {
+        Ok(ttl) => ttl,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+    let mut received: Option<(String, PathBuf, Digests, u64)> = None;
+    // Keeps the received bytes reserved against the quotas until the file is placed
+    let mut reserved: Option<Allowance> = None;
+    let mut quota_folder: Option<String> = None;
 
-    // Parse multipart fields for target path and file data
+    // Parse multipart fields for target path and file data; fields may come in any order
+    let discard = |received: Option<(String, PathBuf, Digests, u64)>| async move {
+        if let Some((_, tmp, _, _)) = received {
+            let _ = tokio::fs::remove_file(tmp).await;
+        }
+    };
     while let Some(field) = multipart.next_field().await.unwrap_or(None) {
-        if let Some(name) = field.name() {
-            if name == "target_path" {
+        match field.name() {
+            Some("target_path") => {
                 target_path = Some(field.text().await.unwrap_or_default());
                 info!("✅ Received target_path: {:?}", target_path);
-                continue;
             }
-
-            if name == "file" {
-                if let Some(filename) = field.file_name().map(|s| s.to_string()) {
-                    match field.bytes().await {
-                        Ok(data) => {
-                            file_data = Some((filename, data));
-                        }
-                        Err(e) => {
-                            eprintln!("Failed to read file bytes: {:?}", e);
-                            return (StatusCode::BAD_REQUEST, "Failed to read file content").into_response();
-                        }
+            Some("sha256") => match parse_sha256(&field.text().await.unwrap_or_default()) {
+                Ok(sha256) => expected.sha256 = Some(sha256),
+                Err(message) => {
+                    discard(received).await;
+                    return (StatusCode::BAD_REQUEST, message).into_response();
+                }
+            },
+            Some("ttl") => match parse_ttl(&field.text().await.unwrap_or_default()) {
+                Ok(requested) => ttl = Some(requested),
+                Err(message) => {
+                    discard(received).await;
+                    return (StatusCode::BAD_REQUEST, message).into_response();
+                }
+            },
+            Some("on_collision") => {
+                let value = field.text().await.unwrap_or_default();
+                let policy: Result<_, serde::de::value::Error> =
+                    CollisionPolicy::deserialize(value.trim().into_deserializer());
+                match policy {
+                    Ok(policy) => on_collision = Some(policy),
+                    Err(_) => {
+                        discard(received).await;
+                        let message = "on_collision must be reject, overwrite, rename or skip_identical";
+                        return (StatusCode::BAD_REQUEST, message).into_response();
                     }
                 }
             }
+            Some("file") if received.is_none() => {
+                let Some(filename) = field.file_name().map(clean_name) else { continue };
+                let filename = match filename {
+                    Ok(filename) => filename,
+                    Err(e) => {
+                        discard(received).await;
+                        return e.into_response();
+                    }
+                };
+                let tmp = match staging_file("upload").await {
+                    Ok(tmp) => tmp,
+                    Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to prepare upload: {e}")).into_response(),
+                };
+                // The target folder is only known here if its field came first
+                quota_folder = target_path.as_ref().and_then(|dir| top_folder(&format!("{dir}/{filename}")).map(str::to_string));
+                let mut allowance = match allowance(Some(&user), quota_folder.as_deref(), true, &file_tree).await {
+                    Ok(allowance) => allowance,
+                    Err(message) => return (StatusCode::INSUFFICIENT_STORAGE, message).into_response(),
+                };
+                let mut digests = Digests::new(&expected);
+                match receive_stream(field, &tmp, &mut digests, &mut allowance).await {
+                    Ok(size) => {
+                        received = Some((filename, tmp, digests, size));
+                        reserved = Some(allowance);
+                    }
+                    Err(e) => {
+                        let _ = tokio::fs::remove_file(&tmp).await;
+                        return receive_error(e, &allowance);
+                    }
+                }
+            }
+            _ => {}
         }
     }
 
-    // Make sure we got both parts
-    let (filename, data) = match file_data {
-        Some(pair) => pair,
-        None => return (StatusCode::BAD_REQUEST, "Missing file").into_response(),
+    // Make sure we got the file
+    let Some((filename, tmp, digests, size)) = received else {
+        return (StatusCode::BAD_REQUEST, "Missing file").into_response();
+    };
+    let sha256 = match digests.finish(&expected) {
+        Ok(sha256) => sha256,
+        Err(digest) => {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            return checksum_mismatch(digest);
+        }
     };
 
     // Build the relative path for the uploaded file
@@ -139,85 +524,336 @@ pub async fn upload_file(
         filename.clone()
     };
 
+    let rel_path = match new_path(&rel_path) {
+        Ok(rel_path) => rel_path,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            return e.into_response();
+        }
+    };
+
     info!("➡️ Final relative path: {}", rel_path);
 
-    let filepath = match safe_path(&rel_path) {
-        Ok(p) => p,
+    // Check the folder's quota now if it wasn't known while receiving
+    if let Some(folder) = top_folder(&rel_path).filter(|folder| quota_folder.as_deref() != Some(*folder)) {
+        let refused = match folder_allowance(folder, true, &file_tree).await {
+            Ok(allowance) if size > allowance.bytes => Some(allowance.exceeded()),
+            Ok(_) => None,
+            Err(message) => Some(message),
+        };
+        if let Some(message) = refused {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            return (StatusCode::INSUFFICIENT_STORAGE, message).into_response();
+        }
+    }
+
+    let head = {
+        let tmp = tmp.clone();
+        tokio::task::spawn_blocking(move || read_head(&tmp)).await
+    };
+    if let Err(violation) = check_type(&rel_path, head.ok().and_then(Result::ok).as_deref().unwrap_or_default()) {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return violation.into_response();
+    }
+
+    let policy = on_collision.unwrap_or_else(|| policy_for(&rel_path).on_collision());
+    let placed = place_upload(&rel_path, &tmp, policy, &sha256, &file_tree).await;
+    // Whatever was not moved into place is no longer needed
+    let _ = tokio::fs::remove_file(&tmp).await;
+    let placed = match placed {
+        Ok(placed) => placed,
         Err(resp) => return resp,
     };
+    if placed.outcome != UploadOutcome::Skipped {
+        let meta = match safe_path(&placed.path) {
+            Ok(filepath) => tokio::fs::metadata(filepath).await.ok(),
+            Err(_) => None,
+        };
+        if let Some(meta) = meta {
+            metadata::record_hash(&placed.path, &meta, sha256);
+        }
+        metadata::record_owner(&placed.path, &user);
+        expiry::record(&placed.path, ttl);
+        if let Some(mut reserved) = reserved {
+            reserved.commit();
+        }
+        // Once the hash is recorded, so the tree picks it up
+        let changes = match placed.outcome {
+            UploadOutcome::Replaced => TreeChanges::modified(placed.path.clone()),
+            _ => TreeChanges::created(placed.path.clone()),
+        };
+        let _ = tree_tx.send(TreeNotice::Changed(changes));
+    }
+
+    Json(placed).into_response()
+}
+
+/// Moves a received upload to `rel_path`, or next to it, as `policy` says.
+#[allow(clippy::result_large_err)]
+pub(super) async fn place_upload(
+    rel_path: &str,
+    tmp: &std::path::Path,
+    policy: CollisionPolicy,
+    sha256: &str,
+    file_tree: &Mutex<Option<FileEntry>>,
+) -> Result<UploadResult, Response> {
+    let filepath = safe_path(rel_path)?;
 
     info!("🧩 Resolved filesystem path: {:?}", filepath);
 
-    // Prevent overwriting existing files
-    if filepath.exists() {
-        return (StatusCode::CONFLICT, "File already exists").into_response();
+    // Ensure parent directory exists
+    let parent = filepath.parent().unwrap_or(std::path::Path::new("."));
+    if !parent.exists() {
+        return Err((StatusCode::BAD_REQUEST, "Target folder does not exist").into_response());
     }
 
-    // Ensure parent directory exists
-    if let Some(parent) = filepath.parent() {
-        if !parent.exists() {
-            return (StatusCode::BAD_REQUEST, "Target folder does not exist").into_response();
+    let existing = match tokio::fs::symlink_metadata(&filepath).await {
+        Ok(meta) if meta.is_file() => Some(meta),
+        Ok(_) => return Err((StatusCode::CONFLICT, "A folder or link with this name exists").into_response()),
+        Err(_) => None,
+    };
+    let (rel, outcome) = match (existing, policy) {
+        (None, _) => (rel_path.to_string(), UploadOutcome::Created),
+        (Some(_), CollisionPolicy::Reject) => {
+            return Err((StatusCode::CONFLICT, "File already exists").into_response());
         }
-    }
+        (Some(meta), CollisionPolicy::SkipIdentical) if same_content(rel_path, &filepath, &meta, sha256).await => {
+            info!("⏭️ '{rel_path}' is already there with the same content");
+            return Ok(UploadResult { path: rel_path.to_string(), outcome: UploadOutcome::Skipped });
+        }
+        (Some(_), CollisionPolicy::Rename | CollisionPolicy::SkipIdentical) => {
+            let name = filepath.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+            let free = free_name(parent, name);
+            let rel = match rel_path.rsplit_once('/') {
+                Some((dir, _)) => format!("{dir}/{free}"),
+                None => free,
+            };
+            (rel, UploadOutcome::Renamed)
+        }
+        (Some(_), CollisionPolicy::Overwrite) => (rel_path.to_string(), UploadOutcome::Replaced),
+    };
+    let filepath = safe_path(&rel)?;
 
-    // Write the file to disk
-    if let Err(e) = tokio::fs::write(&filepath, &data).await {
-        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save file: {e}")).into_response();
+    // Hold the lock of a file being replaced while it is swapped
+    let replaced_entry = match outcome {
+        UploadOutcome::Replaced => {
+            let mut tree_guard = file_tree.lock().await;
+            tree_guard.as_mut().and_then(|tree| find_entry(tree, &rel))
+        }
+        _ => None,
+    };
+    let _file_guard = match &replaced_entry {
+        Some(entry) => Some(entry.lock.lock().await),
+        None => None,
+    };
+    if outcome == UploadOutcome::Replaced {
+        if let Err(e) = save_version(&filepath, &rel) {
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to keep the previous version: {e}")).into_response());
+        }
     }
 
+    // Move the received file into place; only a replacement may take an existing name
+    let placed = match outcome {
+        UploadOutcome::Replaced => move_path(tmp.to_path_buf(), filepath).await,
+        _ => move_new_file(tmp.to_path_buf(), filepath).await,
+    };
+    match placed {
+        Ok(()) => Ok(UploadResult { path: rel, outcome }),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            Err((StatusCode::CONFLICT, "File already exists").into_response())
+        }
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save file: {e}")).into_response()),
+    }
+}
 
-    (StatusCode::OK, "File uploaded").into_response()
+/// Whether the file at `filepath` has the content hashed as `sha256`.
+/// Files without a recorded hash are hashed now, and the hash is kept.
+async fn same_content(rel: &str, filepath: &std::path::Path, meta: &std::fs::Metadata, sha256: &str) -> bool {
+    if let Some(known) = metadata::hash_of(rel, meta) {
+        return known == sha256;
+    }
+    let (rel, filepath, meta) = (rel.to_string(), filepath.to_path_buf(), meta.clone());
+    let hashed = tokio::task::spawn_blocking(move || {
+        let hash = sha256_file(&filepath).ok()?;
+        metadata::record_hash(&rel, &meta, hash.clone());
+        Some(hash)
+    });
+    matches!(hashed.await, Ok(Some(hash)) if hash == sha256)
 }
 
+/// Moves files and folders to the trash, or deletes them for good with `permanent: true`.
+/// Folders that are not empty need `recursive: true`.
+/// With `paths`, every entry is processed and the response lists a result per path.
+/// The locks of everything affected are taken before the first entry is removed.
 pub async fn delete_file(
+    Requester(deleted_by): Requester,
     Extension(file_tree): Extension<Arc<Mutex<Option<FileEntry>>>>,
+    Extension(tree_tx): Extension<Sender<TreeNotice>>,
     Json(payload): Json<DeleteRequest>,
 ) -> impl IntoResponse {
-    // Find the file entry in the in-memory tree before deleting
-    let entry_arc = {
+    let single = payload.path.is_some() && payload.paths.is_empty();
+    let paths: Vec<String> = payload.path.into_iter().chain(payload.paths).collect();
+    if paths.is_empty() {
+        return (StatusCode::BAD_REQUEST, "No path given").into_response();
+    }
+
+    let options = DeleteOptions {
+        permanent: payload.permanent,
+        deleted_by,
+    };
+    let results = delete_paths(paths, payload.recursive, &options, &file_tree).await;
+    let removed: Vec<String> = results
+        .iter()
+        .filter(|r| r.status == StatusCode::OK.as_u16())
+        .map(|r| r.path.trim_matches('/').to_string())
+        .collect();
+    if !removed.is_empty() {
+        let _ = tree_tx.send(TreeNotice::Changed(TreeChanges::removed(removed)));
+    }
+
+    if single {
+        let result = &results[0];
+        let status = StatusCode::from_u16(result.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        return (status, result.message.clone()).into_response();
+    }
+    Json(results).into_response()
+}
+
+/// Deletes `paths` and removes them from the tree, returning one result per path.
+async fn delete_paths(
+    paths: Vec<String>,
+    recursive: bool,
+    options: &DeleteOptions,
+    file_tree: &Arc<Mutex<Option<FileEntry>>>,
+) -> Vec<DeleteResult> {
+    let result = |path: &str, status: StatusCode, message: String| DeleteResult {
+        path: path.to_string(),
+        status: status.as_u16(),
+        message,
+        trash_id: None,
+    };
+
+    // Resolve every path against the tree before touching anything
+    let entries: Vec<Option<FileEntry>> = {
         let mut tree_guard = file_tree.lock().await;
         let tree = tree_guard.as_mut().unwrap();
-        // Clone the Arc to the entry if found
-        if let Some(entry) = find_entry(tree, &payload.path) {
-            Some(entry.clone())
-        } else {
-            None
-        }
+        paths.iter().map(|path| find_entry(tree, path)).collect()
     };
 
-    if let Some(entry) = entry_arc {
-        // Lock the file entry to prevent concurrent access
-        let _file_guard = entry.lock.lock().await;
+    let mut locks: Vec<(String, Arc<Mutex<()>>)> = entries.iter().flatten().flat_map(|e| e.subtree_locks()).collect();
+    locks.sort_by(|a, b| a.0.cmp(&b.0));
+    locks.dedup_by(|a, b| a.0 == b.0);
+    let mut _guards = Vec::with_capacity(locks.len());
+    for (_, lock) in locks {
+        _guards.push(lock.lock_owned().await);
+    }
 
-        let safe_path = match safe_path(&payload.path) {
+    let mut results = Vec::with_capacity(paths.len());
+    let mut deleted_dirs: Vec<String> = Vec::new();
+    for (path, entry) in paths.iter().zip(entries) {
+        let Some(entry) = entry else {
+            results.push(result(path, StatusCode::NOT_FOUND, "File not found in media tree".to_string()));
+            continue;
+        };
+        if entry.path.is_empty() {
+            results.push(result(path, StatusCode::BAD_REQUEST, "Cannot delete the shared folder itself".to_string()));
+            continue;
+        }
+        if let Some(dir) = deleted_dirs.iter().find(|dir| entry.path.starts_with(&format!("{dir}/"))) {
+            results.push(result(path, StatusCode::OK, format!("Deleted along with '{dir}'")));
+            continue;
+        }
+        let safe_path = match safe_path(&entry.path) {
             Ok(p) => p,
-            Err(resp) => return resp,
+            Err(resp) => {
+                results.push(result(path, resp.status(), "Path escape blocked".to_string()));
+                continue;
+            }
         };
 
-        if !safe_path.exists() {
-            return (StatusCode::NOT_FOUND, "File not found").into_response();
+        let meta = match tokio::fs::symlink_metadata(&safe_path).await {
+            Ok(meta) => meta,
+            Err(_) => {
+                results.push(result(path, StatusCode::NOT_FOUND, "File not found".to_string()));
+                continue;
+            }
+        };
+        if meta.is_dir() && !recursive {
+            let is_empty = match tokio::fs::read_dir(&safe_path).await {
+                Ok(mut dir) => matches!(dir.next_entry().await, Ok(None)),
+                Err(_) => false,
+            };
+            if !is_empty {
+                let message = "Folder is not empty; set recursive to delete it".to_string();
+                results.push(result(path, StatusCode::CONFLICT, message));
+                continue;
+            }
         }
 
-        if let Err(e) = tokio::fs::remove_file(&safe_path).await {
-            return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to delete file: {e}")).into_response();
-        }
+        let removed = if options.permanent {
+            let removed = if meta.is_dir() {
+                tokio::fs::remove_dir_all(&safe_path).await
+            } else {
+                tokio::fs::remove_file(&safe_path).await
+            };
+            removed.and_then(|()| remove_history(&entry.path)).map(|()| None)
+        } else {
+            move_to_trash(safe_path, &entry.path, &options.deleted_by).await.map(Some)
+        };
 
-        (StatusCode::OK, "File deleted").into_response()
-    } else {
-        (StatusCode::NOT_FOUND, "File not found in media tree").into_response()
+        match removed {
+            Ok(trashed) => {
+                info!("🗑️ Deleted '{}'", entry.path);
+                metadata::remove(&entry.path);
+                if let Some(tree) = file_tree.lock().await.as_mut() {
+                    tree.detach(&entry.path);
+                }
+                if entry.is_dir {
+                    deleted_dirs.push(entry.path.clone());
+                }
+                let message = match (&trashed, entry.is_dir) {
+                    (Some(_), _) => "Moved to trash",
+                    (None, true) => "Folder deleted",
+                    (None, false) => "File deleted",
+                };
+                let mut deleted = result(path, StatusCode::OK, message.to_string());
+                deleted.trash_id = trashed.map(|item| item.id);
+                results.push(deleted);
+            }
+            Err(e) => {
+                let message = format!("Failed to delete: {e}");
+                results.push(result(path, StatusCode::INTERNAL_SERVER_ERROR, message));
+            }
+        }
     }
+    results
 }
 
 /// Handles file updates (replacement) via multipart form data.
-/// Updates the in-memory file tree metadata after writing.
+/// The new content is received into `.rustyshare/tmp` and renamed over the file
+/// under its lock once complete, then the in-memory file tree metadata is updated.
 #[axum::debug_handler]
 pub async fn update_file(
     Extension(file_tree): Extension<Arc<Mutex<Option<FileEntry>>>>,
+    Extension(tree_tx): Extension<Sender<TreeNotice>>,
+    Requester(user): Requester,
+    headers: HeaderMap,
     mut multipart: Multipart,
 ) -> impl IntoResponse {
+    let mut expected = match ExpectedDigests::from_headers(&headers) {
+        Ok(expected) => expected,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
     let mut replace_path: Option<String> = None;
-    let mut file_bytes: Option<bytes::Bytes> = None;
+    let mut received: Option<(std::path::PathBuf, Digests, u64)> = None;
+    let mut reserved: Option<Allowance> = None;
     let mut uploaded_ext: Option<String> = None;
+    let mut quota_folder: Option<String> = None;
+    let discard = |received: Option<(std::path::PathBuf, Digests, u64)>| async move {
+        if let Some((tmp, ..)) = received {
+            let _ = tokio::fs::remove_file(&tmp).await;
+        }
+    };
 
     // Parse multipart fields for path and file
     while let Some(field) = multipart.next_field().await.unwrap_or(None) {
@@ -225,7 +861,14 @@ pub async fn update_file(
             Some("replace_path") => {
                 replace_path = Some(field.text().await.unwrap_or_default());
             }
-            _ if field.file_name().is_some() => {
+            Some("sha256") => match parse_sha256(&field.text().await.unwrap_or_default()) {
+                Ok(sha256) => expected.sha256 = Some(sha256),
+                Err(message) => {
+                    discard(received).await;
+                    return (StatusCode::BAD_REQUEST, message).into_response();
+                }
+            },
+            _ if field.file_name().is_some() && received.is_none() => {
                 // Get the uploaded file's extension
                 if let Some(fname) = field.file_name() {
                     uploaded_ext = std::path::Path::new(fname)
@@ -233,11 +876,25 @@ pub async fn update_file(
                         .and_then(|e| e.to_str())
                         .map(|s| s.to_lowercase());
                 }
-                match field.bytes().await {
-                    Ok(data) => file_bytes = Some(data),
+                let tmp = match staging_file("update").await {
+                    Ok(tmp) => tmp,
+                    Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to prepare upload: {e}")).into_response(),
+                };
+                // The file's folder is only known here if its field came first
+                quota_folder = replace_path.as_deref().and_then(top_folder).map(str::to_string);
+                let mut allowance = match allowance(Some(&user), quota_folder.as_deref(), false, &file_tree).await {
+                    Ok(allowance) => allowance,
+                    Err(message) => return (StatusCode::INSUFFICIENT_STORAGE, message).into_response(),
+                };
+                let mut digests = Digests::new(&expected);
+                match receive_stream(field, &tmp, &mut digests, &mut allowance).await {
+                    Ok(size) => {
+                        received = Some((tmp, digests, size));
+                        reserved = Some(allowance);
+                    }
                     Err(e) => {
-                        eprintln!("Failed to read file bytes: {:?}", e);
-                        return (StatusCode::BAD_REQUEST, "Failed to read file").into_response();
+                        let _ = tokio::fs::remove_file(&tmp).await;
+                        return receive_error(e, &allowance);
                     }
                 }
             }
@@ -246,13 +903,59 @@ pub async fn update_file(
     }
 
     // Ensure we have both the path and file data
-    let (rp, data) = match (replace_path, file_bytes) {
-        (Some(rp), Some(data)) => (rp, data),
-        _ => return (StatusCode::BAD_REQUEST, "Missing file or path").into_response(),
+    let (rp, (tmp, digests, size)) = match (replace_path, received) {
+        (Some(rp), Some(received)) => (rp, received),
+        (_, received) => {
+            discard(received).await;
+            return (StatusCode::BAD_REQUEST, "Missing file or path").into_response();
+        }
     };
+    let response = replace_with(&rp, &tmp, digests, size, &expected, uploaded_ext, quota_folder, &file_tree, &tree_tx).await;
+    // Whatever was not moved into place is no longer needed
+    let _ = tokio::fs::remove_file(&tmp).await;
+    if let Some(mut reserved) = reserved.filter(|_| response.status().is_success()) {
+        reserved.commit();
+    }
+    response
+}
+
+/// Checks a received update of `rp` in `tmp` and swaps it in for the file.
+#[allow(clippy::too_many_arguments)]
+async fn replace_with(
+    rp: &str,
+    tmp: &std::path::Path,
+    digests: Digests,
+    size: u64,
+    expected: &ExpectedDigests,
+    uploaded_ext: Option<String>,
+    quota_folder: Option<String>,
+    file_tree: &Arc<Mutex<Option<FileEntry>>>,
+    tree_tx: &Sender<TreeNotice>,
+) -> Response {
+    let sha256 = match digests.finish(expected) {
+        Ok(sha256) => sha256,
+        Err(digest) => return checksum_mismatch(digest),
+    };
+    let head = {
+        let tmp = tmp.to_path_buf();
+        tokio::task::spawn_blocking(move || read_head(&tmp)).await
+    };
+    if let Err(violation) = check_type(rp, head.ok().and_then(Result::ok).as_deref().unwrap_or_default()) {
+        return violation.into_response();
+    }
+    // Check the folder's quota now if it wasn't known while receiving
+    if let Some(folder) = top_folder(rp).filter(|folder| quota_folder.as_deref() != Some(*folder)) {
+        match folder_allowance(folder, false, file_tree).await {
+            Ok(allowance) if size > allowance.bytes => {
+                return (StatusCode::INSUFFICIENT_STORAGE, allowance.exceeded()).into_response();
+            }
+            Ok(_) => {}
+            Err(message) => return (StatusCode::INSUFFICIENT_STORAGE, message).into_response(),
+        }
+    }
 
     // Check that the file extension matches the original
-    let orig_ext = std::path::Path::new(&rp)
+    let orig_ext = std::path::Path::new(rp)
         .extension()
         .and_then(|e| e.to_str())
         .unwrap_or("")
@@ -276,62 +979,52 @@ pub async fn update_file(
         let mut tree_guard = file_tree.lock().await;
         let tree = tree_guard.as_mut().unwrap();
         // Clone the Arc to the entry if found
-        if let Some(entry) = find_entry(tree, &rp) {
-            Some(entry.clone())
-        } else {
-            None
-        }
+        find_entry(tree, rp)
     };
 
-    if let Some(entry) = entry_arc {
-        // Lock the file entry to prevent concurrent updates
-        let _file_guard = entry.lock.lock().await;
-
-        // Resolve the safe path
-        let filepath = match safe_path(&rp) {
-            Ok(p) => p,
-            Err(resp) => return resp, // This will return a proper error response
-        };
-
-        // Remove old file if it exists
-        if filepath.exists() {
-            if let Ok(meta) = std::fs::metadata(&filepath) {
-                if meta.is_file() {
-                    let _ = std::fs::remove_file(&filepath);
-                }
-            }
-        }
+    let Some(entry) = entry_arc.filter(|entry| !entry.is_dir) else {
+        return (StatusCode::NOT_FOUND, "File not found in media tree").into_response();
+    };
+    // Lock the file entry to prevent concurrent updates
+    let _file_guard = entry.lock.lock().await;
 
-        // Write the new file
-        if let Err(e) = tokio::fs::write(&filepath, &data).await {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to write file: {e}"),
-            )
-                .into_response();
-        }
+    // Resolve the safe path
+    let filepath = match safe_path(rp) {
+        Ok(p) => p,
+        Err(resp) => return resp, // This will return a proper error response
+    };
 
-        // Update the in-memory FileEntry metadata
-        {
-            let mut tree_guard = file_tree.lock().await;
-            if let Some(tree) = tree_guard.as_mut() {
-                if let Some(mut entry) = find_entry(tree, &rp) {
-                    entry.size = get_file_size(&filepath);
-                    entry.modified = get_modified_time(&filepath);
-                }
-            }
-        }
+    // Keep the current content as a previous version
+    if let Err(e) = save_version(&filepath, rp) {
+        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to keep the previous version: {e}")).into_response();
+    }
 
-        return (StatusCode::OK, "File updated successfully").into_response();
-    } else {
-        return (StatusCode::NOT_FOUND, "File not found in media tree").into_response();
+    // Swap the new content in, so readers see either the old or the new file
+    if let Err(e) = move_path(tmp.to_path_buf(), filepath.clone()).await {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to write file: {e}"),
+        )
+            .into_response();
+    }
+    if let Ok(meta) = tokio::fs::metadata(&filepath).await {
+        metadata::record_hash(rp, &meta, sha256);
     }
+    // New content starts with the folder's expiry, not the replaced file's
+    expiry::record(rp, None);
+    let _ = tree_tx.send(TreeNotice::Changed(TreeChanges::modified(rp)));
+
+    (StatusCode::OK, "File updated successfully").into_response()
 }
 
 pub async fn create_folder (
     Json(payload): Json<CreateFolderRequest>,
 ) -> impl IntoResponse {
-    let safe_path = match safe_path(&payload.path) {
+    let path = match new_path(&payload.path) {
+        Ok(path) => path,
+        Err(e) => return e.into_response(),
+    };
+    let safe_path = match safe_path(&path) {
         Ok(p) => p,
         Err(resp) => return resp,
     };
@@ -347,88 +1040,201 @@ pub async fn create_folder (
     (StatusCode::OK, "Folder created").into_response()
 }
 
-pub async fn build_range_response(
-    mut file: File,
-    file_size: u64,
-    mime: &mime::Mime,
-    range: Range,
-) -> Response {
-    let mut ranges = range.satisfiable_ranges(file_size);
+/// Rejects a move or copy of the share root, into itself, or to a malformed path.
+/// Returns the destination as it should be written (see [`new_path`]).
+#[allow(clippy::result_large_err)]
+fn check_transfer_paths(from: &str, to: &str) -> Result<String, Response> {
+    if from.is_empty() || to.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Cannot move or copy the shared folder itself").into_response());
+    }
+    if to.split('/').any(|part| matches!(part, "" | "." | "..")) {
+        return Err((StatusCode::BAD_REQUEST, "Invalid destination path").into_response());
+    }
+    let to = new_path(to).map_err(IntoResponse::into_response)?;
+    if from == to {
+        return Err((StatusCode::BAD_REQUEST, "Source and destination are the same").into_response());
+    }
+    if to.starts_with(&format!("{from}/")) {
+        return Err((StatusCode::BAD_REQUEST, "Cannot move or copy a folder into itself").into_response());
+    }
+    Ok(to)
+}
 
+/// Moves or renames a file or folder.
+/// Takes the locks of the entry (and everything below a folder) for the duration of
+/// the move, then updates the in-memory tree in place. A move into another top-level
+/// folder must fit its quota (`507 Insufficient Storage`).
+pub async fn move_entry(
+    Extension(file_tree): Extension<Arc<Mutex<Option<FileEntry>>>>,
+    Extension(tree_tx): Extension<Sender<TreeNotice>>,
+    Json(payload): Json<MoveRequest>,
+) -> impl IntoResponse {
+    let from = payload.from.trim_matches('/').to_string();
+    let to = match check_transfer_paths(&from, payload.to.trim_matches('/')) {
+        Ok(to) => to,
+        Err(resp) => return resp,
+    };
 
-    if let Some((start_bound, end_bound)) = ranges.next() {
-        
+    let (entry, existing) = {
+        let mut tree_guard = file_tree.lock().await;
+        let tree = tree_guard.as_mut().unwrap();
+        (find_entry(tree, &from), find_entry(tree, &to))
+    };
+    let Some(entry) = entry else {
+        return (StatusCode::NOT_FOUND, "File not found in media tree").into_response();
+    };
 
-        let start = match start_bound {
-            std::ops::Bound::Included(s) => s,
-            std::ops::Bound::Excluded(s) => s + 1,
-            std::ops::Bound::Unbounded => 0,
-        };
+    let source = match safe_path(&from) {
+        Ok(p) => p,
+        Err(resp) => return resp,
+    };
+    let destination = match safe_path(&to) {
+        Ok(p) => p,
+        Err(resp) => return resp,
+    };
 
-        
-        let end = match end_bound {
-            std::ops::Bound::Included(e) => e,
-            std::ops::Bound::Excluded(e) => e - 1,
-            std::ops::Bound::Unbounded => file_size - 1,
-        };
+    // Lock the entry, everything below it and a file about to be replaced, in path order
+    let mut locks = entry.subtree_locks();
+    if let Some(existing) = &existing {
+        locks.push((existing.path.clone(), existing.lock.clone()));
+        locks.sort_by(|a, b| a.0.cmp(&b.0));
+    }
+    let mut _guards = Vec::with_capacity(locks.len());
+    for (_, lock) in locks {
+        _guards.push(lock.lock_owned().await);
+    }
 
-       
-        if start > end || end >= file_size {
-            return (StatusCode::RANGE_NOT_SATISFIABLE, "Invalid range").into_response();
+    if tokio::fs::symlink_metadata(&source).await.is_err() {
+        return (StatusCode::NOT_FOUND, "File not found").into_response();
+    }
+    if let Ok(meta) = tokio::fs::symlink_metadata(&destination).await {
+        if !payload.overwrite {
+            return (StatusCode::CONFLICT, "Destination already exists").into_response();
         }
-
-        let chunk_size = end - start + 1;
-
-     
-        if file.seek(SeekFrom::Start(start)).await.is_err() {
-            return (StatusCode::RANGE_NOT_SATISFIABLE, "Invalid range").into_response();
+        if meta.is_dir() || entry.is_dir {
+            return (StatusCode::CONFLICT, "Only files can be overwritten").into_response();
+        }
+    }
+    let checked = {
+        let (source, to) = (source.clone(), to.clone());
+        tokio::task::spawn_blocking(move || check_tree(&source, &to)).await
+    };
+    if let Ok(Err(violation)) = checked {
+        return violation.into_response();
+    }
+    // Moving into another top-level folder adds to its quota
+    let (from_quota, to_quota) = if entry.is_dir { (format!("{from}/_"), format!("{to}/_")) } else { (from.clone(), to.clone()) };
+    if let Some(folder) = top_folder(&to_quota).filter(|folder| top_folder(&from_quota) != Some(*folder)) {
+        let (bytes, files) = count(&entry);
+        match allowance(None, Some(folder), true, &file_tree).await {
+            Ok(allowance) if bytes > allowance.bytes => {
+                return (StatusCode::INSUFFICIENT_STORAGE, allowance.exceeded()).into_response();
+            }
+            Ok(_) => {}
+            Err(message) => return (StatusCode::INSUFFICIENT_STORAGE, message).into_response(),
+        }
+        let usage = file_tree.lock().await.as_mut().map(|tree| folder_usage(tree, folder));
+        if let Some(limit) = usage.and_then(|usage| usage.files_limit.filter(|_| usage.files_left().is_some_and(|left| files > left))) {
+            let message = format!("Not enough space: the quota of folder '{folder}' allows {limit} files");
+            return (StatusCode::INSUFFICIENT_STORAGE, message).into_response();
         }
+    }
+    if let Err(e) = save_version(&destination, &to) {
+        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to keep the previous version: {e}")).into_response();
+    }
 
-        // Stream the requested chunk with a buffer size of 128KB
-        let stream = ReaderStream::with_capacity(file.take(chunk_size), 128 * 1024);
+    if let Err(e) = move_path(source, destination).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to move: {e}")).into_response();
+    }
+    info!("🚚 Moved '{from}' to '{to}'");
+    if let Err(e) = move_history(&from, &to) {
+        warn!("Failed to move the versions of '{from}': {e}");
+    }
+    metadata::rename(&from, &to);
 
-        Response::builder()
-            .status(StatusCode::PARTIAL_CONTENT)
-            .header(header::CONTENT_TYPE, mime.as_ref())
-            .header(header::ACCEPT_RANGES, "bytes")
-            .header(
-                header::CONTENT_RANGE,
-                format!("bytes {}-{}/{}", start, end, file_size),
-            )
-            .header(header::CACHE_CONTROL, "public, max-age=86400")
-            .body(Body::from_stream(stream))
-            .unwrap()
-    } else {
-        // No valid range found
-        (StatusCode::RANGE_NOT_SATISFIABLE, "Invalid range").into_response()
+    {
+        let mut tree_guard = file_tree.lock().await;
+        if let Some(tree) = tree_guard.as_mut() {
+            if let Some(mut moved) = tree.detach(&from) {
+                moved.rebase(&from, &to);
+                let parent = to.rsplit_once('/').map_or("", |(parent, _)| parent);
+                tree.attach(parent, moved);
+            }
+        }
     }
+    let _ = tree_tx.send(TreeNotice::Changed(TreeChanges::moved(from, to)));
+
+    (StatusCode::OK, "Moved").into_response()
 }
 
+/// Starts a background job copying a file or folder to `to`.
+/// Folders are merged into an existing destination; files that already exist are
+/// handled by the `conflict` policy. Answers `202 Accepted` with the job.
+pub async fn copy_entry(
+    Extension(file_tree): Extension<Arc<Mutex<Option<FileEntry>>>>,
+    Extension(jobs): Extension<Arc<JobRegistry>>,
+    Extension(tree_tx): Extension<Sender<TreeNotice>>,
+    Json(payload): Json<CopyRequest>,
+) -> impl IntoResponse {
+    let from = payload.from.trim_matches('/').to_string();
+    let to = match check_transfer_paths(&from, payload.to.trim_matches('/')) {
+        Ok(to) => to,
+        Err(resp) => return resp,
+    };
+
+    let entry = {
+        let mut tree_guard = file_tree.lock().await;
+        let tree = tree_guard.as_mut().unwrap();
+        find_entry(tree, &from)
+    };
+    if entry.is_none() {
+        return (StatusCode::NOT_FOUND, "File not found in media tree").into_response();
+    }
+    if let Err(resp) = safe_path(&from).and(safe_path(&to)) {
+        return resp;
+    }
 
+    let job = jobs.start("copy", format!("Copy {from} to {to}"));
+    let snapshot = job.snapshot();
+    info!("📋 Job {}: copying '{from}' to '{to}'", snapshot.id);
+    tokio::task::spawn_blocking(move || {
+        copy_tree(&from, &to, payload.conflict, &file_tree, &job);
+        let _ = tree_tx.send(TreeNotice::Changed(TreeChanges::created(to)));
+    });
 
+    (StatusCode::ACCEPTED, Json(snapshot)).into_response()
+}
 
+/// Streams tree changes as server-sent events: `init` once, then a JSON object with
+/// the `created`, `modified` and `removed` paths of every change applied to the tree.
 pub async fn tree_events(
-    Extension(tree_tx): Extension<Sender<()>>,
+    Extension(tree_tx): Extension<Sender<TreeNotice>>,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
     use tracing::info;
     info!("🌲 [SSE] New client connected to /events/tree");
-    let mut rx = tree_tx.subscribe();
+    let rx = tree_tx.subscribe();
     let stream = futures_util::stream::unfold((false, rx), move |(mut sent_initial, mut rx)| async move {
         if !sent_initial {
             sent_initial = true;
             // Send an initial event to trigger the first refresh
             return Some((Ok(Event::default().data("init")), (sent_initial, rx)));
         }
-        match rx.recv().await {
-            Ok(_) => {
-                info!("🌲 [SSE] Sending update event to client");
-                Some((Ok(Event::default().data("update")), (sent_initial, rx)))
-            },
-            Err(e) => {
-                info!("🌲 [SSE] SSE stream ended: {:?}", e);
-                None
+        let changes = loop {
+            match rx.recv().await {
+                // Changes the watcher hasn't put in the tree yet
+                Ok(TreeNotice::Changed(_)) => continue,
+                Ok(TreeNotice::Updated(changes)) => break changes,
+                // After missed notices, the whole share counts as modified
+                Err(RecvError::Lagged(_)) => break TreeChanges::modified(""),
+                Err(e) => {
+                    info!("🌲 [SSE] SSE stream ended: {:?}", e);
+                    return None;
+                }
             }
-        }
+        };
+        info!("🌲 [SSE] Sending update event to client");
+        let event = Event::default().json_data(&changes).unwrap_or_else(|_| Event::default().data("update"));
+        Some((Ok(event), (sent_initial, rx)))
     });
     Sse::new(stream)
 }
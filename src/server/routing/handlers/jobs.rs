@@ -0,0 +1,60 @@
+use axum::{
+    extract::{Extension, Json, Path},
+    http::StatusCode,
+    response::{
+        sse::{Event, Sse},
+        IntoResponse, Response,
+    },
+};
+use futures_core::Stream;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use crate::file_manager::jobs::JobRegistry;
+
+/// Lists running and recently finished background jobs.
+pub async fn list_jobs(Extension(jobs): Extension<Arc<JobRegistry>>) -> Response {
+    Json(jobs.list()).into_response()
+}
+
+/// Returns a single job's progress.
+pub async fn get_job(Path(id): Path<u64>, Extension(jobs): Extension<Arc<JobRegistry>>) -> Response {
+    match jobs.get(id) {
+        Some(job) => Json(job).into_response(),
+        None => (StatusCode::NOT_FOUND, "Job not found").into_response(),
+    }
+}
+
+/// Asks a running job to stop. The job reports `cancelled` once it has cleaned up.
+pub async fn cancel_job(Path(id): Path<u64>, Extension(jobs): Extension<Arc<JobRegistry>>) -> Response {
+    if jobs.cancel(id) {
+        (StatusCode::ACCEPTED, "Cancellation requested").into_response()
+    } else {
+        (StatusCode::NOT_FOUND, "No running job with this id").into_response()
+    }
+}
+
+/// Server-sent events with a JSON job snapshot whenever a job makes progress.
+/// Every current job is sent once when the stream opens.
+pub async fn job_events(
+    Extension(jobs): Extension<Arc<JobRegistry>>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let rx = jobs.subscribe();
+    let initial = jobs.list();
+    let stream = futures_util::stream::unfold((initial, rx), |(mut initial, mut rx)| async move {
+        let snapshot = match initial.pop() {
+            Some(snapshot) => snapshot,
+            None => loop {
+                match rx.recv().await {
+                    Ok(snapshot) => break snapshot,
+                    // A slow client only misses intermediate progress
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            },
+        };
+        let event = Event::default().event("job").json_data(&snapshot).unwrap_or_default();
+        Some((Ok(event), (initial, rx)))
+    });
+    Sse::new(stream)
+}
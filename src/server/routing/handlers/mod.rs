@@ -2,10 +2,27 @@ pub mod auth;
 pub mod file_operations;
 pub mod static_content;
 pub mod health;
+pub mod archive;
+pub mod jobs;
+pub mod trash;
+pub mod versions;
+pub mod text;
+pub mod raw_upload;
+pub mod quota;
+pub mod fetch;
 
 pub use auth::{login, master_protection, password_required};
 pub use file_operations::{
-    tree_events,master_json, open, upload_file, delete_file, update_file, create_folder
+    tree_events,master_json, open, download, upload_file, delete_file, update_file, create_folder,
+    move_entry, copy_entry
 };
 pub use static_content::static_handler;
 pub use health::health_check;
+pub use archive::{archive_entries, archive_folder, archive_selection, extract_archive};
+pub use jobs::{cancel_job, get_job, job_events, list_jobs};
+pub use trash::{empty_trash, list_trash, purge_trash, restore_trash};
+pub use versions::{download_version, file_versions, restore_version};
+pub use text::{read_text, write_text};
+pub use raw_upload::put_file;
+pub use quota::quota_usage;
+pub use fetch::fetch_url;
@@ -0,0 +1,62 @@
+use axum::{
+    extract::{Extension, Json},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use crate::file_manager::file_tree::FileEntry;
+use crate::file_manager::quota::{folder_usage, space_left, user_usage, QuotaUsage};
+use crate::utils::config::Config;
+use super::auth::Requester;
+
+#[derive(Serialize)]
+pub struct QuotaReport {
+    /// The requesting client, as quotas tell users apart.
+    pub user: String,
+    pub usage: QuotaUsage,
+    /// Usage of every top-level folder.
+    pub folders: BTreeMap<String, QuotaUsage>,
+    /// Bytes uploads may still use before the free-space reserve is reached.
+    pub space_left: u64,
+    pub space_reserve: u64,
+}
+
+/// Reports the quota usage of the requesting client and of the top-level folders.
+pub async fn quota_usage(
+    Requester(user): Requester,
+    Extension(file_tree): Extension<Arc<Mutex<Option<FileEntry>>>>,
+) -> Response {
+    let report = tokio::task::spawn_blocking(move || {
+        let mut folders = BTreeMap::new();
+        if let Some(tree) = file_tree.blocking_lock().as_mut() {
+            let names: Vec<String> = tree
+                .children
+                .iter()
+                .flatten()
+                .filter(|child| child.is_dir)
+                .map(|child| child.path.clone())
+                .collect();
+            for name in names {
+                let usage = folder_usage(tree, &name);
+                folders.insert(name, usage);
+            }
+        }
+        Ok::<_, std::io::Error>(QuotaReport {
+            usage: user_usage(&user),
+            user,
+            folders,
+            space_left: space_left()?,
+            space_reserve: Config::from_env().free_space_reserve_bytes(),
+        })
+    })
+    .await;
+
+    match report {
+        Ok(Ok(report)) => Json(report).into_response(),
+        Ok(Err(e)) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to check free space: {e}")).into_response(),
+        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to compute quota usage").into_response(),
+    }
+}
@@ -0,0 +1,214 @@
+use axum::{
+    body::Body,
+    extract::{Extension, Path},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use std::io::ErrorKind;
+use std::path::Path as FsPath;
+use std::sync::Arc;
+use tokio::sync::{broadcast::Sender, Mutex};
+use tracing::info;
+use crate::file_manager::checksum::{Digests, ExpectedDigests};
+use crate::file_manager::expiry::{self, ttl_from_headers};
+use crate::file_manager::file_tree::FileEntry;
+use crate::file_manager::tree_watcher::{TreeChanges, TreeNotice};
+use crate::file_manager::file_types::{check_type, read_head};
+use crate::file_manager::filename::new_path;
+use crate::file_manager::file_utils::{find_entry, safe_path};
+use crate::file_manager::metadata;
+use crate::file_manager::quota::{allowance, top_folder, Allowance};
+use crate::file_manager::transfer::{move_new_file, move_path, receive_stream, staging_file};
+use crate::file_manager::versions::save_version;
+use crate::server::file_operations::Validators;
+use super::auth::Requester;
+
+/// Writes the raw request body to `path`, for `curl -T` and scripts.
+///
+/// The body is received into `.rustyshare/tmp` and only moved into place once
+/// it is complete and matches any `Content-MD5`, `Digest` (`md5`, `sha-256`) or
+/// `X-Content-SHA256` header, so readers never see a partial file. Its SHA-256 is
+/// recorded. An existing file is replaced (keeping it as a version) unless the
+/// request says `If-None-Match: *`; `If-Match` makes the replacement conditional
+/// on the current `ETag`. Quotas and the free-space reserve are checked before and
+/// while receiving (`507 Insufficient Storage`). `X-Expires-In` gives the file a
+/// time-to-live, as for multipart uploads.
+/// Answers `201 Created` for new files and `200 OK` for replaced ones.
+pub async fn put_file(
+    Path(path): Path<String>,
+    Requester(user): Requester,
+    headers: HeaderMap,
+    Extension(file_tree): Extension<Arc<Mutex<Option<FileEntry>>>>,
+    Extension(tree_tx): Extension<Sender<TreeNotice>>,
+    body: Body,
+) -> Response {
+    if path.trim_matches('/').is_empty() {
+        return (StatusCode::BAD_REQUEST, "A file name is required").into_response();
+    }
+    let rel = match new_path(&path) {
+        Ok(rel) => rel,
+        Err(e) => return e.into_response(),
+    };
+    let expected = match ExpectedDigests::from_headers(&headers) {
+        Ok(expected) => expected,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+    let ttl = match ttl_from_headers(&headers) {
+        Ok(ttl) => ttl,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+    let filepath = match safe_path(&rel) {
+        Ok(p) => p,
+        Err(resp) => return resp,
+    };
+    // Refuse early so a doomed upload isn't received in full
+    if let Err(resp) = check_target(&rel, &filepath, &headers).await {
+        return resp;
+    }
+    if let Err(violation) = check_type(&rel, &[]) {
+        return violation.into_response();
+    }
+    let replacing = filepath.is_file();
+    let mut allowance = match allowance(Some(&user), top_folder(&rel), !replacing, &file_tree).await {
+        Ok(allowance) => allowance,
+        Err(message) => return (StatusCode::INSUFFICIENT_STORAGE, message).into_response(),
+    };
+    let declared = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse::<u64>().ok());
+    if declared.is_some_and(|len| len > allowance.bytes) {
+        return (StatusCode::INSUFFICIENT_STORAGE, allowance.exceeded()).into_response();
+    }
+
+    let tmp = match staging_file("put").await {
+        Ok(tmp) => tmp,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to prepare upload: {e}")).into_response(),
+    };
+    let mut digests = Digests::new(&expected);
+    let size = match receive_stream(body.into_data_stream(), &tmp, &mut digests, &mut allowance).await {
+        Ok(size) => size,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            return receive_error(e, &allowance);
+        }
+    };
+    let sha256 = match digests.finish(&expected) {
+        Ok(sha256) => sha256,
+        Err(digest) => {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            return checksum_mismatch(digest);
+        }
+    };
+    let head = {
+        let tmp = tmp.clone();
+        tokio::task::spawn_blocking(move || read_head(&tmp)).await
+    };
+    if let Err(violation) = check_type(&rel, head.ok().and_then(Result::ok).as_deref().unwrap_or_default()) {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return violation.into_response();
+    }
+
+    // Hold the lock of a file being replaced while it is swapped
+    let existing = {
+        let mut tree_guard = file_tree.lock().await;
+        tree_guard.as_mut().and_then(|tree| find_entry(tree, &rel))
+    };
+    let _file_guard = match &existing {
+        Some(entry) => Some(entry.lock.lock().await),
+        None => None,
+    };
+
+    // The target may have changed while the body was received
+    let replaced = match check_target(&rel, &filepath, &headers).await {
+        Ok(replaced) => replaced,
+        Err(resp) => {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            return resp;
+        }
+    };
+    if replaced {
+        if let Err(e) = save_version(&filepath, &rel) {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to keep the previous version: {e}")).into_response();
+        }
+    }
+    // A new file must not replace one created meanwhile
+    let placed = if replaced {
+        move_path(tmp.clone(), filepath.clone()).await
+    } else {
+        move_new_file(tmp.clone(), filepath.clone()).await
+    };
+    if let Err(e) = placed {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        if e.kind() == ErrorKind::AlreadyExists {
+            let status = if headers.contains_key(header::IF_NONE_MATCH) {
+                StatusCode::PRECONDITION_FAILED
+            } else {
+                StatusCode::CONFLICT
+            };
+            return (status, "File already exists").into_response();
+        }
+        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save file: {e}")).into_response();
+    }
+    if let Ok(meta) = tokio::fs::metadata(&filepath).await {
+        metadata::record_hash(&rel, &meta, sha256);
+    }
+    metadata::record_owner(&rel, &user);
+    expiry::record(&rel, ttl);
+    allowance.commit();
+    info!("⬆️ Received '{rel}' ({size} bytes)");
+    let changes = if replaced { TreeChanges::modified(rel.clone()) } else { TreeChanges::created(rel.clone()) };
+    let _ = tree_tx.send(TreeNotice::Changed(changes));
+
+    let (status, message) = if replaced {
+        (StatusCode::OK, "File replaced")
+    } else {
+        (StatusCode::CREATED, "File created")
+    };
+    let mut response = (status, message).into_response();
+    if let Ok(meta) = tokio::fs::metadata(&filepath).await {
+        Validators::for_file(&rel, &meta).apply(response.headers_mut());
+    }
+    response
+}
+
+/// Applies `If-None-Match: *` and `If-Match` to the current state of the target.
+/// Returns whether a file is there to be replaced.
+#[allow(clippy::result_large_err)]
+async fn check_target(rel: &str, filepath: &FsPath, headers: &HeaderMap) -> Result<bool, Response> {
+    let meta = match tokio::fs::symlink_metadata(filepath).await {
+        Ok(meta) => meta,
+        Err(_) if headers.contains_key(header::IF_MATCH) => {
+            return Err((StatusCode::PRECONDITION_FAILED, "File does not exist").into_response());
+        }
+        Err(_) => return Ok(false),
+    };
+    if !meta.is_file() {
+        return Err((StatusCode::CONFLICT, "A folder or link with this name exists").into_response());
+    }
+    let refuse_existing = headers
+        .get(header::IF_NONE_MATCH)
+        .is_some_and(|value| value.as_bytes().trim_ascii() == b"*");
+    if refuse_existing {
+        return Err((StatusCode::PRECONDITION_FAILED, "File already exists").into_response());
+    }
+    if !Validators::for_file(rel, &meta).write_allowed(headers) {
+        return Err((StatusCode::PRECONDITION_FAILED, "File was changed by someone else").into_response());
+    }
+    Ok(true)
+}
+
+/// Response for a body that could not be stored within `allowance`.
+pub(super) fn receive_error(e: std::io::Error, allowance: &Allowance) -> Response {
+    match e.kind() {
+        ErrorKind::ConnectionAborted => (StatusCode::BAD_REQUEST, format!("Upload interrupted: {e}")).into_response(),
+        ErrorKind::QuotaExceeded => (StatusCode::INSUFFICIENT_STORAGE, allowance.exceeded()).into_response(),
+        ErrorKind::StorageFull => (StatusCode::INSUFFICIENT_STORAGE, "Not enough space: the disk is full").into_response(),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save file: {e}")).into_response(),
+    }
+}
+
+/// Response for an upload whose content doesn't match the digest the client sent.
+pub(super) fn checksum_mismatch(digest: &str) -> Response {
+    (StatusCode::BAD_REQUEST, format!("{digest} checksum does not match; the upload was discarded")).into_response()
+}
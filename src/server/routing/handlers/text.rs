@@ -0,0 +1,154 @@
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Extension, Path},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use headers::{HeaderMapExt, IfMatch};
+use std::sync::Arc;
+use tokio::sync::{broadcast::Sender, Mutex};
+use tracing::info;
+use crate::file_manager::checksum::sha256_hex;
+use crate::file_manager::expiry;
+use crate::file_manager::file_tree::FileEntry;
+use crate::file_manager::tree_watcher::{TreeChanges, TreeNotice};
+use crate::file_manager::file_types::{check_type, SNIFF_LEN};
+use crate::file_manager::file_utils::{find_entry, safe_path};
+use crate::file_manager::metadata;
+use crate::file_manager::quota::{allowance, top_folder};
+use crate::file_manager::transfer::part_path;
+use crate::file_manager::versions::save_version;
+use crate::server::file_operations::{Precondition, Validators};
+use crate::utils::config::Config;
+
+const TEXT_PLAIN: &str = "text/plain; charset=utf-8";
+
+/// Returns a small UTF-8 file as text, with the `ETag` to send back in `If-Match` when saving.
+pub async fn read_text(
+    Path(path): Path<String>,
+    headers: HeaderMap,
+    Extension(file_tree): Extension<Arc<Mutex<Option<FileEntry>>>>,
+) -> Response {
+    let Some(entry) = find_file(&file_tree, &path).await else {
+        return (StatusCode::NOT_FOUND, "File not found in media tree").into_response();
+    };
+    let filepath = match safe_path(&entry.path) {
+        Ok(p) => p,
+        Err(resp) => return resp,
+    };
+
+    let _file_guard = entry.lock.lock().await;
+    let meta = match tokio::fs::metadata(&filepath).await {
+        Ok(meta) => meta,
+        Err(_) => return (StatusCode::NOT_FOUND, "File not found").into_response(),
+    };
+    if meta.len() > Config::from_env().text_edit_max_bytes() {
+        return (StatusCode::PAYLOAD_TOO_LARGE, "File is too large to edit as text").into_response();
+    }
+    let validators = Validators::for_file(&entry.path, &meta);
+    if validators.evaluate(&headers) == Precondition::NotModified {
+        return validators.not_modified_response();
+    }
+    let content = match tokio::fs::read(&filepath).await {
+        Ok(content) => content,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read file: {e}")).into_response(),
+    };
+    let Ok(text) = String::from_utf8(content) else {
+        return (StatusCode::UNSUPPORTED_MEDIA_TYPE, "File is not UTF-8 text").into_response();
+    };
+
+    let mut response = ([(header::CONTENT_TYPE, HeaderValue::from_static(TEXT_PLAIN))], text).into_response();
+    validators.apply(response.headers_mut());
+    response
+}
+
+/// Replaces a text file with the request body.
+/// The client must send a valid `If-Match` with the `ETag` it read (not `*`): a one-second
+/// `If-Unmodified-Since` alone can't tell two saves within the same second apart.
+/// If the file changed since, nothing is written and the answer is `412 Precondition Failed`.
+/// The body must be valid UTF-8 and no larger than `TEXT_EDIT_MAX_BYTES`.
+pub async fn write_text(
+    Path(path): Path<String>,
+    headers: HeaderMap,
+    Extension(file_tree): Extension<Arc<Mutex<Option<FileEntry>>>>,
+    Extension(tree_tx): Extension<Sender<TreeNotice>>,
+    body: Body,
+) -> Response {
+    // `If-Match: *` matches whatever is there now, so it can't catch a lost update
+    if headers.typed_get::<IfMatch>().is_none_or(|if_match| if_match.is_any()) {
+        return (StatusCode::PRECONDITION_REQUIRED, "Send If-Match with the ETag of the version you edited")
+            .into_response();
+    }
+    let Some(entry) = find_file(&file_tree, &path).await else {
+        return (StatusCode::NOT_FOUND, "File not found in media tree").into_response();
+    };
+    let filepath = match safe_path(&entry.path) {
+        Ok(p) => p,
+        Err(resp) => return resp,
+    };
+
+    let max_bytes = Config::from_env().text_edit_max_bytes();
+    let Ok(content) = to_bytes(body, usize::try_from(max_bytes).unwrap_or(usize::MAX)).await else {
+        return (StatusCode::PAYLOAD_TOO_LARGE, format!("Text is larger than {max_bytes} bytes")).into_response();
+    };
+    if std::str::from_utf8(&content).is_err() {
+        return (StatusCode::BAD_REQUEST, "Content is not valid UTF-8").into_response();
+    }
+    if let Err(violation) = check_type(&entry.path, &content[..content.len().min(SNIFF_LEN)]) {
+        return violation.into_response();
+    }
+    let mut allowance = match allowance(None, top_folder(&entry.path), false, &file_tree).await {
+        Ok(allowance) => allowance,
+        Err(message) => return (StatusCode::INSUFFICIENT_STORAGE, message).into_response(),
+    };
+    if allowance.take(content.len() as u64).is_err() {
+        return (StatusCode::INSUFFICIENT_STORAGE, allowance.exceeded()).into_response();
+    }
+
+    // Check the version and swap the content under the file's lock
+    let _file_guard = entry.lock.lock().await;
+    let meta = match tokio::fs::metadata(&filepath).await {
+        Ok(meta) => meta,
+        Err(_) => return (StatusCode::NOT_FOUND, "File not found").into_response(),
+    };
+    let current = Validators::for_file(&entry.path, &meta);
+    if !current.write_allowed(&headers) {
+        let mut response = (StatusCode::PRECONDITION_FAILED, "File was changed by someone else").into_response();
+        current.apply(response.headers_mut());
+        return response;
+    }
+
+    let part = part_path(&filepath, "edit");
+    if let Err(e) = tokio::fs::write(&part, &content).await {
+        let _ = tokio::fs::remove_file(&part).await;
+        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write file: {e}")).into_response();
+    }
+    if let Err(e) = save_version(&filepath, &entry.path) {
+        let _ = tokio::fs::remove_file(&part).await;
+        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to keep the previous version: {e}")).into_response();
+    }
+    if let Err(e) = tokio::fs::rename(&part, &filepath).await {
+        let _ = tokio::fs::remove_file(&part).await;
+        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to write file: {e}")).into_response();
+    }
+    allowance.commit();
+    expiry::record(&entry.path, None);
+    info!("📝 Saved '{}' ({} bytes)", entry.path, content.len());
+
+    // Record the hash before announcing the change, so clients refetch the SHA-based ETag
+    let mut response = (StatusCode::OK, "Saved").into_response();
+    if let Ok(meta) = tokio::fs::metadata(&filepath).await {
+        metadata::record_hash(&entry.path, &meta, sha256_hex(&content));
+        Validators::for_file(&entry.path, &meta).apply(response.headers_mut());
+    }
+    let _ = tree_tx.send(TreeNotice::Changed(TreeChanges::modified(entry.path.clone())));
+    response
+}
+
+async fn find_file(file_tree: &Mutex<Option<FileEntry>>, path: &str) -> Option<FileEntry> {
+    let mut tree_guard = file_tree.lock().await;
+    tree_guard
+        .as_mut()
+        .and_then(|tree| find_entry(tree, path))
+        .filter(|entry| !entry.is_dir)
+}
@@ -0,0 +1,155 @@
+use axum::{
+    extract::{Extension, Json, Path},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::{broadcast::Sender, Mutex};
+use tracing::info;
+use crate::file_manager::expiry;
+use crate::file_manager::file_tree::FileEntry;
+use crate::file_manager::tree_watcher::{TreeChanges, TreeNotice};
+use crate::file_manager::file_types::check_tree;
+use crate::file_manager::filename::new_path;
+use crate::file_manager::file_utils::{find_entry, free_name, safe_path, ConflictPolicy};
+use crate::file_manager::quota::{allowance, top_folder};
+use crate::file_manager::transfer::ensure_dir;
+use crate::file_manager::trash::{get_item, item_path, list_items, purge_all, purge_item, restore_item};
+use crate::file_manager::versions::save_version;
+
+#[derive(Deserialize)]
+pub struct RestoreRequest {
+    /// Where to put the item back; defaults to where it was deleted from.
+    #[serde(default)]
+    pub to: Option<String>,
+    /// `skip` (default, answers 409), `overwrite` or `rename` when the destination exists.
+    #[serde(default)]
+    pub conflict: ConflictPolicy,
+}
+
+#[derive(Serialize)]
+pub struct RestoreResponse {
+    /// Path the item was restored to, which differs from the request after a rename.
+    pub path: String,
+}
+
+/// Lists the trash, most recently deleted first.
+pub async fn list_trash() -> Response {
+    match tokio::task::spawn_blocking(list_items).await {
+        Ok(Ok(items)) => Json(items).into_response(),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read the trash").into_response(),
+    }
+}
+
+/// Moves a trashed item back into the share, recreating missing parent folders.
+pub async fn restore_trash(
+    Path(id): Path<String>,
+    Extension(file_tree): Extension<Arc<Mutex<Option<FileEntry>>>>,
+    Extension(tree_tx): Extension<Sender<TreeNotice>>,
+    Json(payload): Json<RestoreRequest>,
+) -> Response {
+    let Some(item) = get_item(&id) else {
+        return (StatusCode::NOT_FOUND, "No such item in the trash").into_response();
+    };
+    // A new destination must be a valid name; the original path is taken as it was
+    let to = match payload.to.as_deref() {
+        Some(to) => match new_path(to) {
+            Ok(to) => to,
+            Err(e) => return e.into_response(),
+        },
+        None => item.original_path.trim_matches('/').to_string(),
+    };
+    if to.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Destination is required").into_response();
+    }
+
+    let checked = {
+        let (source, to) = (item_path(&item), to.clone());
+        tokio::task::spawn_blocking(move || check_tree(&source, &to)).await
+    };
+    if let Ok(Err(violation)) = checked {
+        return violation.into_response();
+    }
+    let quota_path = if item.is_dir { format!("{to}/_") } else { to.clone() };
+    match allowance(None, top_folder(&quota_path), true, &file_tree).await {
+        Ok(allowance) if item.size > allowance.bytes => {
+            return (StatusCode::INSUFFICIENT_STORAGE, allowance.exceeded()).into_response();
+        }
+        Ok(_) => {}
+        Err(message) => return (StatusCode::INSUFFICIENT_STORAGE, message).into_response(),
+    }
+
+    let parent = to.rsplit_once('/').map_or("", |(parent, _)| parent);
+    match ensure_dir(parent) {
+        Ok(None) => {}
+        Ok(Some(reason)) => return (StatusCode::CONFLICT, reason).into_response(),
+        Err(e) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create folder: {e}")).into_response();
+        }
+    }
+    let destination = match safe_path(&to) {
+        Ok(p) => p,
+        Err(resp) => return resp,
+    };
+
+    // Settle on the final name; only a file can replace a file
+    let (to, destination) = match tokio::fs::symlink_metadata(&destination).await {
+        Err(_) => (to, destination),
+        Ok(_) if payload.conflict == ConflictPolicy::Rename => {
+            let dir = destination.parent().unwrap_or(std::path::Path::new("."));
+            let free = free_name(dir, &item.name);
+            let rel = if parent.is_empty() { free.clone() } else { format!("{parent}/{free}") };
+            (rel, dir.join(free))
+        }
+        Ok(meta) if payload.conflict == ConflictPolicy::Overwrite && meta.is_file() && !item.is_dir => {
+            (to, destination)
+        }
+        Ok(_) => return (StatusCode::CONFLICT, format!("'{to}' already exists")).into_response(),
+    };
+
+    // Hold the lock of a file about to be replaced
+    let existing = {
+        let mut tree_guard = file_tree.lock().await;
+        tree_guard.as_mut().and_then(|tree| find_entry(tree, &to))
+    };
+    let _file_guard = match &existing {
+        Some(entry) => Some(entry.lock.lock().await),
+        None => None,
+    };
+
+    // A file being replaced is kept as a version
+    if let Err(e) = save_version(&destination, &to) {
+        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to keep the previous version: {e}")).into_response();
+    }
+    if let Err(e) = restore_item(&item, destination, &to).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to restore: {e}")).into_response();
+    }
+    if !item.is_dir {
+        expiry::record(&to, None);
+    }
+    info!("♻️ Restored '{}' to '{to}'", item.original_path);
+    let _ = tree_tx.send(TreeNotice::Changed(TreeChanges::created(to.clone())));
+
+    Json(RestoreResponse { path: to }).into_response()
+}
+
+/// Permanently deletes one trashed item.
+pub async fn purge_trash(Path(id): Path<String>) -> Response {
+    match tokio::task::spawn_blocking(move || purge_item(&id)).await {
+        Ok(Ok(true)) => (StatusCode::OK, "Deleted permanently").into_response(),
+        Ok(Ok(false)) => (StatusCode::NOT_FOUND, "No such item in the trash").into_response(),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete the item").into_response(),
+    }
+}
+
+/// Permanently deletes everything in the trash.
+pub async fn empty_trash() -> Response {
+    match tokio::task::spawn_blocking(purge_all).await {
+        Ok(Ok(count)) => {
+            info!("🧹 Emptied the trash ({count} item(s))");
+            (StatusCode::OK, format!("Deleted {count} item(s) permanently")).into_response()
+        }
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to empty the trash").into_response(),
+    }
+}
@@ -0,0 +1,132 @@
+use axum::{
+    body::Body,
+    extract::{Extension, Json, Path},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::TypedHeader;
+use headers::Range;
+use serde::Deserialize;
+use std::sync::Arc;
+use tokio::fs::File;
+use tokio::sync::{broadcast::Sender, Mutex};
+use tokio_util::io::ReaderStream;
+use tracing::info;
+use crate::file_manager::expiry;
+use crate::file_manager::file_tree::FileEntry;
+use crate::file_manager::tree_watcher::{TreeChanges, TreeNotice};
+use crate::file_manager::file_utils::{find_entry, get_mime_type, safe_path};
+use crate::file_manager::transfer::part_path;
+use crate::file_manager::versions::{list_versions, save_version, version_path};
+use crate::server::file_operations::{build_range_response, content_disposition, Disposition};
+
+#[derive(Deserialize)]
+pub struct RestoreVersionRequest {
+    /// File to roll back.
+    pub path: String,
+    /// Version to bring back, as listed by `/api/versions/{path}`.
+    pub id: String,
+}
+
+/// Lists the previous versions of a file, newest first.
+pub async fn file_versions(
+    Path(path): Path<String>,
+    Extension(file_tree): Extension<Arc<Mutex<Option<FileEntry>>>>,
+) -> Response {
+    let entry = {
+        let mut tree_guard = file_tree.lock().await;
+        tree_guard.as_mut().and_then(|tree| find_entry(tree, &path))
+    };
+    match entry {
+        Some(entry) if !entry.is_dir => {}
+        _ => return (StatusCode::NOT_FOUND, "File not found in media tree").into_response(),
+    }
+
+    match tokio::task::spawn_blocking(move || list_versions(&path)).await {
+        Ok(Ok(versions)) => Json(versions).into_response(),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read versions").into_response(),
+    }
+}
+
+/// Downloads one previous version of a file (supports range requests).
+pub async fn download_version(
+    Path((id, path)): Path<(String, String)>,
+    range: Option<TypedHeader<Range>>,
+) -> Response {
+    if let Err(resp) = safe_path(&path) {
+        return resp;
+    }
+    let Some(version) = version_path(&path, &id) else {
+        return (StatusCode::NOT_FOUND, "Version not found").into_response();
+    };
+    let file = match File::open(&version).await {
+        Ok(f) => f,
+        Err(_) => return (StatusCode::NOT_FOUND, "Version not found").into_response(),
+    };
+    let size = match file.metadata().await {
+        Ok(meta) => meta.len(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read file metadata: {e}")).into_response(),
+    };
+
+    // Typed by the file's own name, since the version file has none
+    let mime = get_mime_type(&path);
+    let mut response = match range {
+        Some(TypedHeader(range)) => build_range_response(file, size, &mime, range).await,
+        None => Response::builder()
+            .header(header::CONTENT_TYPE, mime.as_ref())
+            .header(header::ACCEPT_RANGES, "bytes")
+            .header(header::CONTENT_LENGTH, size)
+            .body(Body::from_stream(ReaderStream::new(file)))
+            .unwrap(),
+    };
+    let name = path.rsplit('/').next().unwrap_or(&path);
+    response
+        .headers_mut()
+        .insert(header::CONTENT_DISPOSITION, content_disposition(Disposition::Attachment, name));
+    response
+}
+
+/// Replaces a file with one of its previous versions. The content being replaced
+/// is kept as a new version, so a restore can itself be undone.
+pub async fn restore_version(
+    Extension(file_tree): Extension<Arc<Mutex<Option<FileEntry>>>>,
+    Extension(tree_tx): Extension<Sender<TreeNotice>>,
+    Json(payload): Json<RestoreVersionRequest>,
+) -> Response {
+    let entry = {
+        let mut tree_guard = file_tree.lock().await;
+        tree_guard.as_mut().and_then(|tree| find_entry(tree, &payload.path))
+    };
+    let Some(entry) = entry.filter(|entry| !entry.is_dir) else {
+        return (StatusCode::NOT_FOUND, "File not found in media tree").into_response();
+    };
+    let filepath = match safe_path(&entry.path) {
+        Ok(p) => p,
+        Err(resp) => return resp,
+    };
+
+    let _file_guard = entry.lock.lock().await;
+    let Some(version) = version_path(&entry.path, &payload.id) else {
+        return (StatusCode::NOT_FOUND, "Version not found").into_response();
+    };
+
+    // Copy the version out first: saving the current content may prune it
+    let part = part_path(&filepath, "restore");
+    if let Err(e) = tokio::fs::copy(&version, &part).await {
+        let _ = tokio::fs::remove_file(&part).await;
+        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read version: {e}")).into_response();
+    }
+    if let Err(e) = save_version(&filepath, &entry.path) {
+        let _ = tokio::fs::remove_file(&part).await;
+        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to keep the current version: {e}")).into_response();
+    }
+    if let Err(e) = tokio::fs::rename(&part, &filepath).await {
+        let _ = tokio::fs::remove_file(&part).await;
+        return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to restore version: {e}")).into_response();
+    }
+    expiry::record(&entry.path, None);
+
+    info!("⏪ Restored '{}' to version {}", entry.path, payload.id);
+    let _ = tree_tx.send(TreeNotice::Changed(TreeChanges::modified(entry.path.clone())));
+    (StatusCode::OK, "Version restored").into_response()
+}
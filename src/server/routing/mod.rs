@@ -1,5 +1,6 @@
 pub mod router;
 pub mod handlers;
+pub mod middleware;
 
 // Re-export the main router function for easy access
 pub use router::create_router;
@@ -7,15 +7,16 @@ use tower_cookies::CookieManagerLayer;
 use tokio::sync::Mutex;
 use std::sync::Arc;
 use tower_http::services::ServeDir;
-use crate::file_manager::file_tree::FileEntry;
+use crate::file_manager::{archive::CrcCache, file_tree::FileEntry, jobs::JobRegistry, tree_watcher::TreeNotice};
 
 use super::handlers::*;
+use super::middleware::security_headers;
 
 /// Creates and configures the application router with all routes.
 /// Accepts a shared `file_tree` state for media file management.
 pub fn create_router(
     file_tree: Arc<Mutex<Option<FileEntry>>>,
-    tree_tx: tokio::sync::broadcast::Sender<()>,
+    tree_tx: tokio::sync::broadcast::Sender<TreeNotice>,
 ) -> Router {
     Router::new()
         .nest_service("/static", ServeDir::new("static"))
@@ -24,17 +25,40 @@ pub fn create_router(
         .route("/master", get(master_protection))
         .route("/api/master.json", get(master_json))
         .route("/api/master/{*path}", get(open))
+        .route("/api/download/{*path}", get(download))
+        .route("/api/archive", get(archive_folder).post(archive_selection))
+        .route("/api/archive_entries/{*path}", get(archive_entries))
+        .route("/api/extract", axum::routing::post(extract_archive))
+        .route("/api/jobs", get(list_jobs))
+        .route("/api/fetch", axum::routing::post(fetch_url))
+        .route("/api/jobs/{id}", get(get_job))
+        .route("/api/jobs/{id}/cancel", axum::routing::post(cancel_job))
         .route("/health", get(health_check))
         .route("/api/upload", axum::routing::post(upload_file))
         .route("/api/delete", axum::routing::post(delete_file))
         .route("/api/update", axum::routing::post(update_file))
         .route("/api/create_folder", axum::routing::post(create_folder))
+        .route("/api/move", axum::routing::post(move_entry))
+        .route("/api/copy", axum::routing::post(copy_entry))
+        .route("/api/trash", get(list_trash).delete(empty_trash))
+        .route("/api/trash/{id}", axum::routing::delete(purge_trash))
+        .route("/api/trash/{id}/restore", axum::routing::post(restore_trash))
+        .route("/api/versions/restore", axum::routing::post(restore_version))
+        .route("/api/versions/{*path}", get(file_versions))
+        .route("/api/version/{id}/{*path}", get(download_version))
+        .route("/api/text/{*path}", get(read_text).put(write_text))
+        .route("/api/files/{*path}", axum::routing::put(put_file))
+        .route("/api/quota", get(quota_usage))
         .route("/api/password_required", get(password_required))
         .route("/events/tree", get(tree_events)) // <-- Add this line
+        .route("/events/jobs", get(job_events))
         .fallback(static_handler("static/html/error.html"))
+        .layer(axum::middleware::from_fn(security_headers)) // nosniff + CSP on every response
         .layer(CookieManagerLayer::new()) // Enables cookie management for authentication
         .layer(axum::extract::Extension(file_tree)) // Shares the file tree state with handlers
         .layer(axum::extract::Extension(tree_tx))
+        .layer(axum::extract::Extension(Arc::new(CrcCache::default()))) // CRCs for resumable archives
+        .layer(axum::extract::Extension(Arc::new(JobRegistry::default()))) // Background jobs and their progress
         .layer(DefaultBodyLimit::max(1024 * 1024 * 1024)) // 1GB upload limit
 }
 
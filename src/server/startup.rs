@@ -5,6 +5,7 @@ use tokio::sync::Mutex;
 use std::sync::Arc;
 
 use crate::file_manager::file_tree::scan_dir;
+use crate::file_manager::tree_watcher::TreeNotice;
 use crate::utils::config::Config;
 use std::path::Path;
 use tokio::sync::broadcast;
@@ -20,7 +21,7 @@ pub async fn start_server() {
 
     let file_tree = Arc::new(Mutex::new(scan_dir(fil_dir_path, fil_dir_path)));
     let watcher_tree = file_tree.clone();
-    let (tree_tx, _) = broadcast::channel::<()>(16);
+    let (tree_tx, _) = broadcast::channel::<TreeNotice>(64);
     // Start file watcher in a separate async task
     tokio::spawn({
         let file_dir = file_dir.clone();
@@ -30,12 +31,43 @@ pub async fn start_server() {
         }
     });
 
+    if let Err(e) = crate::file_manager::transfer::clear_staging() {
+        tracing::warn!("Failed to clear interrupted uploads: {e}");
+    }
+
+    // Purge old items from the trash in the background
+    tokio::spawn(crate::file_manager::trash::run_sweeper());
+    // Delete uploads whose time-to-live has passed
+    tokio::spawn(crate::file_manager::expiry::run_sweeper(file_tree.clone(), tree_tx.clone()));
+
+    // Write out metadata changes still waiting for the background flush
+    tokio::spawn(async {
+        shutdown_signal().await;
+        crate::file_manager::metadata::flush();
+        std::process::exit(0);
+    });
+
     let app = routing::create_router(file_tree.clone(), tree_tx);
     let port: u16 = config.port().parse().unwrap();
     let addr = SocketAddr::from(([0, 0, 0, 0], port));
 
     info!("🚀 Server starting on {}", addr);
-    axum::serve(tokio::net::TcpListener::bind(addr).await.unwrap(), app.into_make_service())
+    axum::serve(tokio::net::TcpListener::bind(addr).await.unwrap(), app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .unwrap();
-}
\ No newline at end of file
+}
+/// Resolves on Ctrl+C, or on SIGTERM where there is one.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        if let Ok(mut terminate) = signal(SignalKind::terminate()) {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+            return;
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
@@ -6,6 +6,58 @@ pub struct Config {
     port: String,     
     password: String,  
     rust_log: String,   
+    active_content_policy: ActiveContentPolicy,
+    extract_max_bytes: u64,
+    extract_max_entries: u64,
+    trash_retention_days: u64,
+    trash_max_bytes: u64,
+    versions_keep: u32,
+    versions_max_age_days: u64,
+    text_edit_max_bytes: u64,
+    max_name_bytes: usize,
+    max_path_bytes: usize,
+    case_insensitive_names: bool,
+    quota_user_bytes: u64,
+    quota_user_files: u64,
+    quota_folder_bytes: u64,
+    quota_folder_files: u64,
+    free_space_reserve_bytes: u64,
+    fetch_max_bytes: u64,
+    fetch_allow_hosts: Vec<String>,
+    watch_debounce_ms: u64,
+    watch_max_latency_ms: u64,
+}
+
+/// Default cap on the total unpacked size of one extraction (10 GiB).
+const DEFAULT_EXTRACT_MAX_BYTES: u64 = 10 * 1024 * 1024 * 1024;
+/// Default cap on the number of entries unpacked from one archive.
+const DEFAULT_EXTRACT_MAX_ENTRIES: u64 = 10_000;
+/// Default number of days deleted items stay in the trash.
+const DEFAULT_TRASH_RETENTION_DAYS: u64 = 30;
+/// Default number of previous versions kept per file.
+const DEFAULT_VERSIONS_KEEP: u32 = 5;
+/// Default size limit for files edited through the text API (1 MiB).
+const DEFAULT_TEXT_EDIT_MAX_BYTES: u64 = 1024 * 1024;
+/// Default cap on the length of one file or folder name, in UTF-8 bytes.
+const DEFAULT_MAX_NAME_BYTES: usize = 255;
+/// Default cap on the length of a path inside the share, in UTF-8 bytes.
+const DEFAULT_MAX_PATH_BYTES: usize = 1024;
+/// Default free space uploads must leave on the share's filesystem (1 GiB).
+const DEFAULT_FREE_SPACE_RESERVE_BYTES: u64 = 1024 * 1024 * 1024;
+/// Default cap on the size of one file fetched from a URL (10 GiB).
+const DEFAULT_FETCH_MAX_BYTES: u64 = 10 * 1024 * 1024 * 1024;
+/// Default quiet time after a filesystem event before the tree is updated.
+const DEFAULT_WATCH_DEBOUNCE_MS: u64 = 250;
+/// Default longest wait for the tree to catch up with a continuous stream of events.
+const DEFAULT_WATCH_MAX_LATENCY_MS: u64 = 2000;
+
+/// How files that can execute script in a browser (HTML, SVG, XML) are served.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActiveContentPolicy {
+    /// Always download as an attachment (default).
+    Attachment,
+    /// Allow inline viewing under a sandboxing Content-Security-Policy.
+    Sandbox,
 }
 
 impl Config {
@@ -16,6 +68,88 @@ impl Config {
             port: env::var("PORT").unwrap_or_else(|_| "3000".to_string()),
             password: env::var("PASSWORD").unwrap_or_else(|_| "".to_string()),
             rust_log: env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string()), 
+            active_content_policy: match env::var("ACTIVE_CONTENT_POLICY").as_deref() {
+                Ok("sandbox") => ActiveContentPolicy::Sandbox,
+                _ => ActiveContentPolicy::Attachment,
+            },
+            extract_max_bytes: env::var("EXTRACT_MAX_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_EXTRACT_MAX_BYTES),
+            extract_max_entries: env::var("EXTRACT_MAX_ENTRIES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_EXTRACT_MAX_ENTRIES),
+            trash_retention_days: env::var("TRASH_RETENTION_DAYS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_TRASH_RETENTION_DAYS),
+            trash_max_bytes: env::var("TRASH_MAX_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0),
+            versions_keep: env::var("VERSIONS_KEEP")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_VERSIONS_KEEP),
+            versions_max_age_days: env::var("VERSIONS_MAX_AGE_DAYS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0),
+            text_edit_max_bytes: env::var("TEXT_EDIT_MAX_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_TEXT_EDIT_MAX_BYTES),
+            max_name_bytes: env::var("MAX_NAME_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_MAX_NAME_BYTES),
+            max_path_bytes: env::var("MAX_PATH_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_MAX_PATH_BYTES),
+            case_insensitive_names: matches!(
+                env::var("CASE_INSENSITIVE_NAMES").as_deref(),
+                Ok("1" | "true" | "yes")
+            ),
+            quota_user_bytes: env::var("QUOTA_USER_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0),
+            quota_user_files: env::var("QUOTA_USER_FILES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0),
+            quota_folder_bytes: env::var("QUOTA_FOLDER_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0),
+            quota_folder_files: env::var("QUOTA_FOLDER_FILES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0),
+            free_space_reserve_bytes: env::var("FREE_SPACE_RESERVE_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_FREE_SPACE_RESERVE_BYTES),
+            fetch_max_bytes: env::var("FETCH_MAX_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_FETCH_MAX_BYTES),
+            fetch_allow_hosts: env::var("FETCH_ALLOW_HOSTS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|host| host.trim().to_lowercase())
+                .filter(|host| !host.is_empty())
+                .collect(),
+            watch_debounce_ms: env::var("WATCH_DEBOUNCE_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_WATCH_DEBOUNCE_MS),
+            watch_max_latency_ms: env::var("WATCH_MAX_LATENCY_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_WATCH_MAX_LATENCY_MS),
         }
     }
 
@@ -34,5 +168,86 @@ impl Config {
     pub fn rust_log(&self) -> &str { 
         &self.rust_log
     }
-}
 
+    pub fn active_content_policy(&self) -> ActiveContentPolicy {
+        self.active_content_policy
+    }
+
+    pub fn extract_max_bytes(&self) -> u64 {
+        self.extract_max_bytes
+    }
+
+    pub fn extract_max_entries(&self) -> u64 {
+        self.extract_max_entries
+    }
+
+    pub fn trash_retention_days(&self) -> u64 {
+        self.trash_retention_days
+    }
+
+    pub fn trash_max_bytes(&self) -> u64 {
+        self.trash_max_bytes
+    }
+
+    pub fn versions_keep(&self) -> u32 {
+        self.versions_keep
+    }
+
+    pub fn versions_max_age_days(&self) -> u64 {
+        self.versions_max_age_days
+    }
+
+    pub fn text_edit_max_bytes(&self) -> u64 {
+        self.text_edit_max_bytes
+    }
+
+    pub fn max_name_bytes(&self) -> usize {
+        self.max_name_bytes
+    }
+
+    pub fn max_path_bytes(&self) -> usize {
+        self.max_path_bytes
+    }
+
+    pub fn case_insensitive_names(&self) -> bool {
+        self.case_insensitive_names
+    }
+
+    pub fn quota_user_bytes(&self) -> u64 {
+        self.quota_user_bytes
+    }
+
+    pub fn quota_user_files(&self) -> u64 {
+        self.quota_user_files
+    }
+
+    pub fn quota_folder_bytes(&self) -> u64 {
+        self.quota_folder_bytes
+    }
+
+    pub fn quota_folder_files(&self) -> u64 {
+        self.quota_folder_files
+    }
+
+    pub fn free_space_reserve_bytes(&self) -> u64 {
+        self.free_space_reserve_bytes
+    }
+
+    pub fn fetch_max_bytes(&self) -> u64 {
+        self.fetch_max_bytes
+    }
+
+    /// Hosts (names or IP addresses) that fetches may reach even though they
+    /// resolve to loopback or private addresses.
+    pub fn fetch_allow_hosts(&self) -> &[String] {
+        &self.fetch_allow_hosts
+    }
+
+    pub fn watch_debounce_ms(&self) -> u64 {
+        self.watch_debounce_ms
+    }
+
+    pub fn watch_max_latency_ms(&self) -> u64 {
+        self.watch_max_latency_ms
+    }
+}
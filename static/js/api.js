@@ -3,6 +3,10 @@ export async function fetchMasterTree() {
   return res.json();
 }
 
+export async function fetchArchiveEntries(path) {
+  return fetch(`/api/archive_entries/${encodeURIComponent(path)}`);
+}
+
 export async function uploadFile(formData, currentPath) {
   formData.append("target_path", currentPath);
   const res = await fetch("/api/upload", {
@@ -23,11 +27,29 @@ export async function updateFile(file, replacePath) {
   return res;
 }
 
-export async function deleteFile(path) {
+export async function deleteFile(path, recursive = false) {
   const res = await fetch("/api/delete", {
     method: "POST",
     headers: { "Content-Type": "application/json" },
-    body: JSON.stringify({ path })
+    body: JSON.stringify({ path, recursive })
+  });
+  return res;
+}
+
+export async function moveEntry(from, to, overwrite = false) {
+  const res = await fetch("/api/move", {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({ from, to, overwrite })
+  });
+  return res;
+}
+
+export async function copyEntry(from, to, conflict = "rename") {
+  const res = await fetch("/api/copy", {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({ from, to, conflict })
   });
   return res;
 }
@@ -40,3 +62,21 @@ export async function createFolder(path) {
   });
   return res;
 }
+
+export async function downloadSelection(paths, format = "zip") {
+  const res = await fetch("/api/archive", {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({ paths, format })
+  });
+  return res;
+}
+
+export async function extractArchive(path, conflict = "skip") {
+  const res = await fetch("/api/extract", {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({ path, conflict })
+  });
+  return res;
+}
@@ -1,6 +1,6 @@
 // --- dom.js ---
 
-import { updateFile, deleteFile } from "./api.js";
+import { updateFile, deleteFile, fetchArchiveEntries, extractArchive, moveEntry, copyEntry } from "./api.js";
 
 export function formatSize(bytes) {
   if (!bytes) return "?";
@@ -19,6 +19,15 @@ export function formatDate(ts) {
   return d.toLocaleString();
 }
 
+export function formatExpiry(ts) {
+  const minutes = Math.round((new Date(ts) - Date.now()) / 60000);
+  if (isNaN(minutes)) return "?";
+  if (minutes < 1) return "expires soon";
+  if (minutes < 60) return `expires in ${minutes}m`;
+  if (minutes < 48 * 60) return `expires in ${Math.round(minutes / 60)}h`;
+  return `expires in ${Math.round(minutes / (24 * 60))}d`;
+}
+
 export function findEntryByPath(entry, path) {
   if (entry.path === path) return entry;
   if (!entry.children) return null;
@@ -30,7 +39,7 @@ export function findEntryByPath(entry, path) {
 }
 
 export function renderGrid(entry, currentPath, setPath, refreshTree, search = "") {
-  if (!entry?.is_dir || !entry.children) return document.createTextNode("No master found.");
+  if (!entry?.children) return document.createTextNode("No master found.");
 
   const grid = document.createElement("div");
   grid.className = "grid grid-cols-1 sm:grid-cols-2 md:grid-cols-3 lg:grid-cols-4 gap-8";
@@ -66,7 +75,8 @@ export function renderGrid(entry, currentPath, setPath, refreshTree, search = ""
         "absolute z-10 left-1/2 -translate-x-1/2 bottom-full mb-2 w-64 bg-gray-900 text-white text-xs rounded-lg shadow-lg px-4 py-2 opacity-0 group-hover:opacity-100 pointer-events-none transition-opacity duration-200";
       tooltip.style.whiteSpace = "pre-line";
       tooltip.innerText =
-        `Name: ${child.name}\nSize: ${child.size ? formatSize(child.size) : "?"}\nLast Modified: ${child.modified ? formatDate(child.modified) : "?"}`;
+        `Name: ${child.name}\nSize: ${child.size ? formatSize(child.size) : "?"}\nLast Modified: ${child.modified ? formatDate(child.modified) : "?"}` +
+        (child.expires_at ? `\n${formatExpiry(child.expires_at)}` : "");
       item.appendChild(tooltip);
     }
 
@@ -75,7 +85,8 @@ export function renderGrid(entry, currentPath, setPath, refreshTree, search = ""
     icon.textContent = child.is_dir ? "📂" :
       child.file_type === "Audio" ? "🎵" :
       child.file_type === "Image" ? "🖼️" :
-      child.file_type === "Video" ? "🎬" : "📄";
+      child.file_type === "Video" ? "🎬" :
+      child.file_type === "Archive" ? "🗜️" : "📄";
 
     const label = document.createElement("div");
     label.className = "text-center text-blue-900 font-semibold text-base truncate w-full px-2 mb-2";
@@ -98,14 +109,78 @@ export function renderGrid(entry, currentPath, setPath, refreshTree, search = ""
       btnGroup.appendChild(openBtn);
     }
 
+    if (child.file_type === "Archive" && !child.in_archive) {
+      const browseBtn = document.createElement("button");
+      browseBtn.textContent = "Browse";
+      browseBtn.className = "px-4 py-1 bg-emerald-500 text-white rounded-full text-sm font-medium shadow hover:bg-emerald-600 transition-all duration-150";
+      browseBtn.onclick = async (e) => {
+        e.stopPropagation();
+        const res = await fetchArchiveEntries(child.path);
+        if (!res.ok) return alert("Cannot open archive! " + await res.text());
+        child.children = (await res.json()).children;
+        setPath(child.path);
+      };
+      btnGroup.appendChild(browseBtn);
+
+      const extractBtn = document.createElement("button");
+      extractBtn.textContent = "Extract";
+      extractBtn.className = "px-4 py-1 bg-amber-200 text-amber-900 rounded-full text-sm font-medium shadow hover:bg-amber-300 transition-all duration-150";
+      extractBtn.onclick = async (e) => {
+        e.stopPropagation();
+        const res = await extractArchive(child.path);
+        alert(res.ok ? "Extraction started!" : "Extraction failed! " + await res.text());
+      };
+      btnGroup.appendChild(extractBtn);
+    }
+
+    if (child.is_dir && !child.in_archive) {
+      const archiveBtn = document.createElement("a");
+      archiveBtn.textContent = "Download";
+      archiveBtn.href = `/api/archive?path=${encodeURIComponent(child.path)}`;
+      archiveBtn.className = "px-4 py-1 bg-blue-500 text-white rounded-full text-sm font-medium shadow hover:bg-blue-600 transition-all duration-150 inline-block text-center";
+      archiveBtn.onclick = (e) => e.stopPropagation();
+      btnGroup.appendChild(archiveBtn);
+    }
+
     if (!child.is_dir) {
       const downloadBtn = document.createElement("a");
       downloadBtn.textContent = "Download";
-      downloadBtn.href = `/api/master/${encodeURIComponent(child.path)}`;
+      downloadBtn.href = `/api/download/${encodeURIComponent(child.path)}`;
       downloadBtn.download = child.name;
       downloadBtn.className = "px-4 py-1 bg-blue-500 text-white rounded-full text-sm font-medium shadow hover:bg-blue-600 transition-all duration-150 inline-block text-center";
       btnGroup.appendChild(downloadBtn);
+    }
+
+    if (!child.in_archive) {
+      const renameBtn = document.createElement("button");
+      renameBtn.textContent = "Rename";
+      renameBtn.className = "px-4 py-1 bg-stone-200 text-stone-800 rounded-full text-sm font-medium shadow hover:bg-stone-300 transition-all duration-150";
+      renameBtn.onclick = async (e) => {
+        e.stopPropagation();
+        const newName = prompt("New name:", child.name)?.trim();
+        if (!newName || newName === child.name) return;
+        const parent = child.path.split("/").slice(0, -1).join("/");
+        const res = await moveEntry(child.path, parent ? `${parent}/${newName}` : newName);
+        if (!res.ok) alert("Rename failed! " + await res.text());
+        refreshTree();
+      };
+      btnGroup.appendChild(renameBtn);
 
+      const copyBtn = document.createElement("button");
+      copyBtn.textContent = "Duplicate";
+      copyBtn.className = "px-4 py-1 bg-stone-200 text-stone-800 rounded-full text-sm font-medium shadow hover:bg-stone-300 transition-all duration-150";
+      copyBtn.onclick = async (e) => {
+        e.stopPropagation();
+        const newName = prompt("Name of the copy:", `${child.name} copy`)?.trim();
+        if (!newName) return;
+        const parent = child.path.split("/").slice(0, -1).join("/");
+        const res = await copyEntry(child.path, parent ? `${parent}/${newName}` : newName);
+        alert(res.ok ? "Copy started!" : "Copy failed! " + await res.text());
+      };
+      btnGroup.appendChild(copyBtn);
+    }
+
+    if (!child.is_dir && !child.in_archive) {
       const updateBtn = document.createElement("button");
       updateBtn.textContent = "Update";
       updateBtn.className = "px-4 py-1 bg-amber-200 text-amber-900 rounded-full text-sm font-medium shadow hover:bg-amber-300 transition-all duration-150";
@@ -127,15 +202,18 @@ export function renderGrid(entry, currentPath, setPath, refreshTree, search = ""
         refreshTree();
       };
       btnGroup.append(updateBtn, updateInput);
+    }
 
+    if (!child.in_archive) {
       const deleteBtn = document.createElement("button");
       deleteBtn.textContent = "Delete";
       deleteBtn.className = "px-4 py-1 bg-red-100 text-red-700 rounded-full text-sm font-medium shadow hover:bg-red-200 transition-all duration-150";
       deleteBtn.onclick = async (e) => {
         e.stopPropagation();
-        if (!confirm(`Are you sure you want to delete '${child.name}'?`)) return;
-        const res = await deleteFile(child.path);
-        alert(res.ok ? "File deleted!" : "Delete failed! " + await res.text());
+        const what = child.is_dir ? `the folder '${child.name}' and everything in it` : `'${child.name}'`;
+        if (!confirm(`Are you sure you want to delete ${what}?`)) return;
+        const res = await deleteFile(child.path, child.is_dir);
+        alert(res.ok ? "Moved to trash!" : "Delete failed! " + await res.text());
         refreshTree();
       };
       btnGroup.appendChild(deleteBtn);
@@ -31,7 +31,18 @@ document.getElementById("uploadForm").onsubmit = async function (e) {
   e.preventDefault();
   const formData = new FormData(this);
   const res = await uploadFile(formData, currentPath);
-  alert(res.ok ? "Upload successful!" : "Upload failed! " + await res.text());
+  if (!res.ok) {
+    alert("Upload failed! " + await res.text());
+    return;
+  }
+  const { path, outcome } = await res.json();
+  if (outcome === "renamed") {
+    alert("Upload successful! Saved as " + path);
+  } else if (outcome === "skipped") {
+    alert("This file is already there.");
+  } else {
+    alert("Upload successful!");
+  }
 };
 
 document.getElementById("searchInput").addEventListener("input", updateGrid);